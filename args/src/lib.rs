@@ -2,10 +2,27 @@
 pub struct Args {
     pub input_filename: Option<String>,
     pub debug: bool,
+    pub debugger: bool,
+    pub devices: bool,
     pub pretty: bool,
+    pub strip: bool,
+    pub object: bool,
     pub log_to: Option<String>,
     pub path: String,
     pub filename: Option<String>,
+    pub symbols: Option<String>,
+    pub save_state: Option<String>,
+    pub load_state: Option<String>,
+    pub history: Option<usize>,
+    pub trace: Option<String>,
+    pub trace_format: Option<String>,
+    pub listing: Option<String>,
+    pub output: Option<String>,
+    pub include_paths: Vec<String>,
+    // ISA description to use instead of the built-in one
+    pub isa: Option<String>,
+    // every argument that is neither a flag nor a flag's value, e.g. the objects to link
+    pub inputs: Vec<String>,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -14,6 +31,28 @@ pub enum ArgsError {
     InvalidFlag(String),
 }
 
+// accepts both `--flag=value` and `--flag value`
+fn value_of(args: &[String], flag: &str) -> Option<String> {
+    values_of(args, flag).into_iter().next()
+}
+
+// flags written as `--flag value` whose value must not be taken for an input
+const VALUE_FLAGS: [&str; 6] = ["-o", "--output", "--listing", "-I", "--include", "--isa"];
+
+// every value of a flag that may be repeated
+fn values_of(args: &[String], flag: &str) -> Vec<String> {
+    args.iter()
+        .enumerate()
+        .filter_map(|(i, arg)| {
+            if arg == flag {
+                args.get(i + 1).cloned()
+            } else {
+                arg.strip_prefix(flag)?.strip_prefix('=').map(String::from)
+            }
+        })
+        .collect()
+}
+
 impl Args {
     pub fn parse() -> Result<Self, ArgsError> {
         let args: Vec<String> = std::env::args().collect();
@@ -21,14 +60,33 @@ impl Args {
             return Ok(Self {
                 input_filename: None,
                 debug: false,
+                debugger: false,
+                devices: false,
                 pretty: false,
+                strip: false,
+                object: false,
                 log_to: None,
                 path: String::from("/logs/"),
                 filename: None,
+                symbols: None,
+                save_state: None,
+                load_state: None,
+                history: None,
+                trace: None,
+                trace_format: None,
+                listing: None,
+                output: None,
+                include_paths: Vec::new(),
+                isa: None,
+                inputs: Vec::new(),
             });
         }
         let debug = args.contains(&String::from("--debug"));
+        let debugger = args.contains(&String::from("--debugger"));
+        let devices = args.contains(&String::from("--devices"));
         let pretty = args.contains(&String::from("--pretty"));
+        let strip = args.contains(&String::from("--strip"));
+        let object = args.contains(&String::from("--object")) || args.contains(&String::from("-c"));
         let log_to = args.iter().fold(None, |acc, x| {
             if x.contains("--log=") {
                 Some(x[6..].to_string())
@@ -49,13 +107,92 @@ impl Args {
                 acc
             }
         });
+        let symbols = args.iter().fold(None, |acc, x| {
+            if x.contains("--symbols=") {
+                Some(x[10..].to_string())
+            } else {
+                acc
+            }
+        });
+        let save_state = args.iter().fold(None, |acc, x| {
+            if x.contains("--save-state=") {
+                Some(x[13..].to_string())
+            } else {
+                acc
+            }
+        });
+        let load_state = args.iter().fold(None, |acc, x| {
+            if x.contains("--load-state=") {
+                Some(x[13..].to_string())
+            } else {
+                acc
+            }
+        });
+        let history = match args.iter().find(|x| x.starts_with("--history=")) {
+            Some(x) => Some(
+                x[10..]
+                    .parse()
+                    .map_err(|_| ArgsError::InvalidFlag(x.clone()))?,
+            ),
+            None => None,
+        };
+        let trace = args.iter().fold(None, |acc, x| {
+            if x.contains("--trace=") {
+                Some(x[8..].to_string())
+            } else {
+                acc
+            }
+        });
+        let trace_format = args.iter().fold(None, |acc, x| {
+            if x.contains("--trace-format=") {
+                Some(x[15..].to_string())
+            } else {
+                acc
+            }
+        });
+        let listing = value_of(&args, "--listing");
+        let output = value_of(&args, "--output").or_else(|| value_of(&args, "-o"));
+        let mut include_paths = values_of(&args, "-I");
+        include_paths.extend(values_of(&args, "--include"));
+        let isa = value_of(&args, "--isa");
+        // a flag in the first position means no input file was given, a lone `-` is stdin
+        let input_filename = if args[1].starts_with('-') && args[1] != "-" {
+            None
+        } else {
+            Some(args[1].clone())
+        };
+        let inputs = args
+            .iter()
+            .enumerate()
+            .skip(1)
+            .filter(|(i, arg)| {
+                (!arg.starts_with('-') || *arg == "-")
+                    && !VALUE_FLAGS.contains(&args[i - 1].as_str())
+            })
+            .map(|(_, arg)| arg.clone())
+            .collect();
         Ok(Self {
-            input_filename: Some(args[1].clone()),
+            input_filename,
             debug,
+            debugger,
+            devices,
             pretty,
+            strip,
+            object,
             log_to,
             path: path.to_string(),
             filename,
+            symbols,
+            save_state,
+            load_state,
+            history,
+            trace,
+            trace_format,
+            listing,
+            output,
+            include_paths,
+            isa,
+            inputs,
         })
     }
 }
@@ -119,6 +119,7 @@ mod tests {
                 bit_count: 3,
             }]),
             size: 6,
+            ..Default::default()
         }];
         let (binary, _) = encoder.encode(instructions).unwrap();
         assert_eq!(binary, vec![16, 128, 0, 0, 0, 9]);
@@ -0,0 +1,233 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    lexer::{
+        Lexer, LexerError,
+        token::{Token, TokenStream, TokenType},
+    },
+    render_error::{Diagnostic, Located, render_error},
+    source_map::SourceMap,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum IncludeError {
+    #[error("{message}")]
+    NotFound { message: String },
+    #[error("{message}")]
+    Cycle { message: String },
+    #[error("{message}")]
+    InvalidInclude { message: String },
+}
+
+// replaces every `INCLUDE "file.asm"` line with the tokens of that file, before macros
+// are expanded so that included files can define them
+pub struct Includer {
+    include_paths: Vec<PathBuf>,
+    // canonical paths of the files currently being expanded, outermost first
+    stack: Vec<PathBuf>,
+    // lexer errors of every file, their lines are left out and expansion goes on
+    lexer_errors: Vec<Located<LexerError>>,
+}
+
+fn is_blank(token: &Token) -> bool {
+    token.token_type == TokenType::Whitespace
+}
+
+impl Includer {
+    pub fn new(include_paths: Vec<PathBuf>) -> Self {
+        Self {
+            include_paths,
+            stack: Vec::new(),
+            lexer_errors: Vec::new(),
+        }
+    }
+
+    pub fn lexer_errors(&mut self) -> Vec<Located<LexerError>> {
+        std::mem::take(&mut self.lexer_errors)
+    }
+
+    pub fn expand(
+        &mut self,
+        file: u32,
+        sources: &mut SourceMap,
+    ) -> Result<TokenStream, IncludeError> {
+        let (tokens, errors) = Lexer::new().lex(file, sources);
+        self.lexer_errors.extend(errors);
+        let canonical = sources.file(file).path.canonicalize().ok();
+        self.stack.extend(canonical.clone());
+
+        let mut expanded = TokenStream::new();
+        let mut line = Vec::new();
+        for token in tokens.tokens {
+            if matches!(token.token_type, TokenType::Newline | TokenType::Eof) {
+                match self.include(&line, file, sources)? {
+                    Some(included) => expanded.tokens.extend(included.tokens),
+                    None => expanded.tokens.append(&mut line),
+                }
+                line.clear();
+                expanded.push(token);
+            } else {
+                line.push(token);
+            }
+        }
+
+        if canonical.is_some() {
+            self.stack.pop();
+        }
+        Ok(expanded)
+    }
+
+    // the tokens of the included file without its Eof, or None if `line` is not an INCLUDE
+    fn include(
+        &mut self,
+        line: &[Token],
+        file: u32,
+        sources: &mut SourceMap,
+    ) -> Result<Option<TokenStream>, IncludeError> {
+        let mut words = line.iter().filter(|token| !is_blank(token));
+        let Some(keyword) = words.next() else {
+            return Ok(None);
+        };
+        if keyword.token_type != TokenType::Identifier
+            || keyword.value.as_deref() != Some("INCLUDE")
+        {
+            return Ok(None);
+        }
+        let name = match (words.next(), words.next()) {
+            (Some(operand), None)
+                if operand.value.as_ref().is_some_and(|value| {
+                    value.len() >= 2 && value.starts_with('"') && value.ends_with('"')
+                }) =>
+            {
+                operand
+            }
+            (operand, _) => {
+                return Err(IncludeError::InvalidInclude {
+                    message: render_error(Diagnostic {
+                        headline: "Invalid INCLUDE".to_string(),
+                        loc: operand.unwrap_or(keyword).source_loc,
+                        sources,
+                        help: Some(
+                            "Expected a single file name in double quotes, e.g. INCLUDE \"lib.asm\"",
+                        ),
+                    }),
+                });
+            }
+        };
+        let value = name.value.as_deref().unwrap_or_default();
+        let relative = Path::new(&value[1..value.len() - 1]);
+
+        // next to the including file first, then the include paths in order
+        let directory = sources
+            .file(file)
+            .path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        let Some(path) = std::iter::once(&directory)
+            .chain(&self.include_paths)
+            .map(|directory| directory.join(relative))
+            .find(|path| path.is_file())
+        else {
+            return Err(IncludeError::NotFound {
+                message: render_error(Diagnostic {
+                    headline: format!("Included file {} not found", value),
+                    loc: name.source_loc,
+                    sources,
+                    help: Some(
+                        "Files are searched next to the including file, then in the -I directories",
+                    ),
+                }),
+            });
+        };
+
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        if self.stack.contains(&canonical) {
+            return Err(IncludeError::Cycle {
+                message: render_error(Diagnostic {
+                    headline: format!("{} includes itself", path.display()),
+                    loc: name.source_loc,
+                    sources,
+                    help: Some("Remove one of the INCLUDE lines that lead back to this file"),
+                }),
+            });
+        }
+        let text = fs::read_to_string(&path).map_err(|error| IncludeError::NotFound {
+            message: render_error(Diagnostic {
+                headline: format!("Cannot read {}: {}", path.display(), error),
+                loc: name.source_loc,
+                sources,
+                help: None,
+            }),
+        })?;
+
+        let id = sources.add(
+            &path.display().to_string(),
+            &path,
+            &text,
+            Some(name.source_loc),
+        );
+        let mut included = self.expand(id, sources)?;
+        included.tokens.pop();
+        Ok(Some(included))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(directory: &Path, name: &str, text: &str) {
+        fs::create_dir_all(directory.join(name).parent().unwrap()).unwrap();
+        fs::write(directory.join(name), text).unwrap();
+    }
+
+    #[test]
+    fn test_include() {
+        let directory = std::env::temp_dir().join(format!("include_{}", std::process::id()));
+        write(&directory, "main.asm", "INCLUDE \"a.asm\"\nHALT\n");
+        write(&directory, "a.asm", "INCLUDE \"b.asm\"\nMOVER R0, 0");
+        write(&directory, "lib/b.asm", "NOP: MOVER R1, 1\n");
+        write(&directory, "loop.asm", "INCLUDE \"loop.asm\"\n");
+
+        let main = directory.join("main.asm");
+        let mut sources = SourceMap::new();
+        let file = sources.add("main.asm", &main, &fs::read_to_string(&main).unwrap(), None);
+        let error = Includer::new(Vec::new())
+            .expand(file, &mut sources)
+            .unwrap_err();
+        assert!(matches!(error, IncludeError::NotFound { .. }));
+        assert!(error.to_string().contains("note: included from main.asm:1"));
+
+        let mut sources = SourceMap::new();
+        let file = sources.add("main.asm", &main, &fs::read_to_string(&main).unwrap(), None);
+        let tokens = Includer::new(vec![directory.join("lib")])
+            .expand(file, &mut sources)
+            .unwrap();
+        let values: Vec<&str> = tokens
+            .tokens
+            .iter()
+            .filter_map(|token| token.value.as_deref())
+            .collect();
+        assert_eq!(
+            values,
+            [
+                "NOP", ":", "MOVER", "R1", ",", "1", "MOVER", "R0", ",", "0", "HALT"
+            ]
+        );
+        assert_eq!(tokens.tokens[0].source_loc.file, 2);
+        assert_eq!(sources.include_chain(&tokens.tokens[0].source_loc).len(), 2);
+
+        let path = directory.join("loop.asm");
+        let mut sources = SourceMap::new();
+        let file = sources.add("loop.asm", &path, "INCLUDE \"loop.asm\"", None);
+        assert!(matches!(
+            Includer::new(Vec::new()).expand(file, &mut sources),
+            Err(IncludeError::Cycle { .. })
+        ));
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -1,12 +1,19 @@
 pub mod token;
 
-use crate::lexer::token::{SourceLoc, TokenType};
+use crate::{
+    lexer::token::{SourceLoc, TokenType},
+    render_error::{Diagnostic, Located, render_error},
+    source_map::SourceMap,
+};
 
 use self::token::{Token, TokenStream};
 use std::mem;
 
 #[derive(Debug, thiserror::Error)]
-pub enum LexerError {}
+pub enum LexerError {
+    #[error("{message}")]
+    UnterminatedString { message: String },
+}
 
 pub struct Lexer {
     tokens: TokenStream,
@@ -14,7 +21,8 @@ pub struct Lexer {
     line: u32,
     column: u32,
     token_loc: SourceLoc,
-    source_lines: Vec<String>,
+    file: u32,
+    errors: Vec<Located<LexerError>>,
 }
 
 impl Lexer {
@@ -24,8 +32,14 @@ impl Lexer {
             token: String::new(),
             line: 1,
             column: 0,
-            token_loc: SourceLoc { line: 1, column: 1 },
-            source_lines: Vec::new(),
+            token_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 1,
+                expansion: None,
+            },
+            file: 0,
+            errors: Vec::new(),
         }
     }
 
@@ -72,15 +86,76 @@ impl Lexer {
         });
     }
 
+    // a minus after an operand subtracts, anywhere else it belongs to the number it precedes
+    fn follows_operand(&self) -> bool {
+        self.tokens
+            .tokens
+            .iter()
+            .rev()
+            .find(|token| token.token_type != TokenType::Whitespace)
+            .is_some_and(|token| match token.token_type {
+                TokenType::Identifier => true,
+                TokenType::Symbol => token.value.as_deref() == Some(")"),
+                _ => false,
+            })
+    }
+
+    fn unterminated(&self, quote: char, sources: &SourceMap) -> LexerError {
+        let (headline, help) = match quote {
+            '"' => (
+                "Unterminated string",
+                "Close the string with a double quote (\") on the same line",
+            ),
+            _ => (
+                "Unterminated character literal",
+                "Close the character with a single quote (') on the same line",
+            ),
+        };
+        LexerError::UnterminatedString {
+            message: render_error(Diagnostic {
+                headline: headline.to_string(),
+                loc: SourceLoc {
+                    file: self.file,
+                    ..self.token_loc
+                },
+                sources,
+                help: Some(help),
+            }),
+        }
+    }
+
+    // drops the line with the unterminated literal, so lexing carries on with the next one
+    fn abandon_line(&mut self, quote: char, sources: &SourceMap) {
+        let error = self.unterminated(quote, sources);
+        self.errors.push((
+            SourceLoc {
+                file: self.file,
+                ..self.token_loc
+            },
+            error,
+        ));
+        self.token.clear();
+        let line_start = self
+            .tokens
+            .tokens
+            .iter()
+            .rposition(|token| token.token_type == TokenType::Newline)
+            .map_or(0, |newline| newline + 1);
+        self.tokens.tokens.truncate(line_start);
+    }
+
+    // tokens of one file of the source map, lines with errors are left out
     pub fn lex(
         &mut self,
-        assembly_program: &str,
-    ) -> Result<(TokenStream, Vec<String>), LexerError> {
+        file: u32,
+        sources: &SourceMap,
+    ) -> (TokenStream, Vec<Located<LexerError>>) {
+        let assembly_program = sources.lines(file).join("\n");
+        self.file = file;
         let mut is_comment = false;
-        self.source_lines = assembly_program
-            .split('\n')
-            .map(|s| s.to_string())
-            .collect();
+        // string and char literals stay one identifier, quotes and escapes included
+        let mut quote: Option<char> = None;
+        let mut escaped = false;
         for char in assembly_program.chars() {
             self.column += 1;
             if is_comment {
@@ -95,8 +170,31 @@ impl Lexer {
                 }
                 continue;
             }
+            if let Some(open) = quote
+                && char == '\n'
+            {
+                self.abandon_line(open, sources);
+                quote = None;
+                escaped = false;
+            } else if let Some(open) = quote {
+                self.token.push(char);
+                if escaped {
+                    escaped = false;
+                } else if char == '\\' {
+                    escaped = true;
+                } else if char == open {
+                    quote = None;
+                }
+                continue;
+            }
             match char {
-                ':' | ',' | '+' | '(' | ')' | '&' => {
+                '-' if self.token.is_empty() && !self.follows_operand() => {
+                    self.token_loc.line = self.line;
+                    self.token_loc.column = self.column;
+                    self.token.push(char);
+                }
+                ':' | ',' | '+' | '-' | '*' | '/' | '<' | '>' | '|' | '(' | ')' | '&' | '='
+                | '[' | ']' => {
                     self.push_identifier();
                     self.token_loc.line = self.line;
                     self.token_loc.column = self.column;
@@ -125,17 +223,23 @@ impl Lexer {
                         self.token_loc.column = self.column;
                     }
                     self.token.push(char);
+                    if char == '"' || char == '\'' {
+                        quote = Some(char);
+                    }
                 }
             }
         }
+        if let Some(open) = quote {
+            self.abandon_line(open, sources);
+        }
         self.push_identifier();
         self.token_loc.line = self.line;
         self.token_loc.column = self.column + 1;
         self.push_eof();
-        Ok((
-            mem::take(&mut self.tokens),
-            mem::take(&mut self.source_lines),
-        ))
+        for token in &mut self.tokens.tokens {
+            token.source_loc.file = file;
+        }
+        (mem::take(&mut self.tokens), mem::take(&mut self.errors))
     }
 }
 
@@ -143,36 +247,77 @@ impl Lexer {
 mod tests {
     use super::*;
 
+    fn lex(program: &str) -> Result<TokenStream, LexerError> {
+        let (tokens, mut errors) = Lexer::new().lex(0, &SourceMap::from_program(program));
+        match errors.is_empty() {
+            true => Ok(tokens),
+            false => Err(errors.remove(0).1),
+        }
+    }
+
     #[test]
     fn test_lexer() {
-        let mut lexer = Lexer::new();
-        let tokens = lexer.lex("MOVER R1, 0").unwrap().0.tokens;
+        let tokens = lex("MOVER R1, 0").unwrap().tokens;
 
         assert_eq!(tokens.len(), 7);
 
         assert_eq!(tokens[0].token_type, TokenType::Identifier);
         assert_eq!(tokens[0].value, Some("MOVER".to_string()));
-        assert_eq!(tokens[0].source_loc, SourceLoc { line: 1, column: 1 });
+        assert_eq!(
+            tokens[0].source_loc,
+            SourceLoc {
+                file: 0,
+                line: 1,
+                column: 1,
+                expansion: None,
+            }
+        );
 
         assert_eq!(tokens[1].token_type, TokenType::Whitespace);
         assert_eq!(tokens[1].value, None);
-        assert_eq!(tokens[1].source_loc, SourceLoc { line: 1, column: 6 });
+        assert_eq!(
+            tokens[1].source_loc,
+            SourceLoc {
+                file: 0,
+                line: 1,
+                column: 6,
+                expansion: None,
+            }
+        );
 
         assert_eq!(tokens[2].token_type, TokenType::Identifier);
         assert_eq!(tokens[2].value, Some("R1".to_string()));
-        assert_eq!(tokens[2].source_loc, SourceLoc { line: 1, column: 7 });
+        assert_eq!(
+            tokens[2].source_loc,
+            SourceLoc {
+                file: 0,
+                line: 1,
+                column: 7,
+                expansion: None,
+            }
+        );
 
         assert_eq!(tokens[3].token_type, TokenType::Symbol);
         assert_eq!(tokens[3].value, Some(','.to_string()));
-        assert_eq!(tokens[3].source_loc, SourceLoc { line: 1, column: 9 });
+        assert_eq!(
+            tokens[3].source_loc,
+            SourceLoc {
+                file: 0,
+                line: 1,
+                column: 9,
+                expansion: None,
+            }
+        );
 
         assert_eq!(tokens[4].token_type, TokenType::Whitespace);
         assert_eq!(tokens[4].value, None);
         assert_eq!(
             tokens[4].source_loc,
             SourceLoc {
+                file: 0,
                 line: 1,
-                column: 10
+                column: 10,
+                expansion: None,
             }
         );
 
@@ -181,8 +326,10 @@ mod tests {
         assert_eq!(
             tokens[5].source_loc,
             SourceLoc {
+                file: 0,
                 line: 1,
-                column: 11
+                column: 11,
+                expansion: None,
             }
         );
 
@@ -191,45 +338,127 @@ mod tests {
         assert_eq!(
             tokens[6].source_loc,
             SourceLoc {
+                file: 0,
+                line: 1,
+                column: 12,
+                expansion: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_operators() {
+        let tokens = lex("A-1,-2*(B>>C)").unwrap().tokens;
+        let values: Vec<String> = tokens
+            .iter()
+            .filter_map(|token| token.value.clone())
+            .collect();
+        assert_eq!(
+            values,
+            ["A", "-", "1", ",", "-2", "*", "(", "B", ">", ">", "C", ")"]
+        );
+    }
+
+    #[test]
+    fn test_string_literal() {
+        let tokens = lex("ASCII \"a, b; \\\"c\\\"\" ; done").unwrap().tokens;
+        assert_eq!(tokens[2].value, Some("\"a, b; \\\"c\\\"\"".to_string()));
+        assert_eq!(
+            tokens[2].source_loc,
+            SourceLoc {
+                file: 0,
                 line: 1,
-                column: 12
+                column: 7,
+                expansion: None,
             }
         );
+        assert!(matches!(
+            lex("ASCII \"open\nHALT"),
+            Err(LexerError::UnterminatedString { .. })
+        ));
+        let tokens = lex("MOVEI R0, ' '").unwrap().tokens;
+        assert_eq!(tokens[5].value, Some("' '".to_string()));
+        assert!(lex("MOVEI R0, 'A").is_err());
+    }
+
+    #[test]
+    fn test_recovery() {
+        let sources = SourceMap::from_program("ASCII \"open\nHALT\nDB 'x");
+        let (tokens, errors) = Lexer::new().lex(0, &sources);
+        let values: Vec<&str> = tokens
+            .tokens
+            .iter()
+            .filter_map(|token| token.value.as_deref())
+            .collect();
+        assert_eq!(values, ["HALT"]);
+        let lines: Vec<u32> = errors.iter().map(|(loc, _)| loc.line).collect();
+        assert_eq!(lines, [1, 3]);
     }
 
     #[test]
     fn test() {
-        let mut lexer = Lexer::new();
-        let tokens = lexer
-            .lex("MOVE: MOVER R0, 0\nMOVE1: MOVER R0, 0;comment\n")
+        let tokens = lex("MOVE: MOVER R0, 0\nMOVE1: MOVER R0, 0;comment\n")
             .unwrap()
-            .0
             .tokens;
         assert_eq!(tokens.len(), 21);
 
         assert_eq!(tokens[0].token_type, TokenType::Identifier);
         assert_eq!(tokens[0].value, Some("MOVE".to_string()));
-        assert_eq!(tokens[0].source_loc, SourceLoc { line: 1, column: 1 });
+        assert_eq!(
+            tokens[0].source_loc,
+            SourceLoc {
+                file: 0,
+                line: 1,
+                column: 1,
+                expansion: None,
+            }
+        );
 
         assert_eq!(tokens[1].token_type, TokenType::Symbol);
         assert_eq!(tokens[1].value, Some(':'.to_string()));
-        assert_eq!(tokens[1].source_loc, SourceLoc { line: 1, column: 5 });
+        assert_eq!(
+            tokens[1].source_loc,
+            SourceLoc {
+                file: 0,
+                line: 1,
+                column: 5,
+                expansion: None,
+            }
+        );
 
         assert_eq!(tokens[2].token_type, TokenType::Whitespace);
         assert_eq!(tokens[2].value, None);
-        assert_eq!(tokens[2].source_loc, SourceLoc { line: 1, column: 6 });
+        assert_eq!(
+            tokens[2].source_loc,
+            SourceLoc {
+                file: 0,
+                line: 1,
+                column: 6,
+                expansion: None,
+            }
+        );
 
         assert_eq!(tokens[3].token_type, TokenType::Identifier);
         assert_eq!(tokens[3].value, Some("MOVER".to_string()));
-        assert_eq!(tokens[3].source_loc, SourceLoc { line: 1, column: 7 });
+        assert_eq!(
+            tokens[3].source_loc,
+            SourceLoc {
+                file: 0,
+                line: 1,
+                column: 7,
+                expansion: None,
+            }
+        );
 
         assert_eq!(tokens[4].token_type, TokenType::Whitespace);
         assert_eq!(tokens[4].value, None);
         assert_eq!(
             tokens[4].source_loc,
             SourceLoc {
+                file: 0,
                 line: 1,
-                column: 12
+                column: 12,
+                expansion: None,
             }
         );
 
@@ -238,8 +467,10 @@ mod tests {
         assert_eq!(
             tokens[5].source_loc,
             SourceLoc {
+                file: 0,
                 line: 1,
-                column: 13
+                column: 13,
+                expansion: None,
             }
         );
 
@@ -248,8 +479,10 @@ mod tests {
         assert_eq!(
             tokens[6].source_loc,
             SourceLoc {
+                file: 0,
                 line: 1,
-                column: 15
+                column: 15,
+                expansion: None,
             }
         );
 
@@ -258,8 +491,10 @@ mod tests {
         assert_eq!(
             tokens[7].source_loc,
             SourceLoc {
+                file: 0,
                 line: 1,
-                column: 16
+                column: 16,
+                expansion: None,
             }
         );
 
@@ -268,8 +503,10 @@ mod tests {
         assert_eq!(
             tokens[8].source_loc,
             SourceLoc {
+                file: 0,
                 line: 1,
-                column: 17
+                column: 17,
+                expansion: None,
             }
         );
 
@@ -278,34 +515,70 @@ mod tests {
         assert_eq!(
             tokens[9].source_loc,
             SourceLoc {
+                file: 0,
                 line: 1,
-                column: 18
+                column: 18,
+                expansion: None,
             }
         );
 
         assert_eq!(tokens[10].token_type, TokenType::Identifier);
         assert_eq!(tokens[10].value, Some("MOVE1".to_string()));
-        assert_eq!(tokens[10].source_loc, SourceLoc { line: 2, column: 1 });
+        assert_eq!(
+            tokens[10].source_loc,
+            SourceLoc {
+                file: 0,
+                line: 2,
+                column: 1,
+                expansion: None,
+            }
+        );
 
         assert_eq!(tokens[11].token_type, TokenType::Symbol);
         assert_eq!(tokens[11].value, Some(':'.to_string()));
-        assert_eq!(tokens[11].source_loc, SourceLoc { line: 2, column: 6 });
+        assert_eq!(
+            tokens[11].source_loc,
+            SourceLoc {
+                file: 0,
+                line: 2,
+                column: 6,
+                expansion: None,
+            }
+        );
 
         assert_eq!(tokens[12].token_type, TokenType::Whitespace);
         assert_eq!(tokens[12].value, None);
-        assert_eq!(tokens[12].source_loc, SourceLoc { line: 2, column: 7 });
+        assert_eq!(
+            tokens[12].source_loc,
+            SourceLoc {
+                file: 0,
+                line: 2,
+                column: 7,
+                expansion: None,
+            }
+        );
 
         assert_eq!(tokens[13].token_type, TokenType::Identifier);
         assert_eq!(tokens[13].value, Some("MOVER".to_string()));
-        assert_eq!(tokens[13].source_loc, SourceLoc { line: 2, column: 8 });
+        assert_eq!(
+            tokens[13].source_loc,
+            SourceLoc {
+                file: 0,
+                line: 2,
+                column: 8,
+                expansion: None,
+            }
+        );
 
         assert_eq!(tokens[14].token_type, TokenType::Whitespace);
         assert_eq!(tokens[14].value, None);
         assert_eq!(
             tokens[14].source_loc,
             SourceLoc {
+                file: 0,
                 line: 2,
-                column: 13
+                column: 13,
+                expansion: None,
             }
         );
 
@@ -314,8 +587,10 @@ mod tests {
         assert_eq!(
             tokens[15].source_loc,
             SourceLoc {
+                file: 0,
                 line: 2,
-                column: 14
+                column: 14,
+                expansion: None,
             }
         );
 
@@ -324,8 +599,10 @@ mod tests {
         assert_eq!(
             tokens[16].source_loc,
             SourceLoc {
+                file: 0,
                 line: 2,
-                column: 16
+                column: 16,
+                expansion: None,
             }
         );
 
@@ -334,8 +611,10 @@ mod tests {
         assert_eq!(
             tokens[17].source_loc,
             SourceLoc {
+                file: 0,
                 line: 2,
-                column: 17
+                column: 17,
+                expansion: None,
             }
         );
 
@@ -344,8 +623,10 @@ mod tests {
         assert_eq!(
             tokens[18].source_loc,
             SourceLoc {
+                file: 0,
                 line: 2,
-                column: 18
+                column: 18,
+                expansion: None,
             }
         );
 
@@ -354,13 +635,23 @@ mod tests {
         assert_eq!(
             tokens[19].source_loc,
             SourceLoc {
+                file: 0,
                 line: 2,
-                column: 27
+                column: 27,
+                expansion: None,
             }
         );
 
         assert_eq!(tokens[20].token_type, TokenType::Eof);
         assert_eq!(tokens[20].value, None);
-        assert_eq!(tokens[20].source_loc, SourceLoc { line: 3, column: 1 });
+        assert_eq!(
+            tokens[20].source_loc,
+            SourceLoc {
+                file: 0,
+                line: 3,
+                column: 1,
+                expansion: None,
+            }
+        );
     }
 }
@@ -9,8 +9,13 @@ pub enum TokenType {
 
 #[derive(Debug, Clone, Default, PartialEq, Copy, Eq, Hash)]
 pub struct SourceLoc {
+    // index into the `SourceMap`, 0 is the file being assembled
+    pub file: u32,
     pub line: u32,
     pub column: u32,
+    // line of the file being assembled with the outermost macro call that expanded this
+    // token, `line` stays in the body
+    pub expansion: Option<u32>,
 }
 
 impl std::fmt::Display for SourceLoc {
@@ -1,17 +1,31 @@
 mod encoder;
+mod include;
 mod lexer;
+pub mod listing;
 mod parser;
 mod preprocessor;
 mod render_error;
+mod source_map;
 pub mod writer;
 
+use container::{Container, ContainerError, Object};
+use isa::OptSpec;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 use thiserror::Error;
 
 use self::{
     encoder::{Encoder, EncoderError, delimiter::DelimiterTable},
-    lexer::{Lexer, LexerError},
+    include::{IncludeError, Includer},
+    lexer::LexerError,
+    listing::Listing,
     parser::{Parser, ParserError},
     preprocessor::{PreProcessor, PreProcessorError},
+    render_error::Located,
+    source_map::SourceMap,
+    writer::{Writer, WriterError},
 };
 
 #[derive(Debug, Error)]
@@ -22,48 +36,593 @@ pub enum AssemblerError {
     Unknown { msg: String },
     #[error("Lexer error:\n{0}")]
     Lexer(#[from] LexerError),
+    #[error("Include error:\n{0}")]
+    Include(IncludeError),
     #[error("{0}")]
     Parser(#[from] ParserError),
     #[error("Encoder error:\n{0}")]
     Encoder(#[from] EncoderError),
     #[error("Preprocessor error:\n{0}")]
     PreProcessor(#[from] PreProcessorError),
+    #[error("Writer error:\n{0}")]
+    Writer(#[from] WriterError),
+    #[error("Container error:\n{0}")]
+    Container(#[from] ContainerError),
+    #[error("{}", join(.0))]
+    Multiple(Vec<AssemblerError>),
+}
+
+fn join(errors: &[AssemblerError]) -> String {
+    let mut message = String::new();
+    for error in errors {
+        message.push_str(error.to_string().trim_end());
+        message.push('\n');
+    }
+    message + &format!("{} errors", errors.len())
+}
+
+// a single error as is, several in the order of the lines of the main file they come from
+fn combine(mut errors: Vec<Located<AssemblerError>>, sources: &SourceMap) -> AssemblerError {
+    errors.sort_by_key(|(loc, _)| (sources.main_line(loc), loc.file, loc.line, loc.column));
+    // an EQU defined in terms of itself fails at every use with the same message
+    let mut seen = HashSet::new();
+    errors.retain(|(_, error)| seen.insert(error.to_string()));
+    let mut errors: Vec<AssemblerError> = errors.into_iter().map(|(_, error)| error).collect();
+    match errors.len() {
+        1 => errors.remove(0),
+        _ => AssemblerError::Multiple(errors),
+    }
 }
 
-pub struct MyAssembler {}
+pub struct MyAssembler {
+    symbols: HashMap<String, u32>,
+    listing: Listing,
+    include_paths: Vec<PathBuf>,
+    isa: OptSpec,
+}
 
 impl MyAssembler {
     pub fn new() -> Result<Self, AssemblerError> {
-        Ok(Self {})
+        Ok(Self {
+            symbols: HashMap::new(),
+            listing: Listing::default(),
+            include_paths: Vec::new(),
+            isa: OptSpec::clone(),
+        })
+    }
+
+    // label bit addresses of the last successful assembly
+    pub fn symbols(&self) -> &HashMap<String, u32> {
+        &self.symbols
+    }
+
+    // listing of the last successful assembly
+    pub fn listing(&self) -> &Listing {
+        &self.listing
+    }
+
+    // directories searched for INCLUDE files that are not next to the including file
+    pub fn add_include_path(&mut self, path: impl Into<PathBuf>) {
+        self.include_paths.push(path.into());
+    }
+
+    // assemble for an ISA loaded from a description file instead of the built-in one
+    pub fn set_isa(&mut self, isa: OptSpec) {
+        self.isa = isa;
     }
 
     pub fn assemble(
         &mut self,
         assembly_program: &str,
-    ) -> Result<(Vec<u8>, DelimiterTable), AssemblerError> {
-        let mut lexer = Lexer::new();
+    ) -> Result<(Container, DelimiterTable), AssemblerError> {
+        self.assemble_source("", assembly_program)
+    }
+
+    // `name` is the path of the program, used in diagnostics and to find its includes
+    pub fn assemble_source(
+        &mut self,
+        name: &str,
+        assembly_program: &str,
+    ) -> Result<(Container, DelimiterTable), AssemblerError> {
+        let (container, delimiter_table, _) = self.translate(name, assembly_program, false)?;
+        Ok((container, delimiter_table))
+    }
+
+    // a relocatable object for the linker, with GLOBAL and EXTERN symbols and the
+    // fields the linker has to patch
+    pub fn assemble_object(
+        &mut self,
+        name: &str,
+        assembly_program: &str,
+    ) -> Result<Object, AssemblerError> {
+        let (container, _, parser) = self.translate(name, assembly_program, true)?;
+        Ok(Object {
+            isa: container.isa,
+            code: container.code,
+            code_bits: container.code_bits,
+            data: container.data,
+            globals: parser.exports(),
+            externs: parser.externs(),
+            relocations: parser.relocations().to_vec(),
+        })
+    }
+
+    fn translate(
+        &mut self,
+        name: &str,
+        assembly_program: &str,
+        relocatable: bool,
+    ) -> Result<(Container, DelimiterTable, Parser), AssemblerError> {
+        let mut sources = SourceMap::new();
+        let file = sources.add(name, std::path::Path::new(name), assembly_program, None);
+        let mut includer = Includer::new(self.include_paths.clone());
         let mut preprocessor = PreProcessor::new();
         let mut parser = Parser::new();
         let mut encoder = Encoder::new();
+        parser.set_relocatable(relocatable);
+        parser.set_isa(self.isa.clone());
 
-        println!("Assembling...");
-        let (mut tokens, source_lines) = lexer.lex(assembly_program)?;
-        preprocessor.preprocess(&mut tokens, &source_lines)?;
-        let instructions = parser.parse(tokens, &source_lines)?;
+        let mut tokens = includer
+            .expand(file, &mut sources)
+            .map_err(AssemblerError::Include)?;
+        let mut errors: Vec<Located<AssemblerError>> = includer
+            .lexer_errors()
+            .into_iter()
+            .map(|(loc, error)| (loc, error.into()))
+            .collect();
+        // a failed macro leaves out its definition or invocation, the rest is still parsed
+        if let Err(preprocessor_errors) = preprocessor.preprocess(&mut tokens, &sources) {
+            errors.extend(
+                preprocessor_errors
+                    .into_iter()
+                    .map(|(loc, error)| (loc, error.into())),
+            );
+        }
+        let instructions = match parser.parse(tokens, &sources) {
+            Ok(instructions) if errors.is_empty() => instructions,
+            Ok(_) => return Err(combine(errors, &sources)),
+            Err(parser_errors) => {
+                errors.extend(
+                    parser_errors
+                        .into_iter()
+                        .map(|(loc, error)| (loc, error.into())),
+                );
+                return Err(combine(errors, &sources));
+            }
+        };
+        let listing_instructions = instructions.clone();
         let (binary, delimiter_table) = encoder.encode(instructions)?;
+        self.symbols = parser.symbols().clone();
+
+        // the encoder emits the bitstream followed by its bit length, which is the legacy layout
+        let mut container = Container::from_legacy(&binary)?;
+        container.isa = self.isa.identifier();
+        container.data = parser.data().to_vec();
+        container.symbols = Some(self.symbols.clone().into_iter().collect());
+        container.debug = Some(
+            listing_instructions
+                .iter()
+                .map(|instruction| (instruction.address, instruction.line))
+                .collect(),
+        );
+        self.listing = Listing::new(sources.lines(file).to_vec(), listing_instructions, &parser);
+
+        Ok((container, delimiter_table, parser))
+    }
 
-        Ok((binary, delimiter_table))
+    // assembles straight into `output`, which may be `writer::STDIO`
+    pub fn assemble_to(
+        &mut self,
+        assembly_program: &str,
+        output: &str,
+    ) -> Result<(), AssemblerError> {
+        let (container, mut delimiter_table) = self.assemble(assembly_program)?;
+        Writer::new(output, false, false)?.write(&container, &mut delimiter_table)?;
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use container::{Section, Target};
+    use std::collections::BTreeMap;
 
     #[test]
     fn test_assemble() {
         let mut assembler = MyAssembler::new().unwrap();
-        let (binary, _) = assembler.assemble("MOVE:\nMOVER R0, 0").unwrap();
-        assert_eq!(binary, vec![4, 0, 0, 0, 0, 12]);
+        let (container, _) = assembler.assemble("MOVE:\nMOVER R0, 0").unwrap();
+        assert_eq!(container.code, vec![4, 0]);
+        assert_eq!(container.code_bits, 12);
+        assert_eq!(container.isa, OptSpec::clone().identifier());
+        assert_eq!(container.debug, Some(vec![(0, 2)]));
+        assert_eq!(assembler.symbols().get("MOVE"), Some(&0));
+    }
+
+    #[test]
+    fn test_forward_reference() {
+        let mut assembler = MyAssembler::new().unwrap();
+        let (binary, _) = assembler.assemble("JZ END\nJNZ END\nEND: HALT").unwrap();
+        // both jumps are patched with the address of END, 44
+        assert_eq!(
+            binary.code[..6],
+            [
+                0b01011000, 0b00000000, 0b10110001, 0b01110000, 0b00000010, 0b11000000
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assemble_to() {
+        let output = std::env::temp_dir().join(format!("assemble_to_{}.bin", std::process::id()));
+        let output = output.to_str().unwrap();
+        let mut assembler = MyAssembler::new().unwrap();
+        assembler.assemble_to("MOVER R0, 0", output).unwrap();
+        let container = Container::parse(&std::fs::read(output).unwrap()).unwrap();
+        assert_eq!(container.code, vec![4, 0]);
+        assert_eq!(container.symbols, Some(Default::default()));
+        std::fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn test_data_directives() {
+        let mut assembler = MyAssembler::new().unwrap();
+        let (container, _) = assembler
+            .assemble(
+                "MOVER R1, COUNT\nJMP END\nEND: HALT\n\
+                 TABLE: DB 1, -1, 255\nWORD: DW 258\nCOUNT: ASCIIZ \"a\\n\"\nPAD: DS 6\n",
+            )
+            .unwrap();
+        // data that would reach the devices at 12 is placed after the interrupt vectors
+        let mut data = vec![1, 255, 255, 1, 2, b'a', b'\n', 0];
+        data.extend([0; 22]);
+        assert_eq!(container.data, data);
+        let listing = assembler.listing().to_string();
+        assert!(
+            listing.contains("\nPAD                  24   18\n"),
+            "{}",
+            listing
+        );
+        // a data label is a memory operand, a forward code label resolves once defined
+        assert_eq!(
+            container.code,
+            vec![0b00000101, 0b01010101, 0b01000000, 0b00001000, 0b10000000]
+        );
+        assert_eq!(assembler.symbols().get("END"), Some(&34));
+        assert_eq!(assembler.symbols().get("COUNT"), None);
+    }
+
+    #[test]
+    fn test_expressions() {
+        let mut assembler = MyAssembler::new().unwrap();
+        let (container, _) = assembler
+            .assemble(
+                "TOP EQU END - 8\nTWICE EQU SIZE * 2\nSIZE EQU 3\n\
+                 MOVEI R0, TWICE\nMOVEI R1, -(SIZE << 1) | 1\nMOVER R2, TABLE + 1\n\
+                 MOVEI R3, HIGH(300)\nJMP TOP+8\nEND: HALT\nTABLE: DB SIZE, LOW(END)\n",
+            )
+            .unwrap();
+        assert_eq!(
+            container.code,
+            vec![8, 6, 9, 251, 6, 16, 176, 21, 64, 20, 128]
+        );
+        assert_eq!(container.data, vec![3, 82]);
+        assert_eq!(assembler.symbols().get("TOP"), None);
+    }
+
+    #[test]
+    fn test_addressing_modes() {
+        let mut assembler = MyAssembler::new().unwrap();
+        let (container, _) = assembler
+            .assemble("MOVER R0, [R1]\nMOVEM R2, [R3 + TABLE + 1]\nMOVER R1, [R2+]\nHALT\nTABLE: DB 1, 2\n")
+            .unwrap();
+        // indexed operands hold the offset, then the register
+        assert_eq!(container.code, vec![184, 114, 135, 193, 128]);
+
+        let error = MyAssembler::new()
+            .unwrap()
+            .assemble("MOVER R0, [R4]\nMOVEM R0, [R1+16]\nMOVEI R0, [R1]")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Token '[R4]' does not look like a register-indirect address"));
+        assert!(error.contains("'16' evaluates to 16, which does not fit in a 4 bit operand"));
+        assert!(error.contains("Token '[R1]' does not look like a constant"));
+    }
+
+    #[test]
+    fn test_literals() {
+        let mut assembler = MyAssembler::new().unwrap();
+        let (container, _) = assembler
+            .assemble(
+                "MOVEI R0, 0x1F\nMOVEI R1, 0b1010\nMOVEI R2, 'A'\nMOVEI R3, '\\n'\n\
+                 MOVER R0, 0xF\nHALT\nTEXT: DB \"hi\", 0, ' ', 0x7F\n",
+            )
+            .unwrap();
+        assert_eq!(
+            container.code,
+            vec![8, 31, 9, 10, 10, 65, 11, 10, 4, 240, 0]
+        );
+        assert_eq!(container.data, vec![b'h', b'i', 0, b' ', 0x7F]);
+
+        let error = MyAssembler::new()
+            .unwrap()
+            .assemble("MOVER R0, 0x10")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("'0x10' evaluates to 16, which does not fit in a 4 bit operand"));
+        assert!(error.contains("Expected a value between 0 and 15"));
+    }
+
+    #[test]
+    fn test_data_directive_errors() {
+        let errors = [
+            ("DB 256", "out of range"),
+            ("DB 1.5", "is not a number"),
+            ("DB X", "is not defined yet"),
+            ("ASCII hi", "is not a string"),
+            (
+                "DS 4\nDS 201",
+                "the top 32 are kept for the stack, this directive would end at byte 225",
+            ),
+            ("JMP TEXT\nTEXT: DB 0", "is a data label"),
+            ("MOVER R0, LOOP\nLOOP: HALT", "is a code label"),
+            ("JMP NOWHERE", "NOWHERE"),
+            (
+                "A EQU B\nB EQU A\nMOVEI R0, A",
+                "defined in terms of itself",
+            ),
+            ("MOVEI R0, 1/(2-2)", "Division by zero"),
+            ("MOVEI R0, 100*3", "does not fit in a 8 bit operand"),
+            ("EQU 1", "EQU without a name"),
+            ("MOVEI R0, 1 +", "Incomplete expression"),
+            ("MOVEI R0, 0x100", "Expected a value between -128 and 255"),
+            ("MOVEI R0, 0b102", "does not look like a constant"),
+            ("MOVEI R0, 'AB'", "exactly one character"),
+            ("MOVEI R0, '\\q'", "Unknown escape sequence"),
+            ("ASCII \"\\x4\"", "Invalid escape sequence"),
+        ];
+        for (program, expected) in errors {
+            let mut assembler = MyAssembler::new().unwrap();
+            let error = assembler.assemble(program).unwrap_err().to_string();
+            assert!(error.contains(expected), "{}: {}", program, error);
+        }
+    }
+
+    fn kind(error: &AssemblerError) -> &'static str {
+        match error {
+            AssemblerError::Lexer(_) => "lexer",
+            AssemblerError::Parser(ParserError::SyntacticParsing(_)) => "syntactic",
+            AssemblerError::Parser(ParserError::SemanticParsing(_)) => "semantic",
+            AssemblerError::PreProcessor(_) => "preprocessor",
+            _ => "other",
+        }
+    }
+
+    #[test]
+    fn test_isa() {
+        let description = isa::DEFAULT_ISA
+            .replace("opcode_bit_count = 6", "opcode_bit_count = 7")
+            .replace("opcode = 0\n", "opcode = 127\n");
+        let isa = OptSpec::from_toml(&description).unwrap();
+        let identifier = isa.identifier();
+        let mut assembler = MyAssembler::new().unwrap();
+        assembler.set_isa(isa);
+        let (container, _) = assembler.assemble("HALT\nHALT").unwrap();
+        assert_eq!(container.code, vec![0b11111111, 0b11111100]);
+        assert_eq!(container.code_bits, 14);
+        assert_eq!(container.isa, identifier);
+        assert_ne!(identifier, OptSpec::clone().identifier());
+    }
+
+    #[test]
+    fn test_program_too_large() {
+        let description =
+            isa::DEFAULT_ISA.replace("address_bit_count = 16", "address_bit_count = 8");
+        let mut assembler = MyAssembler::new().unwrap();
+        assembler.set_isa(OptSpec::from_toml(&description).unwrap());
+        // sixteen MOVEI fill the 256 bits exactly, the next one is the only error
+        let source = "MOVEI R0, 1\n".repeat(16);
+        assert_eq!(assembler.assemble(&source).unwrap().0.code_bits, 256);
+        let error = assembler
+            .assemble(&(source + "MOVEI R1, 2\nHALT\n"))
+            .unwrap_err()
+            .to_string();
+        assert!(
+            error.contains("does not fit in 8 bit code addresses"),
+            "{}",
+            error
+        );
+        assert!(error.contains("this instruction ends at bit 272"));
+        assert!(!error.contains("errors"));
+    }
+
+    #[test]
+    fn test_data_memory_size() {
+        let description =
+            isa::DEFAULT_ISA.replace("data_memory_size = 256", "data_memory_size = 512");
+        let mut assembler = MyAssembler::new().unwrap();
+        assembler.set_isa(OptSpec::from_toml(&description).unwrap());
+        // the second directive goes after the devices and vectors, 24..424
+        let (container, _) = assembler.assemble("DS 8\nDS 400").unwrap();
+        assert_eq!(container.data.len(), 424);
+        let error = assembler.assemble("DS 8\nDS 457").unwrap_err().to_string();
+        assert!(
+            error.contains(
+                "Data memory holds 512 bytes and the top 32 are kept for the stack, this directive would end at byte 481"
+            ),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn test_multiple_errors() {
+        let error = MyAssembler::new()
+            .unwrap()
+            .assemble("JMP NOWHERE\nMOVER R0, \"abc\nHALT\nMOVER R1 2\nFOO R2\n")
+            .unwrap_err();
+        let AssemblerError::Multiple(errors) = &error else {
+            panic!("{}", error);
+        };
+        let kinds: Vec<&str> = errors.iter().map(kind).collect();
+        assert_eq!(kinds, ["semantic", "lexer", "syntactic", "semantic"]);
+        assert!(error.to_string().ends_with("4 errors"));
+        assert!(error.to_string().contains("Unknown opcode 'FOO'"));
+
+        // a bad invocation is left out in line order, the lines around it are still parsed
+        let error = MyAssembler::new()
+            .unwrap()
+            .assemble(
+                "MACRO\nLOAD &REG\nMOVEI &REG, 1\nMEND\nJMP NOWHERE\nMOVER R0, \"abc\n\
+                 LOAD R1, 2\nFOO R2\n: HALT\n",
+            )
+            .unwrap_err();
+        let AssemblerError::Multiple(errors) = &error else {
+            panic!("{}", error);
+        };
+        let kinds: Vec<&str> = errors.iter().map(kind).collect();
+        assert_eq!(
+            kinds,
+            ["semantic", "lexer", "preprocessor", "semantic", "syntactic"]
+        );
+        assert!(
+            error
+                .to_string()
+                .contains("Too many arguments for macro 'LOAD'")
+        );
+        // no help line is printed for an error without help
+        assert!(
+            !error
+                .to_string()
+                .lines()
+                .any(|line| line.trim_end() == "help:"),
+            "{}",
+            error
+        );
+
+        // a broken definition is reported once, its invocations are dropped
+        let error = MyAssembler::new()
+            .unwrap()
+            .assemble("MACRO\nBAD &\nOUT R1\nMEND\nBAD R1\nFOO\nHALT\n")
+            .unwrap_err();
+        let AssemblerError::Multiple(errors) = &error else {
+            panic!("{}", error);
+        };
+        assert_eq!(
+            errors.iter().map(kind).collect::<Vec<_>>(),
+            ["preprocessor", "semantic"]
+        );
+
+        // constants defined in terms of each other are reported once each, not at every use
+        let error = MyAssembler::new()
+            .unwrap()
+            .assemble("A EQU B\nB EQU A\nMOVEI R0, A\nMOVEI R1, A")
+            .unwrap_err();
+        assert!(error.to_string().ends_with("2 errors"), "{}", error);
+    }
+
+    #[test]
+    fn test_include() {
+        let directory =
+            std::env::temp_dir().join(format!("assemble_include_{}", std::process::id()));
+        std::fs::create_dir_all(directory.join("lib")).unwrap();
+        std::fs::write(
+            directory.join("lib/util.asm"),
+            "START: MOVER R0, 0\nBAD R1\n",
+        )
+        .unwrap();
+        std::fs::write(directory.join("lib/good.asm"), "START: MOVER R0, 0\n").unwrap();
+        let main = directory.join("main.asm");
+        let main = main.to_str().unwrap();
+
+        let mut assembler = MyAssembler::new().unwrap();
+        assembler.add_include_path(directory.join("lib"));
+        let (container, _) = assembler
+            .assemble_source(main, "INCLUDE \"good.asm\"\nJMP START\n")
+            .unwrap();
+        assert_eq!(assembler.symbols().get("START"), Some(&0));
+        assert_eq!(container.debug, Some(vec![(0, 1), (12, 2)]));
+
+        let error = assembler
+            .assemble_source(main, "\nINCLUDE \"util.asm\"\n")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("util.asm:2:1"), "{}", error);
+        assert!(
+            error.contains(&format!("note: included from {}:2", main)),
+            "{}",
+            error
+        );
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_object() {
+        let mut assembler = MyAssembler::new().unwrap();
+        let object = assembler
+            .assemble_object(
+                "",
+                "GLOBAL MAIN, COUNT\nEXTERN PRINT\nMAIN: MOVER R1, COUNT\nJMP PRINT\n\
+                 LOOP: JMP LOOP + 1\nMOVEI R0, 5\nHALT\nCOUNT: DB 7\n",
+            )
+            .unwrap();
+        assert_eq!(object.data, vec![7]);
+        assert_eq!(
+            object.globals,
+            BTreeMap::from([
+                (String::from("COUNT"), (Section::Data, 0)),
+                (String::from("MAIN"), (Section::Code, 0)),
+            ])
+        );
+        assert_eq!(object.externs, vec![String::from("PRINT")]);
+        let targets: Vec<(u32, u8, Target, i32)> = object
+            .relocations
+            .iter()
+            .map(|relocation| {
+                (
+                    relocation.offset,
+                    relocation.bit_count,
+                    relocation.target.clone(),
+                    relocation.addend,
+                )
+            })
+            .collect();
+        assert_eq!(
+            targets,
+            vec![
+                (8, 4, Target::Section(Section::Data), 0),
+                (18, 16, Target::Symbol(String::from("PRINT")), 0),
+                (40, 16, Target::Section(Section::Code), 35),
+            ]
+        );
+
+        let errors = [
+            ("LOOP: JMP LOOP * 2", "cannot be relocated"),
+            // byte selectors and masks hide how far the label moves
+            ("T: MOVEI R3, LOW(T)", "cannot be relocated"),
+            ("T: MOVEI R3, HIGH(T)", "cannot be relocated"),
+            ("T: MOVEI R3, T & 0xFF", "cannot be relocated"),
+            ("T: MOVEI R3, T >> 8", "cannot be relocated"),
+            ("EXTERN X\nMOVEI R3, LOW(X)", "cannot be relocated"),
+            ("EXTERN X\nX: HALT", "declared EXTERN but defined"),
+            ("GLOBAL NOWHERE\nHALT", "Undefined label 'NOWHERE'"),
+            (
+                "HALT\nA: DB 1\nDB A",
+                "depends on where the object is linked",
+            ),
+            ("GLOBAL 1", "is not a symbol"),
+        ];
+        for (program, expected) in errors {
+            let error = assembler
+                .assemble_object("", program)
+                .unwrap_err()
+                .to_string();
+            assert!(error.contains(expected), "{}: {}", program, error);
+        }
+        let error = assembler
+            .assemble("EXTERN X\nJMP X")
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("assemble with --object"), "{}", error);
     }
 }
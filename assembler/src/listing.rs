@@ -0,0 +1,371 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::parser::{
+    Parser,
+    instruction::{DataDirective, Instruction, InstructionField},
+};
+
+// everything needed to print the source next to what it assembled into
+#[derive(Debug, Default)]
+pub struct Listing {
+    source_lines: Vec<String>,
+    instructions: Vec<Instruction>,
+    symbols: HashMap<String, u32>,
+    directives: Vec<DataDirective>,
+    data_symbols: HashMap<String, u32>,
+    definitions: HashMap<String, u32>,
+    references: HashMap<String, Vec<u32>>,
+    constants: HashMap<String, i64>,
+}
+
+enum Row<'a> {
+    Instruction(&'a Instruction),
+    Data(&'a DataDirective),
+}
+
+impl Row<'_> {
+    fn line(&self) -> u32 {
+        match self {
+            Row::Instruction(instruction) => instruction.line,
+            Row::Data(directive) => directive.line,
+        }
+    }
+
+    fn expansion(&self) -> Option<&str> {
+        match self {
+            Row::Instruction(instruction) => instruction.expansion.as_deref(),
+            Row::Data(directive) => directive.expansion.as_deref(),
+        }
+    }
+}
+
+fn binary(field: &InstructionField) -> String {
+    format!(
+        "{:0>width$b}",
+        field.value,
+        width = field.bit_count as usize
+    )
+}
+
+fn hex(field: &InstructionField) -> String {
+    format!(
+        "{:0>width$X}",
+        field.value,
+        width = (field.bit_count as usize).div_ceil(4)
+    )
+}
+
+impl Listing {
+    pub(crate) fn new(
+        source_lines: Vec<String>,
+        instructions: Vec<Instruction>,
+        parser: &Parser,
+    ) -> Self {
+        Self {
+            source_lines,
+            instructions,
+            symbols: parser.symbols().clone(),
+            directives: parser.directives().to_vec(),
+            data_symbols: parser.data_symbols().clone(),
+            definitions: parser.definitions().clone(),
+            references: parser.references().clone(),
+            constants: parser.constant_values().clone(),
+        }
+    }
+
+    fn source_line(&self, line: u32) -> &str {
+        self.source_lines
+            .get(line as usize - 1)
+            .map(|line| line.trim_end())
+            .unwrap_or("")
+    }
+
+    // instructions and data directives in the order they appeared in the source
+    fn rows(&self) -> Vec<Row<'_>> {
+        let mut rows = Vec::new();
+        let mut directives = self.directives.iter().peekable();
+        for (i, instruction) in self.instructions.iter().enumerate() {
+            while let Some(directive) = directives.next_if(|directive| directive.position <= i) {
+                rows.push(Row::Data(directive));
+            }
+            rows.push(Row::Instruction(instruction));
+        }
+        rows.extend(directives.map(Row::Data));
+        rows
+    }
+
+    // data addresses are byte addresses in data memory, marked with a `d`
+    fn write_directive(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        directive: &DataDirective,
+        source: &str,
+    ) -> std::fmt::Result {
+        let abbreviate = |parts: Vec<String>, shown: usize| {
+            let mut text = parts[..parts.len().min(shown)].join(" ");
+            if parts.len() > shown {
+                text.push_str(" ..");
+            }
+            text
+        };
+        let binary = abbreviate(
+            directive
+                .bytes
+                .iter()
+                .map(|byte| format!("{:08b}", byte))
+                .collect(),
+            2,
+        );
+        let hex = abbreviate(
+            directive
+                .bytes
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect(),
+            3,
+        );
+        writeln!(
+            f,
+            "{:>5} {:>6}  {:<26} {:<14} {:<14} {}",
+            directive.line,
+            format!("d{}", directive.address),
+            binary,
+            hex,
+            "",
+            source
+        )
+    }
+
+    fn write_instruction(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        instruction: &Instruction,
+        source: &str,
+    ) -> std::fmt::Result {
+        let mut fields = vec![&instruction.opcode];
+        if let Some(operands) = &instruction.operands {
+            fields.extend(operands);
+        }
+        let binary: Vec<String> = fields.iter().map(|field| binary(field)).collect();
+        let hex: Vec<String> = fields.iter().map(|field| hex(field)).collect();
+        let labels: Vec<String> = instruction
+            .labels
+            .iter()
+            .map(|label| match self.symbols.get(label) {
+                Some(address) => format!("{}={}", label, address),
+                None => format!("{}=?", label),
+            })
+            .collect();
+        writeln!(
+            f,
+            "{:>5} {:>6}  {:<26} {:<14} {:<14} {}",
+            instruction.line,
+            instruction.address,
+            binary.join(" "),
+            hex.join(" "),
+            labels.join(" "),
+            source
+        )
+    }
+}
+
+impl Display for Listing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:>5} {:>6}  {:<26} {:<14} {:<14} SOURCE",
+            "LINE", "ADDR", "BINARY", "HEX", "LABELS"
+        )?;
+        // lines that produced no instruction (labels, comments, macro definitions) are
+        // printed as they are passed; a macro call is printed on its own, followed by the
+        // expanded instructions indented under it
+        let mut printed = 0;
+        for row in self.rows() {
+            let last_plain = match row.expansion() {
+                Some(_) => row.line(),
+                None => row.line() - 1,
+            };
+            while printed < last_plain {
+                printed += 1;
+                writeln!(f, "{:>5} {:>64} {}", printed, "", self.source_line(printed))?;
+            }
+            let source = match row.expansion() {
+                Some(expansion) => format!("    {}", expansion),
+                None => self.source_line(row.line()).to_string(),
+            };
+            match row {
+                Row::Instruction(instruction) => self.write_instruction(f, instruction, &source)?,
+                Row::Data(directive) => self.write_directive(f, directive, &source)?,
+            }
+            printed = printed.max(row.line());
+        }
+        while (printed as usize) < self.source_lines.len() {
+            printed += 1;
+            writeln!(f, "{:>5} {:>64} {}", printed, "", self.source_line(printed))?;
+        }
+
+        let mut symbols: Vec<(&String, &u32)> = self.symbols.iter().collect();
+        symbols.sort_by_key(|(label, address)| (**address, label.as_str()));
+
+        writeln!(f, "\nSYMBOL TABLE")?;
+        writeln!(f, "{:<16} {:>6} {:>4}", "LABEL", "ADDR", "HEX")?;
+        for (label, address) in &symbols {
+            writeln!(f, "{:<16} {:>6} {:>4X}", label, address, address)?;
+        }
+
+        let mut data_symbols: Vec<(&String, &u32)> = self.data_symbols.iter().collect();
+        data_symbols.sort_by_key(|(label, address)| (**address, label.as_str()));
+        if !data_symbols.is_empty() {
+            writeln!(f, "\nDATA SYMBOL TABLE")?;
+            writeln!(f, "{:<16} {:>6} {:>4}", "LABEL", "ADDR", "HEX")?;
+            for (label, address) in &data_symbols {
+                writeln!(f, "{:<16} {:>6} {:>4X}", label, address, address)?;
+            }
+        }
+
+        let mut constants: Vec<(&String, &i64)> = self.constants.iter().collect();
+        constants.sort_by_key(|(name, _)| self.definitions.get(*name));
+        if !constants.is_empty() {
+            writeln!(f, "\nCONSTANTS")?;
+            writeln!(f, "{:<16} {:>6} {:>4}", "NAME", "VALUE", "HEX")?;
+            for &(name, &value) in &constants {
+                let hex = if value < 0 {
+                    format!("-{:X}", value.unsigned_abs())
+                } else {
+                    format!("{:X}", value)
+                };
+                writeln!(f, "{:<16} {:>6} {:>4}", name, value, hex)?;
+            }
+        }
+
+        writeln!(f, "\nCROSS REFERENCE")?;
+        writeln!(f, "{:<16} {:>7}  USED ON", "LABEL", "DEFINED")?;
+        let constant_names = constants.iter().map(|(name, _)| *name);
+        for label in symbols
+            .iter()
+            .chain(&data_symbols)
+            .map(|(label, _)| *label)
+            .chain(constant_names)
+        {
+            let defined = match self.definitions.get(label) {
+                Some(line) => line.to_string(),
+                None => String::from("-"),
+            };
+            let used = match self.references.get(label) {
+                Some(lines) => lines
+                    .iter()
+                    .map(|line| line.to_string())
+                    .collect::<Vec<String>>()
+                    .join(", "),
+                None => String::from("-"),
+            };
+            writeln!(f, "{:<16} {:>7}  {}", label, defined, used)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::MyAssembler;
+
+    #[test]
+    fn test_listing() {
+        let mut assembler = MyAssembler::new().unwrap();
+        assembler
+            .assemble("; countdown\nLOOP: SUBI R2, R2, 1\nJNZ LOOP\nHALT\n")
+            .unwrap();
+        let listing = assembler.listing().to_string();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert!(lines[1].starts_with("    1 ") && lines[1].ends_with("; countdown"));
+        assert_eq!(
+            lines[2],
+            "    2      0  001110 10 10 00000001      0E 2 2 01                     LOOP: SUBI R2, R2, 1"
+        );
+        assert_eq!(
+            lines[3],
+            "    3     18  010111 0000000000000000    17 0000        LOOP=0         JNZ LOOP"
+        );
+        assert!(lines[4].starts_with("    4     40  000000 "));
+        assert!(listing.contains("\nLOOP                  0    0\n"));
+        assert!(listing.ends_with("LOOP                   2  3\n"));
+    }
+
+    #[test]
+    fn test_macro_expansion() {
+        let mut assembler = MyAssembler::new().unwrap();
+        assembler
+            .assemble("MACRO\nSHOW &REG\nMOVEI &REG, 1\nOUT &REG\nMEND\nSHOW R1\nHALT\n")
+            .unwrap();
+        let listing = assembler.listing().to_string();
+        let lines: Vec<&str> = listing.lines().collect();
+        // the call is listed once, its expansion is attributed to it and indented
+        assert!(lines[6].starts_with("    6 ") && lines[6].ends_with(" SHOW R1"));
+        assert!(lines[7].starts_with("    6      0  ") && lines[7].ends_with("     MOVEI R1, 1"));
+        assert!(lines[8].starts_with("    6     16  ") && lines[8].ends_with("     OUT R1"));
+        assert!(lines[9].starts_with("    7     24  "));
+    }
+
+    #[test]
+    fn test_listing_data() {
+        let mut assembler = MyAssembler::new().unwrap();
+        assembler
+            .assemble("MOVER R0, TEXT\nHALT\nTEXT: ASCIIZ \"hi\"\n")
+            .unwrap();
+        let listing = assembler.listing().to_string();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(
+            lines[3],
+            "    3     d0  01101000 01101001 ..       68 69 00                      TEXT: ASCIIZ \"hi\""
+        );
+        assert!(listing.contains(
+            "DATA SYMBOL TABLE\nLABEL              ADDR  HEX\nTEXT                  0    0\n"
+        ));
+        assert!(listing.ends_with("TEXT                   3  1\n"));
+    }
+
+    #[test]
+    fn test_listing_constants() {
+        let mut assembler = MyAssembler::new().unwrap();
+        assembler
+            .assemble("SIZE EQU 3\nBACK EQU -END\nMOVEI R0, SIZE\nEND: HALT\n")
+            .unwrap();
+        let listing = assembler.listing().to_string();
+        assert!(listing.contains(
+            "CONSTANTS\nNAME              VALUE  HEX\nSIZE                  3    3\nBACK                -16  -10\n"
+        ));
+        assert!(listing.ends_with("SIZE                   1  3\nBACK                   2  -\n"));
+    }
+
+    #[test]
+    fn test_listing_include() {
+        let directory =
+            std::env::temp_dir().join(format!("listing_include_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("util.asm"),
+            "; helpers\nSTART: MOVEI R0, 1\n",
+        )
+        .unwrap();
+        let main = directory.join("main.asm");
+        let mut assembler = MyAssembler::new().unwrap();
+        assembler
+            .assemble_source(main.to_str().unwrap(), "INCLUDE \"util.asm\"\nHALT\n")
+            .unwrap();
+        let listing = assembler.listing().to_string();
+        let lines: Vec<&str> = listing.lines().collect();
+        // included code is listed under its INCLUDE line, with the file and line it came from
+        assert!(lines[1].starts_with("    1 ") && lines[1].ends_with(" INCLUDE \"util.asm\""));
+        let origin = format!(
+            "    {}:2  START: MOVEI R0, 1",
+            directory.join("util.asm").display()
+        );
+        assert!(
+            lines[2].starts_with("    1      0  ") && lines[2].ends_with(&origin),
+            "{}",
+            listing
+        );
+        assert!(lines[3].starts_with("    2     16  "));
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
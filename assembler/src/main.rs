@@ -1,66 +1,147 @@
 use args::Args;
-use assembler::{MyAssembler, writer::Writer};
+use assembler::{
+    MyAssembler,
+    writer::{
+        DEFAULT_OBJECT_OUTPUT, DEFAULT_OUTPUT, STDIO, Writer, write_listing, write_object,
+        write_symbols,
+    },
+};
+use isa::OptSpec;
 use std::{
     fs::File,
-    io::{BufReader, Read},
+    io::{self, BufReader, Read},
+    path::Path,
     process,
 };
 
+// status goes to stderr so the binary can be piped out through stdout
 fn main() {
     let args = match Args::parse() {
         Ok(args) => args,
         Err(err) => {
-            println!("Failed to parse arguments:\n\t{}", err);
+            eprintln!("Failed to parse arguments:\n\t{}", err);
             process::exit(1);
         }
     };
     let input_filename = match args.input_filename.clone() {
         Some(filename) => {
-            if filename.split('.').last().unwrap() == "asm" {
+            if filename == STDIO
+                || Path::new(&filename)
+                    .extension()
+                    .is_some_and(|extension| extension == "asm")
+            {
                 filename
             } else {
-                println!("Assembler only accepts .asm files");
+                eprintln!("Assembler only accepts .asm files");
                 process::exit(1);
             }
         }
         None => {
-            println!("Usage: assembler <filename.asm> [--debug] [--pretty] [--log=<console|file>]");
+            eprintln!(
+                "Usage: assembler <filename.asm|-> [-o|--output <file.bin|->] [-c|--object] [--debug] [--pretty] [--strip] [-I|--include <dir>]... [--isa <file.toml>] [--symbols=<file.sym>] [--listing <file.lst>] [--log=<console|file>]"
+            );
             process::exit(1);
         }
     };
+    let output = args.output.as_deref().unwrap_or(if args.object {
+        DEFAULT_OBJECT_OUTPUT
+    } else {
+        DEFAULT_OUTPUT
+    });
     let mut assembler = match MyAssembler::new() {
         Ok(assembler) => assembler,
         Err(err) => {
-            println!("Failed to create assembler:\n\t{}", err);
+            eprintln!("Failed to create assembler:\n\t{}", err);
             process::exit(1);
         }
     };
+    for path in &args.include_paths {
+        assembler.add_include_path(path);
+    }
+    if let Some(path) = &args.isa {
+        match OptSpec::load(path) {
+            Ok(isa) => assembler.set_isa(isa),
+            Err(err) => {
+                eprintln!("Failed to load ISA:\n\t{}", err);
+                process::exit(1);
+            }
+        }
+    }
     if args.debug {
-        println!("Debug mode enabled.");
+        eprintln!("Debug mode enabled.");
         if args.pretty {
-            println!("ASCII binary would be prettified.");
+            eprintln!("ASCII binary would be prettified.");
         }
     }
 
-    let file = File::open(&input_filename).expect("Failed to open file");
-    println!("Assembly file: {}", input_filename);
     let mut assembly_program = String::new();
-    let mut reader = BufReader::new(file);
-    reader
-        .read_to_string(&mut assembly_program)
-        .expect("Failed to read file");
+    let result = if input_filename == STDIO {
+        io::stdin().read_to_string(&mut assembly_program)
+    } else {
+        File::open(&input_filename)
+            .and_then(|file| BufReader::new(file).read_to_string(&mut assembly_program))
+    };
+    if let Err(err) = result {
+        eprintln!("Failed to read {}:\n\t{}", input_filename, err);
+        process::exit(1);
+    }
+    eprintln!("Assembly file: {}", input_filename);
+    eprintln!("Assembling...");
 
-    match assembler.assemble(assembly_program.as_str()) {
-        Ok((binary, mut delimiter_table)) => match Writer::new(args.debug, args.pretty) {
-            Ok(mut writer) => writer.write(binary, &mut delimiter_table).unwrap(),
-            Err(err) => {
-                println!("Failed to create writer:\n\t{}", err);
+    // includes of a program read from stdin are searched from the current directory
+    let source_name = if input_filename == STDIO {
+        ""
+    } else {
+        input_filename.as_str()
+    };
+    if args.object {
+        let result = assembler
+            .assemble_object(source_name, assembly_program.as_str())
+            .map_err(|err| format!("Failed to assemble:\n{}", err))
+            .and_then(|object| {
+                write_object(output, &object)
+                    .map_err(|err| format!("Failed to write {}:\n\t{}", output, err))
+            });
+        if let Err(err) = result {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+        write_extras(&args, &assembler);
+        return;
+    }
+    match assembler.assemble_source(source_name, assembly_program.as_str()) {
+        Ok((mut container, mut delimiter_table)) => {
+            if args.strip {
+                container.symbols = None;
+                container.debug = None;
+            }
+            let result = Writer::new(output, args.debug, args.pretty)
+                .and_then(|mut writer| writer.write(&container, &mut delimiter_table));
+            if let Err(err) = result {
+                eprintln!("Failed to write {}:\n\t{}", output, err);
                 process::exit(1);
             }
-        },
+            write_extras(&args, &assembler);
+        }
         Err(err) => {
-            println!("Failed to assemble:\n{}", err);
+            eprintln!("Failed to assemble:\n{}", err);
             process::exit(1);
         }
     };
 }
+
+// the symbol file and the listing, for executables and objects alike
+fn write_extras(args: &Args, assembler: &MyAssembler) {
+    if let Some(symbols) = &args.symbols
+        && let Err(err) = write_symbols(symbols, assembler.symbols())
+    {
+        eprintln!("Failed to write symbols:\n\t{}", err);
+        process::exit(1);
+    }
+    if let Some(listing) = &args.listing
+        && let Err(err) = write_listing(listing, assembler.listing())
+    {
+        eprintln!("Failed to write listing:\n\t{}", err);
+        process::exit(1);
+    }
+}
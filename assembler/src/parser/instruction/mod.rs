@@ -19,6 +19,22 @@ pub struct Statement {
     pub operands: Option<Vec<StatementField>>,
 }
 
+// the statement as it would be written, without comments or extra whitespace
+impl std::fmt::Display for Statement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(label) = &self.label {
+            write!(f, "{}: ", label.value)?;
+        }
+        if let Some(operation_name) = &self.operation_name {
+            write!(f, "{}", operation_name.value)?;
+        }
+        for (i, operand) in self.operands.iter().flatten().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, operand.value)?;
+        }
+        Ok(())
+    }
+}
+
 impl Statement {
     pub fn new() -> Self {
         Self {
@@ -43,6 +59,17 @@ impl Statement {
             self.operands = Some(vec![StatementField { value, loc }]);
         }
     }
+
+    // appends the next token of an operand written as an expression
+    pub fn extend_operand(&mut self, value: &str) {
+        if let Some(operand) = self
+            .operands
+            .as_mut()
+            .and_then(|operands| operands.last_mut())
+        {
+            operand.value.push_str(value);
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Default)]
@@ -56,4 +83,22 @@ pub struct Instruction {
     pub opcode: InstructionField,
     pub operands: Option<Vec<InstructionField>>,
     pub size: u32,
+    // where the instruction came from and where it lands, for the listing
+    pub line: u32,
+    pub address: u32,
+    pub labels: Vec<String>,
+    // the statement listed under `line` when it comes from a macro call or an included file
+    pub expansion: Option<String>,
+}
+
+// bytes a data directive places into the initialized data memory image
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DataDirective {
+    pub line: u32,
+    pub address: u32,
+    pub bytes: Vec<u8>,
+    // instructions emitted before this directive, to interleave the two in the listing
+    pub position: usize,
+    // the statement listed under `line` when it comes from a macro call or an included file
+    pub expansion: Option<String>,
 }
@@ -2,13 +2,19 @@ pub mod instruction;
 mod semantic_parser;
 mod syntactic_parser;
 
+use container::{Relocation, Section};
+use isa::OptSpec;
+use std::collections::{BTreeMap, HashMap};
+
 use crate::{
     lexer::token::TokenStream,
     parser::{
-        instruction::Instruction,
+        instruction::{DataDirective, Instruction},
         semantic_parser::{SemanticError, SemanticParser},
         syntactic_parser::{SyntacticError, SyntacticParser},
     },
+    render_error::Located,
+    source_map::SourceMap,
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -32,21 +38,85 @@ impl Parser {
         }
     }
 
+    pub fn symbols(&self) -> &HashMap<String, u32> {
+        self.semantic_parser.symbols()
+    }
+
+    pub fn data_symbols(&self) -> &HashMap<String, u32> {
+        self.semantic_parser.data_symbols()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        self.semantic_parser.data()
+    }
+
+    pub fn directives(&self) -> &[DataDirective] {
+        self.semantic_parser.directives()
+    }
+
+    pub fn set_relocatable(&mut self, relocatable: bool) {
+        self.semantic_parser.set_relocatable(relocatable);
+    }
+
+    pub fn set_isa(&mut self, optspec: OptSpec) {
+        self.semantic_parser.set_isa(optspec);
+    }
+
+    pub fn exports(&self) -> BTreeMap<String, (Section, u32)> {
+        self.semantic_parser.exports()
+    }
+
+    pub fn externs(&self) -> Vec<String> {
+        self.semantic_parser.externs()
+    }
+
+    pub fn relocations(&self) -> &[Relocation] {
+        self.semantic_parser.relocations()
+    }
+
+    pub fn definitions(&self) -> &HashMap<String, u32> {
+        self.semantic_parser.definitions()
+    }
+
+    pub fn references(&self) -> &HashMap<String, Vec<u32>> {
+        self.semantic_parser.references()
+    }
+
+    pub fn constant_values(&self) -> &HashMap<String, i64> {
+        self.semantic_parser.constant_values()
+    }
+
     pub fn parse(
         &mut self,
         tokens: TokenStream,
-        source_lines: &Vec<String>,
-    ) -> Result<Vec<Instruction>, ParserError> {
-        let statements = self.syntactic_parser.parse(tokens, source_lines)?;
-        let instructions = self.semantic_parser.parse(statements, source_lines)?;
-        Ok(instructions)
+        sources: &SourceMap,
+    ) -> Result<Vec<Instruction>, Vec<Located<ParserError>>> {
+        // lines with a syntax error are dropped, the rest still gets checked
+        let (statements, syntactic_errors) = self.syntactic_parser.parse(tokens, sources);
+        let mut errors: Vec<Located<ParserError>> = syntactic_errors
+            .into_iter()
+            .map(|(loc, error)| (loc, error.into()))
+            .collect();
+        match self.semantic_parser.parse(statements, sources) {
+            Ok(instructions) if errors.is_empty() => return Ok(instructions),
+            Ok(_) => {}
+            Err(semantic_errors) => errors.extend(
+                semantic_errors
+                    .into_iter()
+                    .map(|(loc, error)| (loc, error.into())),
+            ),
+        }
+        Err(errors)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::{
-        super::lexer::token::{SourceLoc, Token, TokenStream, TokenType},
+        super::{
+            lexer::token::{SourceLoc, Token, TokenStream, TokenType},
+            source_map::SourceMap,
+        },
         Parser,
         instruction::InstructionField,
     };
@@ -58,52 +128,86 @@ mod tests {
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("MOVE".to_string()),
-            source_loc: SourceLoc { line: 1, column: 1 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 1,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Symbol,
             value: Some(":".to_string()),
-            source_loc: SourceLoc { line: 1, column: 6 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 6,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Newline,
             value: None,
-            source_loc: SourceLoc { line: 1, column: 7 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 7,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("MOVER".to_string()),
-            source_loc: SourceLoc { line: 2, column: 1 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 2,
+                column: 1,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("R0".to_string()),
-            source_loc: SourceLoc { line: 2, column: 7 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 2,
+                column: 7,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Symbol,
             value: Some(",".to_string()),
-            source_loc: SourceLoc { line: 2, column: 9 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 2,
+                column: 9,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("0".to_string()),
             source_loc: SourceLoc {
+                file: 0,
                 line: 2,
                 column: 11,
+                expansion: None,
             },
         });
         tokens.push(Token {
             token_type: TokenType::Eof,
             value: None,
             source_loc: SourceLoc {
+                file: 0,
                 line: 2,
                 column: 12,
+                expansion: None,
             },
         });
         let mut parser = Parser::new();
-        let source_lines = ["", ""].map(|s| s.to_string()).to_vec();
-        let instructions = parser.parse(tokens, &source_lines).unwrap();
+        let sources = SourceMap::from_program("\n");
+        let instructions = parser.parse(tokens, &sources).unwrap();
         assert_eq!(instructions.len(), 1);
         let instruction = &instructions[0];
         assert_eq!(
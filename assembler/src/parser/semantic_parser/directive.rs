@@ -0,0 +1,276 @@
+use isa::{OperandType, STACK_RESERVE};
+
+use super::{
+    super::{
+        super::{
+            render_error::{Diagnostic, render_error},
+            source_map::SourceMap,
+        },
+        instruction::{DataDirective, Statement, StatementField},
+    },
+    SemanticError, SemanticParser, Value,
+    expression::{is_expression, is_symbol_name},
+    literal::{parse_integer, unescape},
+};
+
+pub fn is_directive(operation_name: &str) -> bool {
+    matches!(
+        operation_name,
+        "DB" | "DW" | "DS" | "RESB" | "ASCII" | "ASCIIZ"
+    )
+}
+
+// GLOBAL exports labels to other object files, EXTERN imports theirs
+pub fn is_linkage(operation_name: &str) -> bool {
+    matches!(operation_name, "GLOBAL" | "EXTERN")
+}
+
+fn directive_error(
+    token: &StatementField,
+    headline: String,
+    help: Option<&str>,
+    sources: &SourceMap,
+) -> SemanticError {
+    SemanticError::Directive {
+        message: render_error(Diagnostic {
+            headline,
+            loc: token.loc,
+            sources,
+            help,
+        }),
+    }
+}
+
+// a double quoted string, see `unescape` for the escapes it may use
+fn parse_string(token: &StatementField, sources: &SourceMap) -> Result<Vec<u8>, SemanticError> {
+    let inner = token
+        .value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .filter(|_| token.value.len() >= 2)
+        .ok_or_else(|| {
+            directive_error(
+                token,
+                format!("Token '{}' is not a string", token.value),
+                Some("Strings are written in double quotes, e.g. \"hello\""),
+                sources,
+            )
+        })?;
+    unescape(inner).map_err(|message| directive_error(token, message, None, sources))
+}
+
+impl SemanticParser {
+    // data is laid out as it is seen, so symbols in directive operands must already be defined
+    fn parse_number(
+        &mut self,
+        token: &StatementField,
+        min: i64,
+        max: i64,
+        sources: &SourceMap,
+    ) -> Result<i64, SemanticError> {
+        let value = match parse_integer(&token.value) {
+            Ok(value) => value,
+            Err(_) if is_expression(&token.value) || token.value.starts_with('\'') => {
+                match self.evaluate(token, &OperandType::Constant, sources, &mut vec![])? {
+                    Value::Known(value) if self.relocatable => {
+                        if self
+                            .relocation_target(token, &OperandType::Constant, value, sources)?
+                            .is_some()
+                        {
+                            return Err(directive_error(
+                                token,
+                                format!("'{}' depends on where the object is linked", token.value),
+                                Some("Data directives in object files can only use constants"),
+                                sources,
+                            ));
+                        }
+                        value
+                    }
+                    Value::Known(value) => value,
+                    Value::Undefined(name) => {
+                        return Err(directive_error(
+                            token,
+                            format!("Symbol '{}' is not defined yet", name),
+                            Some("Symbols used by data directives must be defined above them"),
+                            sources,
+                        ));
+                    }
+                }
+            }
+            Err(_) => {
+                return Err(directive_error(
+                    token,
+                    format!("Token '{}' is not a number", token.value),
+                    None,
+                    sources,
+                ));
+            }
+        };
+        if !(min..=max).contains(&value) {
+            return Err(directive_error(
+                token,
+                format!("Value {} is out of range", value),
+                Some(format!("Expected a value between {} and {}", min, max).as_str()),
+                sources,
+            ));
+        }
+        Ok(value)
+    }
+
+    // `NAME EQU expr`, the expression is kept and evaluated wherever NAME is used
+    pub fn analyze_equ(
+        &self,
+        statement: &Statement,
+        sources: &SourceMap,
+    ) -> Result<StatementField, SemanticError> {
+        let operation_name = statement.operation_name.as_ref().unwrap();
+        if statement.label.is_none() {
+            return Err(directive_error(
+                operation_name,
+                "EQU without a name".to_string(),
+                Some("Constants are defined as NAME EQU expression"),
+                sources,
+            ));
+        }
+        match statement.operands.as_deref() {
+            Some([expression]) => Ok(expression.clone()),
+            _ => Err(directive_error(
+                operation_name,
+                "Wrong number of operands for EQU".to_string(),
+                Some("EQU takes exactly one expression"),
+                sources,
+            )),
+        }
+    }
+
+    // `GLOBAL NAME, ...` and `EXTERN NAME, ...`, resolved once every statement is seen
+    pub fn analyze_linkage(
+        &mut self,
+        operation_name: &StatementField,
+        operands: &[StatementField],
+        sources: &SourceMap,
+    ) -> Result<(), SemanticError> {
+        if operands.is_empty() {
+            return Err(directive_error(
+                operation_name,
+                format!("Wrong number of operands for {}", operation_name.value),
+                Some("This directive takes one or more comma separated symbols"),
+                sources,
+            ));
+        }
+        for operand in operands {
+            if !is_symbol_name(&operand.value) {
+                return Err(directive_error(
+                    operand,
+                    format!("Token '{}' is not a symbol", operand.value),
+                    None,
+                    sources,
+                ));
+            }
+            let names = match operation_name.value.as_str() {
+                "GLOBAL" => &mut self.globals,
+                _ => &mut self.externs,
+            };
+            if !names.iter().any(|name| name.value == operand.value) {
+                names.push(operand.clone());
+            }
+        }
+        Ok(())
+    }
+
+    // appends the directive's bytes to the data image, the address is where they start. A
+    // directive that would reach into the reserved cells is placed after them as a whole
+    pub fn analyze_directive(
+        &mut self,
+        operation_name: &StatementField,
+        operands: &[StatementField],
+        sources: &SourceMap,
+    ) -> Result<DataDirective, SemanticError> {
+        let expect_operands = |count: Option<usize>| -> Result<(), SemanticError> {
+            let valid = match count {
+                Some(count) => operands.len() == count,
+                None => !operands.is_empty(),
+            };
+            if valid {
+                return Ok(());
+            }
+            Err(directive_error(
+                operation_name,
+                format!("Wrong number of operands for {}", operation_name.value),
+                Some(match count {
+                    Some(_) => "This directive takes exactly one operand",
+                    None => "This directive takes one or more comma separated operands",
+                }),
+                sources,
+            ))
+        };
+
+        let mut bytes = Vec::new();
+        match operation_name.value.as_str() {
+            "DB" => {
+                expect_operands(None)?;
+                for operand in operands {
+                    if operand.value.starts_with('"') {
+                        bytes.extend(parse_string(operand, sources)?);
+                    } else {
+                        bytes.push(self.parse_number(operand, -128, 255, sources)? as u8);
+                    }
+                }
+            }
+            "DW" => {
+                expect_operands(None)?;
+                for operand in operands {
+                    let word = self.parse_number(operand, -32768, 65535, sources)? as u16;
+                    bytes.extend(word.to_be_bytes());
+                }
+            }
+            "DS" | "RESB" => {
+                expect_operands(Some(1))?;
+                let size = self.optspec.data_memory_size as i64;
+                let count = self.parse_number(&operands[0], 0, size, sources)?;
+                bytes.resize(count as usize, 0);
+            }
+            "ASCII" | "ASCIIZ" => {
+                expect_operands(None)?;
+                for operand in operands {
+                    bytes.extend(parse_string(operand, sources)?);
+                }
+                if operation_name.value == "ASCIIZ" {
+                    bytes.push(0);
+                }
+            }
+            _ => unreachable!("not a directive: {}", operation_name.value),
+        }
+
+        let mut address = self.data.len() as u32;
+        let reserved = self.optspec.reserved_data();
+        if address < reserved.end && address + bytes.len() as u32 > reserved.start {
+            address = reserved.end;
+        }
+        let end = address + bytes.len() as u32;
+        let size = self.optspec.data_memory_size;
+        if end > size - STACK_RESERVE {
+            return Err(directive_error(
+                operation_name,
+                "Data does not fit in data memory".to_string(),
+                Some(
+                    format!(
+                        "Data memory holds {} bytes and the top {} are kept for the stack, this directive would end at byte {}",
+                        size, STACK_RESERVE, end
+                    )
+                    .as_str(),
+                ),
+                sources,
+            ));
+        }
+        self.data.resize(address as usize, 0);
+        self.data.extend(&bytes);
+        Ok(DataDirective {
+            line: sources.main_line(&operation_name.loc),
+            address,
+            bytes,
+            position: self.instruction_counter,
+            expansion: None,
+        })
+    }
+}
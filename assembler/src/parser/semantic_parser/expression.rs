@@ -0,0 +1,325 @@
+// assemble-time expressions: numbers and symbols combined with + - * / << >> & |,
+// parentheses and the HIGH()/LOW() byte selectors, with C precedence
+
+use super::literal::{parse_char, parse_integer};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    ShiftLeft,
+    ShiftRight,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expression {
+    Number(i64),
+    Symbol(String),
+    Negate(Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+    High(Box<Expression>),
+    Low(Box<Expression>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+}
+
+// lowest to highest precedence, all left associative
+const LEVELS: [&[(&str, Operator)]; 5] = [
+    &[("|", Operator::Or)],
+    &[("&", Operator::And)],
+    &[("<<", Operator::ShiftLeft), (">>", Operator::ShiftRight)],
+    &[("+", Operator::Add), ("-", Operator::Subtract)],
+    &[("*", Operator::Multiply), ("/", Operator::Divide)],
+];
+
+pub fn is_symbol_name(value: &str) -> bool {
+    let mut chars = value.chars();
+    let starts_like_name = chars
+        .next()
+        .is_some_and(|char| char.is_ascii_alphabetic() || char == '_');
+    // register operands share the shape of names
+    let is_register = value.len() > 1
+        && value.starts_with('R')
+        && value[1..].chars().all(|char| char.is_ascii_digit());
+    starts_like_name
+        && chars.all(|char| char.is_ascii_alphanumeric() || char == '_')
+        && !is_register
+}
+
+// operands that have to go through the expression evaluator instead of the literal regexes
+pub fn is_expression(value: &str) -> bool {
+    is_symbol_name(value) || value.chars().any(|char| "+-*/<>&|()".contains(char))
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let char = chars[i];
+        if char.is_whitespace() {
+            i += 1;
+            continue;
+        }
+        if char.is_ascii_alphanumeric() || char == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            let word: String = chars[start..i].iter().collect();
+            tokens.push(if char.is_ascii_digit() {
+                Token::Number(parse_integer(&word)?)
+            } else {
+                Token::Name(word)
+            });
+            continue;
+        }
+        if char == '\'' {
+            let start = i;
+            i += 1;
+            while i < chars.len() && chars[i] != '\'' {
+                i += if chars[i] == '\\' { 2 } else { 1 };
+            }
+            let literal: String = chars[start..(i + 1).min(chars.len())].iter().collect();
+            tokens.push(Token::Number(parse_char(&literal)?));
+            i += 1;
+            continue;
+        }
+        let operator = match (char, chars.get(i + 1)) {
+            ('<', Some('<')) => "<<",
+            ('>', Some('>')) => ">>",
+            ('+', _) => "+",
+            ('-', _) => "-",
+            ('*', _) => "*",
+            ('/', _) => "/",
+            ('&', _) => "&",
+            ('|', _) => "|",
+            ('(', _) => "(",
+            (')', _) => ")",
+            _ => return Err(format!("Unexpected character '{}' in expression", char)),
+        };
+        i += operator.len();
+        tokens.push(Token::Operator(operator));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    index: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.index)
+    }
+
+    fn eat(&mut self, operator: &'static str) -> bool {
+        if self.peek() == Some(&Token::Operator(operator)) {
+            self.index += 1;
+            return true;
+        }
+        false
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expression, String> {
+        if level == LEVELS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        while let Some(Token::Operator(symbol)) = self.peek() {
+            let Some((_, operator)) = LEVELS[level].iter().find(|(s, _)| s == symbol) else {
+                break;
+            };
+            self.index += 1;
+            let right = self.binary(level + 1)?;
+            left = Expression::Binary(*operator, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        if self.eat("-") {
+            return Ok(Expression::Negate(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expression, String> {
+        let token = self.peek().cloned();
+        self.index += 1;
+        match token {
+            Some(Token::Number(value)) => Ok(Expression::Number(value)),
+            Some(Token::Name(name)) if self.peek() == Some(&Token::Operator("(")) => {
+                let selector = match name.as_str() {
+                    "HIGH" => Expression::High,
+                    "LOW" => Expression::Low,
+                    _ => return Err(format!("Unknown function '{}'", name)),
+                };
+                self.index += 1;
+                let argument = self.binary(0)?;
+                self.close()?;
+                Ok(selector(Box::new(argument)))
+            }
+            Some(Token::Name(name)) => Ok(Expression::Symbol(name)),
+            Some(Token::Operator("(")) => {
+                let inner = self.binary(0)?;
+                self.close()?;
+                Ok(inner)
+            }
+            Some(Token::Operator(operator)) => Err(format!(
+                "Expected a number, a symbol or '(' but found '{}'",
+                operator
+            )),
+            None => Err("Expression ends unexpectedly".to_string()),
+        }
+    }
+
+    fn close(&mut self) -> Result<(), String> {
+        if self.eat(")") {
+            return Ok(());
+        }
+        Err("Missing closing parenthesis".to_string())
+    }
+}
+
+impl Expression {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?,
+            index: 0,
+        };
+        let expression = parser.binary(0)?;
+        match parser.peek() {
+            None => Ok(expression),
+            Some(Token::Operator(operator)) => Err(format!("Unexpected '{}'", operator)),
+            Some(Token::Number(value)) => Err(format!("Unexpected number {}", value)),
+            Some(Token::Name(name)) => Err(format!("Unexpected symbol '{}'", name)),
+        }
+    }
+
+    // every symbol the expression uses, once each, in order of appearance
+    pub fn symbols(&self) -> Vec<&str> {
+        let mut symbols = Vec::new();
+        self.collect_symbols(&mut symbols);
+        symbols
+    }
+
+    fn collect_symbols<'a>(&'a self, symbols: &mut Vec<&'a str>) {
+        match self {
+            Expression::Number(_) => {}
+            Expression::Symbol(name) => {
+                if !symbols.contains(&name.as_str()) {
+                    symbols.push(name);
+                }
+            }
+            Expression::Negate(inner) | Expression::High(inner) | Expression::Low(inner) => {
+                inner.collect_symbols(symbols)
+            }
+            Expression::Binary(_, left, right) => {
+                left.collect_symbols(symbols);
+                right.collect_symbols(symbols);
+            }
+        }
+    }
+
+    pub fn evaluate(&self, value_of: &impl Fn(&str) -> i64) -> Result<i64, String> {
+        let overflow = || "Expression overflows".to_string();
+        Ok(match self {
+            Expression::Number(value) => *value,
+            Expression::Symbol(name) => value_of(name),
+            Expression::Negate(inner) => inner
+                .evaluate(value_of)?
+                .checked_neg()
+                .ok_or_else(overflow)?,
+            Expression::High(inner) => (inner.evaluate(value_of)? >> 8) & 0xFF,
+            Expression::Low(inner) => inner.evaluate(value_of)? & 0xFF,
+            Expression::Binary(operator, left, right) => {
+                let left = left.evaluate(value_of)?;
+                let right = right.evaluate(value_of)?;
+                let shift = || {
+                    u32::try_from(right)
+                        .ok()
+                        .filter(|amount| *amount < 64)
+                        .ok_or_else(|| format!("Cannot shift by {}", right))
+                };
+                match operator {
+                    Operator::Add => left.checked_add(right).ok_or_else(overflow)?,
+                    Operator::Subtract => left.checked_sub(right).ok_or_else(overflow)?,
+                    Operator::Multiply => left.checked_mul(right).ok_or_else(overflow)?,
+                    Operator::Divide if right == 0 => return Err("Division by zero".to_string()),
+                    Operator::Divide => left.checked_div(right).ok_or_else(overflow)?,
+                    Operator::ShiftLeft => left.checked_shl(shift()?).ok_or_else(overflow)?,
+                    Operator::ShiftRight => left >> shift()?,
+                    Operator::And => left & right,
+                    Operator::Or => left | right,
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(text: &str) -> Result<i64, String> {
+        Expression::parse(text)?.evaluate(&|name| match name {
+            "TABLE" => 4,
+            "BIG" => 0x1234,
+            _ => unreachable!(),
+        })
+    }
+
+    #[test]
+    fn test_precedence() {
+        assert_eq!(evaluate("1+2*3"), Ok(7));
+        assert_eq!(evaluate("(1+2)*3"), Ok(9));
+        assert_eq!(evaluate("1<<2+1"), Ok(8));
+        assert_eq!(evaluate("6&3|8"), Ok(10));
+        assert_eq!(evaluate("10-4-3"), Ok(3));
+        assert_eq!(evaluate("--2*-3"), Ok(-6));
+        assert_eq!(evaluate("TABLE+1"), Ok(5));
+        assert_eq!(evaluate("HIGH(BIG)"), Ok(0x12));
+        assert_eq!(evaluate("LOW(BIG)+1"), Ok(0x35));
+        assert_eq!(evaluate("0x10|0b11"), Ok(19));
+        assert_eq!(evaluate("'a'-'A'"), Ok(32));
+        assert_eq!(evaluate("'+'+' '"), Ok(75));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(evaluate("1/0"), Err("Division by zero".to_string()));
+        assert_eq!(
+            evaluate("(1+2"),
+            Err("Missing closing parenthesis".to_string())
+        );
+        assert_eq!(
+            evaluate("1+"),
+            Err("Expression ends unexpectedly".to_string())
+        );
+        assert_eq!(
+            evaluate("MID(1)"),
+            Err("Unknown function 'MID'".to_string())
+        );
+        assert_eq!(evaluate("1 2"), Err("Unexpected number 2".to_string()));
+        assert!(evaluate("1<<64").is_err());
+    }
+
+    #[test]
+    fn test_symbols() {
+        let expression = Expression::parse("HIGH(TABLE)+TABLE*COUNT").unwrap();
+        assert_eq!(expression.symbols(), vec!["TABLE", "COUNT"]);
+        assert!(is_expression("COUNT") && is_expression("TABLE+1") && is_expression("-(1)"));
+        assert!(!is_expression("R1") && !is_expression("12"));
+    }
+}
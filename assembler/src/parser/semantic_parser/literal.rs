@@ -0,0 +1,99 @@
+// numeric, character and string literals shared by operands and data directives
+
+// decimal, 0x hexadecimal or 0b binary
+pub fn parse_integer(text: &str) -> Result<i64, String> {
+    let (digits, radix) = match text.get(..2) {
+        Some("0x" | "0X") => (&text[2..], 16),
+        Some("0b" | "0B") => (&text[2..], 2),
+        _ => (text, 10),
+    };
+    let signed = digits.starts_with(['+', '-']);
+    i64::from_str_radix(digits, radix)
+        .ok()
+        .filter(|_| radix == 10 || !signed)
+        .ok_or_else(|| match radix {
+            16 => format!("'{}' is not a valid hexadecimal number", text),
+            2 => format!("'{}' is not a valid binary number", text),
+            _ => format!("'{}' is not a valid number", text),
+        })
+}
+
+// the bytes between the quotes of a char or string literal, ASCII only, with
+// \n \t \r \0 \\ \' \" and \xHH escapes
+pub fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    let mut chars = text.chars();
+    while let Some(char) = chars.next() {
+        let char = if char == '\\' {
+            match chars.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some('\\') => '\\',
+                Some('\'') => '\'',
+                Some('"') => '"',
+                Some('x') => {
+                    let digits: String = chars.by_ref().take(2).collect();
+                    match u8::from_str_radix(&digits, 16) {
+                        Ok(byte) if digits.len() == 2 => {
+                            bytes.push(byte);
+                            continue;
+                        }
+                        _ => return Err(format!("Invalid escape sequence '\\x{}'", digits)),
+                    }
+                }
+                other => {
+                    return Err(format!(
+                        "Unknown escape sequence '\\{}'",
+                        other.map(String::from).unwrap_or_default()
+                    ));
+                }
+            }
+        } else {
+            char
+        };
+        if !char.is_ascii() {
+            return Err(format!("Character '{}' is not ASCII", char));
+        }
+        bytes.push(char as u8);
+    }
+    Ok(bytes)
+}
+
+// 'A' stands for the byte value of the character
+pub fn parse_char(text: &str) -> Result<i64, String> {
+    let inner = text
+        .strip_prefix('\'')
+        .and_then(|text| text.strip_suffix('\''))
+        .filter(|_| text.len() >= 2)
+        .ok_or_else(|| format!("'{}' is not a character literal", text))?;
+    match unescape(inner)?.as_slice() {
+        [byte] => Ok(*byte as i64),
+        _ => Err(format!(
+            "Character literal {} must hold exactly one character",
+            text
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_literals() {
+        assert_eq!(parse_integer("0x1F"), Ok(31));
+        assert_eq!(parse_integer("0b1010"), Ok(10));
+        assert_eq!(parse_integer("42"), Ok(42));
+        assert!(parse_integer("0x1G").is_err());
+        assert_eq!(parse_char("'A'"), Ok(65));
+        assert_eq!(parse_char("'\\n'"), Ok(10));
+        assert_eq!(parse_char("'\\''"), Ok(39));
+        assert_eq!(parse_char("'\\x7F'"), Ok(127));
+        assert!(parse_char("'AB'").is_err());
+        assert_eq!(unescape("a\\tb\\\"").unwrap(), b"a\tb\"".to_vec());
+        assert!(unescape("\\q").is_err());
+        assert!(unescape("é").is_err());
+    }
+}
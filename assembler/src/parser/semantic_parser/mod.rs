@@ -1,10 +1,24 @@
-use isa::{OperandSpec, OperandType, OptSpec};
+mod directive;
+mod expression;
+mod literal;
+
+use container::{Relocation, Section, Target};
+use isa::{OperandSpec, OperandType, Operation, OptSpec};
 use regex::Regex;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
+use self::{
+    directive::{is_directive, is_linkage},
+    expression::{Expression, is_expression},
+    literal::parse_integer,
+};
 use super::{
-    super::render_error::{Diagnostic, render_error},
-    instruction::{Instruction, InstructionField, Statement, StatementField},
+    super::{
+        lexer::token::SourceLoc,
+        render_error::{Diagnostic, Located, render_error},
+        source_map::SourceMap,
+    },
+    instruction::{DataDirective, Instruction, InstructionField, Statement, StatementField},
 };
 
 #[derive(Debug, thiserror::Error)]
@@ -19,25 +33,111 @@ pub enum SemanticError {
     UnknownOperation { message: String },
     #[error("Unable to parse the token as an integer: {0}")]
     ParseInt(String),
-    #[error("Unable to parse the token as a signed 8 bit integer: {0}")]
-    NotI8(String),
     #[error("Label {0} already in use")]
     LabelAlreadyInUse(String),
     #[error("{message}")]
     UndefinedLabel { message: String },
+    #[error("{message}")]
+    LabelKind { message: String },
+    #[error("{message}")]
+    OutOfRange { message: String },
+    #[error("{message}")]
+    Directive { message: String },
+    #[error("{message}")]
+    Expression { message: String },
+    #[error("{message}")]
+    Linkage { message: String },
+    #[error("{message}")]
+    ProgramTooLarge { message: String },
 }
 
+// an operand using a symbol that wasn't defined yet, resolved once every statement is seen
 struct TiiEntry {
     instruction_number: usize,
     operand_number: usize,
+    token: StatementField,
+    operand_type: OperandType,
+    bit_count: u8,
 }
 
+enum Value {
+    Known(i64),
+    Undefined(String),
+}
+
+// how far `relocation_target` moves a section or an EXTERN, larger than any address. The
+// second probe has a one in every nibble, so LOW(), HIGH(), masks and shifts that hide the
+// first one still change the value
+const SHIFTS: [i64; 2] = [1 << 20, (1 << 21) + 0x11111];
+
 pub struct SemanticParser {
     optspec: OptSpec,
     symtab: HashMap<String, u32>,
-    tii: HashMap<StatementField, Vec<TiiEntry>>,
+    // labels of data directives, by byte address in data memory
+    data_symbols: HashMap<String, u32>,
+    // label -> line it is defined on, and the lines it is used on
+    definitions: HashMap<String, u32>,
+    references: HashMap<String, Vec<u32>>,
+    // EQU constants, evaluated whenever they are used so they may refer to later labels
+    constants: HashMap<String, StatementField>,
+    // what each constant evaluated to once every label was known, for the listing
+    constant_values: HashMap<String, i64>,
+    tii: Vec<TiiEntry>,
+    data: Vec<u8>,
+    directives: Vec<DataDirective>,
     location_counter: u32,
     instruction_counter: usize,
+    // object files keep symbol operands open and describe them as relocations instead
+    relocatable: bool,
+    globals: Vec<StatementField>,
+    externs: Vec<StatementField>,
+    relocations: Vec<Relocation>,
+    // the section or EXTERN that evaluation currently moves, and by how much
+    shift: Option<(Target, i64)>,
+}
+
+fn expression_error(
+    token: &StatementField,
+    headline: String,
+    sources: &SourceMap,
+) -> SemanticError {
+    SemanticError::Expression {
+        message: render_error(Diagnostic {
+            headline,
+            loc: token.loc,
+            sources,
+            help: None,
+        }),
+    }
+}
+
+// fits an evaluated operand into its field, constants may be negative and use two's complement
+fn encode(
+    token: &StatementField,
+    value: i64,
+    operand_type: &OperandType,
+    bit_count: u8,
+    sources: &SourceMap,
+) -> Result<u32, SemanticError> {
+    let max = (1i64 << bit_count) - 1;
+    let min = match operand_type {
+        OperandType::Constant => -(1i64 << (bit_count - 1)),
+        _ => 0,
+    };
+    if (min..=max).contains(&value) {
+        return Ok((value & max) as u32);
+    }
+    Err(SemanticError::OutOfRange {
+        message: render_error(Diagnostic {
+            headline: format!(
+                "'{}' evaluates to {}, which does not fit in a {} bit operand",
+                token.value, value, bit_count
+            ),
+            loc: token.loc,
+            sources,
+            help: Some(format!("Expected a value between {} and {}", min, max).as_str()),
+        }),
+    })
 }
 
 impl SemanticParser {
@@ -45,12 +145,84 @@ impl SemanticParser {
         Self {
             optspec: OptSpec::clone(),
             symtab: HashMap::new(),
-            tii: HashMap::new(),
+            data_symbols: HashMap::new(),
+            definitions: HashMap::new(),
+            references: HashMap::new(),
+            constants: HashMap::new(),
+            constant_values: HashMap::new(),
+            tii: Vec::new(),
+            data: Vec::new(),
+            directives: Vec::new(),
             location_counter: 0,
             instruction_counter: 0,
+            relocatable: false,
+            globals: Vec::new(),
+            externs: Vec::new(),
+            relocations: Vec::new(),
+            shift: None,
         }
     }
 
+    pub fn set_relocatable(&mut self, relocatable: bool) {
+        self.relocatable = relocatable;
+    }
+
+    pub fn set_isa(&mut self, optspec: OptSpec) {
+        self.optspec = optspec;
+    }
+
+    // GLOBAL labels with the section they are in, checked by `check_linkage`
+    pub fn exports(&self) -> BTreeMap<String, (Section, u32)> {
+        self.globals
+            .iter()
+            .filter_map(|global| {
+                let name = global.value.clone();
+                match (self.symtab.get(&name), self.data_symbols.get(&name)) {
+                    (Some(address), _) => Some((name, (Section::Code, *address))),
+                    (_, Some(address)) => Some((name, (Section::Data, *address))),
+                    _ => None,
+                }
+            })
+            .collect()
+    }
+
+    pub fn externs(&self) -> Vec<String> {
+        self.externs.iter().map(|name| name.value.clone()).collect()
+    }
+
+    pub fn relocations(&self) -> &[Relocation] {
+        &self.relocations
+    }
+
+    pub fn symbols(&self) -> &HashMap<String, u32> {
+        &self.symtab
+    }
+
+    pub fn data_symbols(&self) -> &HashMap<String, u32> {
+        &self.data_symbols
+    }
+
+    // the initialized data memory image
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn directives(&self) -> &[DataDirective] {
+        &self.directives
+    }
+
+    pub fn definitions(&self) -> &HashMap<String, u32> {
+        &self.definitions
+    }
+
+    pub fn references(&self) -> &HashMap<String, Vec<u32>> {
+        &self.references
+    }
+
+    pub fn constant_values(&self) -> &HashMap<String, i64> {
+        &self.constant_values
+    }
+
     pub fn normalize(&self, statements: Vec<Statement>) -> Result<Vec<Statement>, SemanticError> {
         statements
             .iter()
@@ -73,13 +245,6 @@ impl SemanticParser {
                                     Some(operands.clone())
                                 }
                             }
-                            "NOT" => {
-                                if operands.len() == 1 {
-                                    Some(vec![operands[0].clone(), operands[0].clone()])
-                                } else {
-                                    Some(operands.clone())
-                                }
-                            }
                             _ => Some(operands.clone()),
                         },
                         None => Some(operands.clone()),
@@ -98,8 +263,22 @@ impl SemanticParser {
         spec: &OperandSpec,
         re: &Regex,
         operand_number: usize,
-        source_lines: &Vec<String>,
+        sources: &SourceMap,
     ) -> Result<InstructionField, SemanticError> {
+        // literals go through the evaluator too, so every value is range checked the same way.
+        // The regexes only accept numbers that fit, a wider one gets the range error
+        let too_wide = parse_integer(&token.value).is_ok_and(|value| {
+            encode(&token, value, &spec.operand_type, spec.bit_count, sources).is_err()
+        });
+        let literal =
+            spec.operand_type != OperandType::Label && (re.is_match(&token.value) || too_wide);
+        let evaluated = matches!(
+            spec.operand_type,
+            OperandType::Memory | OperandType::Label | OperandType::Constant
+        );
+        if evaluated && (literal || is_expression(&token.value)) {
+            return self.resolve_expression(token, spec, operand_number, sources);
+        }
         match spec.operand_type {
             OperandType::Register => {
                 if !re.is_match(&token.value) {
@@ -109,9 +288,8 @@ impl SemanticParser {
                                 "Token '{}' does not look like a register",
                                 token.value
                             ),
-                            line: token.loc.line,
-                            column: token.loc.column,
-                            source_line: &source_lines[token.loc.line as usize as usize - 1],
+                            loc: token.loc,
+                            sources,
                             help: Some(
                                 format!(
                                     "Register operand must match the regex: {}",
@@ -128,115 +306,470 @@ impl SemanticParser {
                 let bit_count = spec.bit_count;
                 Ok(InstructionField { value, bit_count })
             }
-            OperandType::Constant => {
-                if !re.is_match(&token.value) {
-                    return Err(SemanticError::ShapeDoesNotMatch {
-                        message: render_error(Diagnostic {
-                            headline: format!(
-                                "Token '{}' does not look like a constant",
-                                token.value
-                            ),
-                            line: token.loc.line,
-                            column: token.loc.column,
-                            source_line: &source_lines[token.loc.line as usize - 1],
-                            help: Some(
-                                format!(
-                                    "Constant operand must match the regex: {}",
-                                    spec.operand_regex
-                                )
-                                .as_str(),
-                            ),
-                        }),
-                    });
+            OperandType::Constant => Err(SemanticError::ShapeDoesNotMatch {
+                message: render_error(Diagnostic {
+                    headline: format!("Token '{}' does not look like a constant", token.value),
+                    loc: token.loc,
+                    sources,
+                    help: Some(
+                        format!(
+                            "Constant operand must match the regex: {}",
+                            spec.operand_regex
+                        )
+                        .as_str(),
+                    ),
+                }),
+            }),
+            OperandType::Memory => Err(SemanticError::ShapeDoesNotMatch {
+                message: render_error(Diagnostic {
+                    headline: format!(
+                        "Token '{}' does not look like a memory address",
+                        token.value
+                    ),
+                    loc: token.loc,
+                    sources,
+                    help: Some(
+                        format!(
+                            "Memory operand must match the regex: {}",
+                            spec.operand_regex
+                        )
+                        .as_str(),
+                    ),
+                }),
+            }),
+            OperandType::Label => Err(SemanticError::ShapeDoesNotMatch {
+                message: render_error(Diagnostic {
+                    headline: format!("Token '{}' does not look like a label", token.value),
+                    loc: token.loc,
+                    sources,
+                    help: Some(
+                        format!("Label must match the regex: {}", spec.operand_regex).as_str(),
+                    ),
+                }),
+            }),
+            OperandType::Indirect | OperandType::Indexed | OperandType::PostIncrement => {
+                self.parse_address(token, spec, re, operand_number, sources)
+            }
+        }
+    }
+
+    // `[R1]`, `[R1+4]` and `[R1+]`, the offset of an indexed address is evaluated like a memory
+    // operand and goes above the register number
+    fn parse_address(
+        &mut self,
+        token: StatementField,
+        spec: &OperandSpec,
+        re: &Regex,
+        operand_number: usize,
+        sources: &SourceMap,
+    ) -> Result<InstructionField, SemanticError> {
+        if !re.is_match(&token.value) {
+            let mode = match spec.operand_type {
+                OperandType::Indexed => "an indexed",
+                OperandType::PostIncrement => "a post-increment",
+                _ => "a register-indirect",
+            };
+            return Err(SemanticError::ShapeDoesNotMatch {
+                message: render_error(Diagnostic {
+                    headline: format!(
+                        "Token '{}' does not look like {} address",
+                        token.value, mode
+                    ),
+                    loc: token.loc,
+                    sources,
+                    help: Some(
+                        format!("The operand must match the regex: {}", spec.operand_regex)
+                            .as_str(),
+                    ),
+                }),
+            });
+        }
+        let inside = &token.value[1..token.value.len() - 1];
+        let (register, offset) = inside.split_once('+').unwrap_or((inside, ""));
+        let register: u32 = register[1..]
+            .parse()
+            .map_err(|_| SemanticError::ParseInt(token.to_string()))?;
+        if spec.operand_type != OperandType::Indexed {
+            return Ok(InstructionField {
+                value: register,
+                bit_count: spec.bit_count,
+            });
+        }
+        let register_bits = self.optspec.register_bits();
+        let offset_spec = OperandSpec {
+            operand_type: OperandType::Memory,
+            operand_regex: spec.operand_regex.clone(),
+            bit_count: spec.bit_count - register_bits,
+        };
+        let offset = StatementField {
+            value: offset.to_string(),
+            loc: token.loc,
+        };
+        let offset = self.resolve_expression(offset, &offset_spec, operand_number, sources)?;
+        Ok(InstructionField {
+            value: offset.value << register_bits | register,
+            bit_count: spec.bit_count,
+        })
+    }
+
+    // EQU constants first, then code labels for label operands and data labels for memory
+    // operands, constants may use either kind
+    fn lookup(
+        &self,
+        name: &str,
+        token: &StatementField,
+        operand_type: &OperandType,
+        sources: &SourceMap,
+        visiting: &mut Vec<String>,
+    ) -> Result<Value, SemanticError> {
+        if let Some(definition) = self.constants.get(name) {
+            if visiting.iter().any(|constant| constant == name) {
+                return Err(expression_error(
+                    definition,
+                    format!("Constant '{}' is defined in terms of itself", name),
+                    sources,
+                ));
+            }
+            visiting.push(name.to_string());
+            let value = self.evaluate(definition, &OperandType::Constant, sources, visiting)?;
+            visiting.pop();
+            return Ok(value);
+        }
+        let code = self
+            .symtab
+            .get(name)
+            .map(|address| self.shifted(Target::Section(Section::Code), *address));
+        let data = self
+            .data_symbols
+            .get(name)
+            .map(|address| self.shifted(Target::Section(Section::Data), *address));
+        let external = (self.relocatable
+            && self.externs.iter().any(|external| external.value == name))
+        .then(|| self.shifted(Target::Symbol(name.to_string()), 0));
+        let (table, other, kind, other_kind) = match operand_type {
+            OperandType::Memory => (data, code, "data", "code"),
+            OperandType::Label => (code, data, "code", "data"),
+            _ => {
+                return Ok(match code.or(data).or(external) {
+                    Some(value) => Value::Known(value),
+                    None => Value::Undefined(name.to_string()),
+                });
+            }
+        };
+        match table.or(external) {
+            Some(value) => Ok(Value::Known(value)),
+            None if other.is_some() => Err(SemanticError::LabelKind {
+                message: render_error(Diagnostic {
+                    headline: format!(
+                        "'{}' is a {} label, but a {} label is expected here",
+                        name, other_kind, kind
+                    ),
+                    loc: token.loc,
+                    sources,
+                    help: Some(match operand_type {
+                        OperandType::Memory => {
+                            "Memory operands take labels of data directives (DB, DW, DS, RESB, ASCII, ASCIIZ)"
+                        }
+                        _ => "Jumps and calls take labels of instructions",
+                    }),
+                }),
+            }),
+            None => Ok(Value::Undefined(name.to_string())),
+        }
+    }
+
+    fn shifted(&self, target: Target, address: u32) -> i64 {
+        match &self.shift {
+            Some((shifted, shift)) if *shifted == target => address as i64 + shift,
+            _ => address as i64,
+        }
+    }
+
+    // the section or EXTERN an operand of an object file moves with, found by moving each of
+    // them in turn, None for operands that stay put. Only `symbol ± constant` can be patched
+    // by the linker, so the operand has to move by exactly as much as its target, twice
+    fn relocation_target(
+        &mut self,
+        token: &StatementField,
+        operand_type: &OperandType,
+        value: i64,
+        sources: &SourceMap,
+    ) -> Result<Option<Target>, SemanticError> {
+        let mut candidates = vec![
+            Target::Section(Section::Code),
+            Target::Section(Section::Data),
+        ];
+        candidates.extend(self.externs().into_iter().map(Target::Symbol));
+        let mut moved = Vec::new();
+        for candidate in candidates {
+            let mut deltas = Vec::new();
+            for shift in SHIFTS {
+                self.shift = Some((candidate.clone(), shift));
+                let shifted = self.evaluate(token, operand_type, sources, &mut vec![]);
+                self.shift = None;
+                if let Value::Known(shifted) = shifted? {
+                    deltas.push(shifted - value);
                 }
-                let value = token
-                    .value
-                    .parse::<i8>()
-                    .map_err(|_| SemanticError::NotI8(token.value))?
-                    as u8 as u32;
-                let bit_count = spec.bit_count;
-                Ok(InstructionField { value, bit_count })
             }
-            OperandType::Memory => {
-                if !re.is_match(&token.value) {
-                    return Err(SemanticError::ShapeDoesNotMatch {
-                        message: render_error(Diagnostic {
-                            headline: format!(
-                                "Token '{}' does not look like a memory address",
-                                token.value
-                            ),
-                            line: token.loc.line,
-                            column: token.loc.column,
-                            source_line: &source_lines[token.loc.line as usize - 1],
-                            help: Some(
-                                format!(
-                                    "Memory operand must match the regex: {}",
-                                    spec.operand_regex
-                                )
-                                .as_str(),
-                            ),
-                        }),
-                    });
+            if deltas.iter().any(|delta| *delta != 0) {
+                moved.push((candidate, deltas));
+            }
+        }
+        match moved.as_slice() {
+            [] => Ok(None),
+            [(target, deltas)] if *deltas == SHIFTS => Ok(Some(target.clone())),
+            _ => Err(SemanticError::Linkage {
+                message: render_error(Diagnostic {
+                    headline: format!("'{}' cannot be relocated", token.value),
+                    loc: token.loc,
+                    sources,
+                    help: Some(
+                        "In an object file an operand may use one label or EXTERN, plus or minus a constant",
+                    ),
+                }),
+            }),
+        }
+    }
+
+    fn evaluate(
+        &self,
+        token: &StatementField,
+        operand_type: &OperandType,
+        sources: &SourceMap,
+        visiting: &mut Vec<String>,
+    ) -> Result<Value, SemanticError> {
+        let expression = Expression::parse(&token.value)
+            .map_err(|message| expression_error(token, message, sources))?;
+        let mut values = HashMap::new();
+        for name in expression.symbols() {
+            match self.lookup(name, token, operand_type, sources, visiting)? {
+                Value::Known(value) => values.insert(name, value),
+                undefined => return Ok(undefined),
+            };
+        }
+        expression
+            .evaluate(&|name| values[name])
+            .map(Value::Known)
+            .map_err(|message| expression_error(token, message, sources))
+    }
+
+    // operands made of symbols go through the tii when a symbol isn't defined yet
+    fn resolve_expression(
+        &mut self,
+        token: StatementField,
+        spec: &OperandSpec,
+        operand_number: usize,
+        sources: &SourceMap,
+    ) -> Result<InstructionField, SemanticError> {
+        for name in symbol_names(&token.value) {
+            self.references
+                .entry(name)
+                .or_default()
+                .push(sources.main_line(&token.loc));
+        }
+        let value = match self.evaluate(&token, &spec.operand_type, sources, &mut vec![])? {
+            Value::Known(value) if !self.relocatable || symbol_names(&token.value).is_empty() => {
+                encode(&token, value, &spec.operand_type, spec.bit_count, sources)?
+            }
+            _ => {
+                self.tii.push(TiiEntry {
+                    instruction_number: self.instruction_counter,
+                    operand_number,
+                    token,
+                    operand_type: spec.operand_type,
+                    bit_count: spec.bit_count,
+                });
+                0
+            }
+        };
+        Ok(InstructionField {
+            value,
+            bit_count: spec.bit_count,
+        })
+    }
+
+    fn undefined_error(&self, token: &StatementField, name: &str, sources: &SourceMap) -> String {
+        let external = self.externs.iter().any(|external| external.value == name);
+        render_error(Diagnostic {
+            headline: format!("Undefined label '{}'", name),
+            loc: token.loc,
+            sources,
+            help: external
+                .then_some("EXTERN symbols are resolved by the linker, assemble with --object"),
+        })
+    }
+
+    // patches every forward reference and checks every EQU, GLOBAL and EXTERN, reporting
+    // each undefined symbol separately
+    fn resolve_forward_references(
+        &mut self,
+        instructions: &mut [Instruction],
+        sources: &SourceMap,
+    ) -> Vec<Located<SemanticError>> {
+        let mut errors = Vec::new();
+        let undefined =
+            |parser: &Self, token: &StatementField, name: &str| SemanticError::UndefinedLabel {
+                message: parser.undefined_error(token, name, sources),
+            };
+        for entry in std::mem::take(&mut self.tii) {
+            let result =
+                match self.evaluate(&entry.token, &entry.operand_type, sources, &mut vec![]) {
+                    Ok(Value::Known(value)) if self.relocatable => {
+                        self.relocate(&entry, value, instructions, sources)
+                    }
+                    Ok(Value::Known(value)) => encode(
+                        &entry.token,
+                        value,
+                        &entry.operand_type,
+                        entry.bit_count,
+                        sources,
+                    )
+                    .map(|value| {
+                        let field = &mut instructions[entry.instruction_number]
+                            .operands
+                            .as_mut()
+                            .unwrap()[entry.operand_number];
+                        field.value |= value << (field.bit_count - entry.bit_count)
+                    }),
+                    Ok(Value::Undefined(name)) => Err(undefined(self, &entry.token, &name)),
+                    Err(error) => Err(error),
+                };
+            if let Err(error) = result {
+                errors.push((entry.token.loc, error));
+            }
+        }
+        let mut constants: Vec<(&String, &StatementField)> = self.constants.iter().collect();
+        constants.sort_by_key(|(_, definition)| (definition.loc.line, definition.loc.column));
+        let mut constant_values = HashMap::new();
+        for (constant, definition) in constants {
+            match self.evaluate(definition, &OperandType::Constant, sources, &mut vec![]) {
+                Ok(Value::Known(value)) => {
+                    constant_values.insert(constant.clone(), value);
                 }
-                let value = token
-                    .value
-                    .parse::<u32>()
-                    .map_err(|_| SemanticError::ParseInt(token.value))?;
-                let bit_count = spec.bit_count;
-                Ok(InstructionField { value, bit_count })
+                Ok(Value::Undefined(name)) => {
+                    errors.push((definition.loc, undefined(self, definition, &name)))
+                }
+                Err(error) => errors.push((definition.loc, error)),
             }
-            OperandType::Label => {
-                if !re.is_match(&token.value) {
-                    return Err(SemanticError::ShapeDoesNotMatch {
+        }
+        self.constant_values = constant_values;
+        for external in &self.externs {
+            if self.symtab.contains_key(&external.value)
+                || self.data_symbols.contains_key(&external.value)
+                || self.constants.contains_key(&external.value)
+            {
+                errors.push((
+                    external.loc,
+                    SemanticError::Linkage {
                         message: render_error(Diagnostic {
-                            headline: format!("Token '{}' does not look like a label", token.value),
-                            line: token.loc.line,
-                            column: token.loc.column,
-                            source_line: &source_lines[token.loc.line as usize - 1].clone(),
-                            help: Some(
-                                format!("Label must match the regex: {}", spec.operand_regex)
-                                    .as_str(),
+                            headline: format!(
+                                "'{}' is declared EXTERN but defined in this file",
+                                external.value
                             ),
+                            loc: external.loc,
+                            sources,
+                            help: Some("Use GLOBAL to export a symbol defined here"),
                         }),
-                    });
-                }
-                if let Some(location) = self.symtab.get(&token.value) {
-                    Ok(InstructionField {
-                        value: *location,
-                        bit_count: spec.bit_count,
-                    })
-                } else {
-                    self.tii.entry(token).or_default().push(TiiEntry {
-                        instruction_number: self.instruction_counter,
-                        operand_number,
-                    });
-                    Ok(InstructionField {
-                        value: 0,
-                        bit_count: spec.bit_count,
-                    })
-                }
+                    },
+                ));
+            }
+        }
+        for global in &self.globals {
+            if !self.symtab.contains_key(&global.value)
+                && !self.data_symbols.contains_key(&global.value)
+            {
+                errors.push((global.loc, undefined(self, global, &global.value)));
             }
         }
+        errors
+    }
+
+    // encodes an operand that stays put, or records where the linker has to patch it
+    fn relocate(
+        &mut self,
+        entry: &TiiEntry,
+        value: i64,
+        instructions: &mut [Instruction],
+        sources: &SourceMap,
+    ) -> Result<(), SemanticError> {
+        let target = self.relocation_target(&entry.token, &entry.operand_type, value, sources)?;
+        let instruction = &mut instructions[entry.instruction_number];
+        let operands = instruction.operands.as_mut().unwrap();
+        let Some(target) = target else {
+            let field = &mut operands[entry.operand_number];
+            field.value |= encode(
+                &entry.token,
+                value,
+                &entry.operand_type,
+                entry.bit_count,
+                sources,
+            )? << (field.bit_count - entry.bit_count);
+            return Ok(());
+        };
+        let offset = instruction.address
+            + instruction.opcode.bit_count as u32
+            + operands[..entry.operand_number]
+                .iter()
+                .map(|operand| operand.bit_count as u32)
+                .sum::<u32>();
+        let addend = i32::try_from(value).map_err(|_| {
+            expression_error(&entry.token, "Expression overflows".to_string(), sources)
+        })?;
+        self.relocations.push(Relocation {
+            offset,
+            bit_count: entry.bit_count,
+            target,
+            addend,
+        });
+        Ok(())
+    }
+
+    // the form of an operation that fits its operands, `MOVER R0, [R1]` picks the
+    // register-indirect one. Without an exact fit the form with the right brackets reports
+    // what is wrong
+    fn select_form(&self, name: &str, operands: &[StatementField]) -> Option<&Operation> {
+        let forms: Vec<&Operation> = self.optspec.get_forms(name).collect();
+        let fits = |operation: &Operation, exact: bool| {
+            operation
+                .operands
+                .iter()
+                .zip(operands)
+                .all(|(spec, token)| match spec.operand_type.is_indirect() {
+                    true => {
+                        token.value.starts_with('[')
+                            && (!exact
+                                || Regex::new(&spec.operand_regex)
+                                    .is_ok_and(|re| re.is_match(&token.value)))
+                    }
+                    false => !token.value.starts_with('['),
+                })
+        };
+        forms
+            .iter()
+            .find(|operation| fits(operation, true))
+            .or_else(|| forms.iter().find(|operation| fits(operation, false)))
+            .or(forms.first())
+            .copied()
     }
 
     pub fn analyze_statement(
         &mut self,
         statement: Statement,
-        source_lines: &Vec<String>,
+        sources: &SourceMap,
     ) -> Result<Instruction, SemanticError> {
+        let expansion = listed_text(&statement, sources);
         let operation_name = statement.operation_name.unwrap();
-        let operation = match self
-            .optspec
-            .get_by_operation_name(&operation_name.value.as_str())
-        {
+        let operation = match self.select_form(
+            &operation_name.value,
+            statement.operands.as_deref().unwrap_or_default(),
+        ) {
             Some(operation) => operation,
             None => {
                 return Err(SemanticError::UnknownOperation {
                     message: render_error(Diagnostic {
                         headline: format!("Unknown opcode '{}'", operation_name.value),
-                        line: operation_name.loc.line,
-                        column: operation_name.loc.column,
-                        source_line: &source_lines[operation_name.loc.line as usize - 1].clone(),
+                        loc: operation_name.loc,
+                        sources,
                         help: None,
                     }),
                 });
@@ -251,9 +784,8 @@ impl SemanticParser {
                 return Err(SemanticError::ShapeDoesNotMatch {
                     message: render_error(Diagnostic {
                         headline: "Too few operands".to_string(),
-                        line: operation_name.loc.line,
-                        source_line: &source_lines[operation_name.loc.line as usize - 1].clone(),
-                        column: operation_name.loc.column,
+                        loc: operation_name.loc,
+                        sources,
                         help: Some(
                             format!(
                                 "Operation {} requires {} operands",
@@ -268,9 +800,8 @@ impl SemanticParser {
                 return Err(SemanticError::ShapeDoesNotMatch {
                     message: render_error(Diagnostic {
                         headline: "Too many operands".to_string(),
-                        line: operation_name.loc.line,
-                        source_line: &source_lines[operation_name.loc.line as usize - 1].clone(),
-                        column: operation_name.loc.column,
+                        loc: operation_name.loc,
+                        sources,
                         help: Some(
                             format!(
                                 "Operation {} requires {} operands",
@@ -289,9 +820,8 @@ impl SemanticParser {
                 return Err(SemanticError::ShapeDoesNotMatch {
                     message: render_error(Diagnostic {
                         headline: "Missing operands".to_string(),
-                        line: operation_name.loc.line,
-                        source_line: &source_lines[operation_name.loc.line as usize - 1].clone(),
-                        column: operation_name.loc.column,
+                        loc: operation_name.loc,
+                        sources,
                         help: Some(
                             format!(
                                 "Operation {} requires {} operands",
@@ -307,13 +837,20 @@ impl SemanticParser {
             }
         };
 
+        let labels = expected_operands
+            .iter()
+            .zip(operands.iter())
+            .filter(|(spec, _)| spec.operand_type == OperandType::Label)
+            .flat_map(|(_, token)| symbol_names(&token.value))
+            .collect();
+
         let operands: Result<Vec<InstructionField>, SemanticError> = expected_operands
             .iter()
             .zip(operands.iter())
             .enumerate()
             .map(|(i, (spec, token))| {
                 let re = Regex::new(spec.operand_regex.as_str())?;
-                self.parse_operand(token.clone(), spec, &re, i, source_lines)
+                self.parse_operand(token.clone(), spec, &re, i, sources)
             })
             .collect();
 
@@ -333,73 +870,161 @@ impl SemanticParser {
             },
             operands: Some(operands),
             size,
+            line: sources.main_line(&operation_name.loc),
+            address: self.location_counter,
+            labels,
+            expansion,
         })
     }
 
+    // a statement with an error is left out and analysis goes on with the next one
     pub fn parse(
         &mut self,
         statements: Vec<Statement>,
-        source_lines: &Vec<String>,
-    ) -> Result<Vec<Instruction>, SemanticError> {
-        let statements = self.normalize(statements)?;
+        sources: &SourceMap,
+    ) -> Result<Vec<Instruction>, Vec<Located<SemanticError>>> {
+        let statements = self
+            .normalize(statements)
+            .map_err(|error| vec![(SourceLoc::default(), error)])?;
         let mut instructions = Vec::<Instruction>::new();
+        let mut errors = Vec::new();
         for statement in statements {
-            if let Some(label) = &statement.label {
-                match self.symtab.contains_key(label.value.as_str()) {
-                    true => {
-                        return Err(SemanticError::LabelAlreadyInUse(label.to_string()));
-                    }
-                    false => {
-                        self.symtab
-                            .insert(label.value.clone(), self.location_counter);
-
-                        // patch
-                        if let Some(tii_entries) = self.tii.get(label) {
-                            for entry in tii_entries {
-                                instructions[entry.instruction_number]
-                                    .operands
-                                    .as_mut()
-                                    .unwrap()[entry.operand_number]
-                                    .value = self.location_counter;
-                            }
+            let loc = statement
+                .operation_name
+                .as_ref()
+                .or(statement.label.as_ref())
+                .map(|field| field.loc)
+                .unwrap_or_default();
+            let tii = self.tii.len();
+            if let Err(error) = self.analyze(statement, &mut instructions, sources) {
+                // forward references of the failed statement would patch the next instruction
+                self.tii.truncate(tii);
+                errors.push((loc, error));
+            }
+        }
+        errors.extend(self.resolve_forward_references(&mut instructions, sources));
+        match errors.is_empty() {
+            true => Ok(instructions),
+            false => Err(errors),
+        }
+    }
 
-                            // remove the entry from the tii
-                            self.tii.remove(label);
-                        };
-                    }
-                };
+    fn analyze(
+        &mut self,
+        statement: Statement,
+        instructions: &mut Vec<Instruction>,
+        sources: &SourceMap,
+    ) -> Result<(), SemanticError> {
+        {
+            let is_data = statement
+                .operation_name
+                .as_ref()
+                .is_some_and(|operation_name| is_directive(&operation_name.value));
+            let is_constant = statement
+                .operation_name
+                .as_ref()
+                .is_some_and(|operation_name| operation_name.value == "EQU");
+            if let Some(label) = &statement.label {
+                if self.symtab.contains_key(&label.value)
+                    || self.data_symbols.contains_key(&label.value)
+                    || self.constants.contains_key(&label.value)
+                {
+                    return Err(SemanticError::LabelAlreadyInUse(label.to_string()));
+                }
+                // a data label is bound once its directive has been placed
+                if is_constant {
+                    let definition = self.analyze_equ(&statement, sources)?;
+                    self.constants.insert(label.value.clone(), definition);
+                } else if !is_data {
+                    self.symtab
+                        .insert(label.value.clone(), self.location_counter);
+                }
+                self.definitions
+                    .insert(label.value.clone(), sources.main_line(&label.loc));
             }
-            if statement.operation_name.is_some() {
-                let instruction = self.analyze_statement(statement, source_lines)?;
+            if is_constant {
+                if statement.label.is_none() {
+                    self.analyze_equ(&statement, sources)?;
+                }
+            } else if let Some(operation_name) = statement
+                .operation_name
+                .as_ref()
+                .filter(|operation_name| is_linkage(&operation_name.value))
+            {
+                let operands = statement.operands.as_deref().unwrap_or_default();
+                self.analyze_linkage(operation_name, operands, sources)?;
+            } else if is_data {
+                let operation_name = statement.operation_name.as_ref().unwrap();
+                let operands = statement.operands.as_deref().unwrap_or_default();
+                let mut directive = self.analyze_directive(operation_name, operands, sources)?;
+                if let Some(label) = &statement.label {
+                    self.data_symbols
+                        .insert(label.value.clone(), directive.address);
+                }
+                directive.expansion = listed_text(&statement, sources);
+                self.directives.push(directive);
+            } else if let Some(operation_name) = statement.operation_name.clone() {
+                let instruction = self.analyze_statement(statement, sources)?;
+                let start = self.location_counter;
+                let capacity = self.optspec.address_space();
                 self.location_counter += instruction.size;
+                // only the instruction that crosses the end is reported, not every one after it
+                if start <= capacity && self.location_counter > capacity {
+                    return Err(SemanticError::ProgramTooLarge {
+                        message: render_error(Diagnostic {
+                            headline: format!(
+                                "The program does not fit in {} bit code addresses",
+                                self.optspec.address_bit_count
+                            ),
+                            loc: operation_name.loc,
+                            sources,
+                            help: Some(
+                                format!(
+                                    "Code may take up to {} bits, this instruction ends at bit {}",
+                                    capacity, self.location_counter
+                                )
+                                .as_str(),
+                            ),
+                        }),
+                    });
+                }
                 instructions.push(instruction);
             }
         }
-        if !self.tii.is_empty() {
-            let entries = self.tii.keys();
-            let mut message = String::new();
-            for entry in entries {
-                message.push_str(
-                    render_error(Diagnostic {
-                        headline: format!("Undefined label '{}'", entry.value),
-                        line: entry.loc.line,
-                        source_line: &source_lines[entry.loc.line as usize - 1].clone(),
-                        column: entry.loc.column,
-                        help: None,
-                    })
-                    .as_str(),
-                );
-            }
-            return Err(SemanticError::UndefinedLabel { message: message });
-        }
-        Ok(instructions)
+        Ok(())
+    }
+}
+
+// what the listing shows under the line of the file being assembled for a statement that
+// came from a macro call or, with its file and line, from an included file
+fn listed_text(statement: &Statement, sources: &SourceMap) -> Option<String> {
+    let loc = statement.operation_name.as_ref()?.loc;
+    if loc.expansion.is_some() {
+        Some(statement.to_string())
+    } else if !sources.include_chain(&loc).is_empty() {
+        Some(format!(
+            "{}:{}  {}",
+            sources.name(loc.file),
+            loc.line,
+            statement
+        ))
+    } else {
+        None
+    }
+}
+
+// the symbols an operand uses, for the cross reference and the listing
+fn symbol_names(value: &str) -> Vec<String> {
+    match Expression::parse(value) {
+        Ok(expression) => expression.symbols().into_iter().map(String::from).collect(),
+        Err(_) => vec![],
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::super::{
-        super::lexer::token::SourceLoc,
+        super::{lexer::token::SourceLoc, source_map::SourceMap},
         instruction::{InstructionField, Statement, StatementField},
         semantic_parser::SemanticParser,
     };
@@ -410,7 +1035,12 @@ mod tests {
             Statement {
                 label: Some(StatementField {
                     value: "MOVE".to_string(),
-                    loc: SourceLoc { line: 1, column: 1 },
+                    loc: SourceLoc {
+                        file: 0,
+                        line: 1,
+                        column: 1,
+                        expansion: None,
+                    },
                 }),
                 operation_name: None,
                 operands: None,
@@ -419,18 +1049,30 @@ mod tests {
                 label: None,
                 operation_name: Some(StatementField {
                     value: "MOVER".to_string(),
-                    loc: SourceLoc { line: 2, column: 1 },
+                    loc: SourceLoc {
+                        file: 0,
+                        line: 2,
+                        column: 1,
+                        expansion: None,
+                    },
                 }),
                 operands: Some(vec![
                     StatementField {
                         value: "R0".to_string(),
-                        loc: SourceLoc { line: 2, column: 7 },
+                        loc: SourceLoc {
+                            file: 0,
+                            line: 2,
+                            column: 7,
+                            expansion: None,
+                        },
                     },
                     StatementField {
                         value: "0".to_string(),
                         loc: SourceLoc {
+                            file: 0,
                             line: 2,
                             column: 11,
+                            expansion: None,
                         },
                     },
                 ]),
@@ -438,8 +1080,8 @@ mod tests {
         ];
 
         let mut semantic_parser = SemanticParser::new();
-        let source_lines = ["", ""].map(|s| s.to_string()).to_vec();
-        let instructions = semantic_parser.parse(statements, &source_lines).unwrap();
+        let sources = SourceMap::from_program("\n");
+        let instructions = semantic_parser.parse(statements, &sources).unwrap();
 
         assert_eq!(instructions.len(), 1);
         let instruction = &instructions[0];
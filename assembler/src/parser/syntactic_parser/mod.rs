@@ -3,7 +3,8 @@ use std::mem;
 use super::{
     super::{
         lexer::token::{TokenStream, TokenType},
-        render_error::{Diagnostic, render_error},
+        render_error::{Diagnostic, Located, render_error},
+        source_map::SourceMap,
     },
     instruction::Statement,
 };
@@ -21,32 +22,76 @@ enum DFAState {
     AfterOpcode,
     AfterOperand,
     ExpectOperand, // after comma
+    InExpression,  // after an operator or an opening parenthesis inside an operand
+}
+
+// symbols that continue an operand as an expression instead of separating operands, square
+// brackets hold the register of an indirect address like `[R1+4]`
+fn is_operator(symbol: &str) -> bool {
+    matches!(
+        symbol,
+        "+" | "-" | "*" | "/" | "<" | ">" | "&" | "|" | "(" | ")" | "[" | "]"
+    )
 }
 
 pub struct SyntacticParser {
     statements: Vec<Statement>,
+    errors: Vec<Located<SyntacticError>>,
+}
+
+// a line with an error only keeps its label, so uses of the label aren't reported as well
+fn keep_label(statement: &mut Statement) -> DFAState {
+    statement.operation_name = None;
+    statement.operands = None;
+    match statement.label {
+        Some(_) => DFAState::AfterLabel,
+        None => DFAState::Start,
+    }
 }
 
 impl SyntacticParser {
     pub fn new() -> Self {
-        return Self { statements: vec![] };
+        Self {
+            statements: vec![],
+            errors: vec![],
+        }
     }
 
+    // parsing resumes at the next line after an error, so every line gets checked
     pub fn parse(
         &mut self,
         mut tokens: TokenStream,
-        source_lines: &Vec<String>,
-    ) -> Result<Vec<Statement>, SyntacticError> {
+        sources: &SourceMap,
+    ) -> (Vec<Statement>, Vec<Located<SyntacticError>>) {
         let mut statement = Statement::new();
         let mut state = DFAState::Start;
         loop {
-            let current_token = tokens.seek(0).unwrap();
+            // a stream cut short of its Eof, after a line that failed
+            let Some(current_token) = tokens.seek(0) else {
+                return (mem::take(&mut self.statements), mem::take(&mut self.errors));
+            };
+            let loc = current_token.source_loc;
+            let mut error = None;
             match current_token.token_type {
                 TokenType::Identifier => {
                     match state {
                         DFAState::Start => {
-                            // label or opcode
-                            if let Some(':') = tokens.seek_as_symbol(1) {
+                            // label or opcode, `NAME EQU expr` names the constant like a label
+                            let next = (1..)
+                                .map_while(|at| tokens.seek(at))
+                                .find(|token| token.token_type != TokenType::Whitespace);
+                            let is_equ = next.is_some_and(|token| {
+                                token.token_type == TokenType::Identifier
+                                    && token.value.as_deref() == Some("EQU")
+                            });
+                            if is_equ {
+                                statement.set_label(
+                                    current_token.value.clone().unwrap(),
+                                    current_token.source_loc,
+                                );
+                                tokens.next();
+                                state = DFAState::AfterLabel;
+                            } else if let Some(':') = tokens.seek_as_symbol(1) {
                                 statement.set_label(
                                     current_token.value.clone().unwrap(),
                                     current_token.source_loc,
@@ -79,74 +124,101 @@ impl SyntacticParser {
                             tokens.next();
                             state = DFAState::AfterOperand;
                         }
+                        DFAState::InExpression => {
+                            statement.extend_operand(current_token.value.as_ref().unwrap());
+                            tokens.next();
+                            state = DFAState::AfterOperand;
+                        }
                         _ => {
-                            return Err(SyntacticError::UnexpectedToken {
+                            error = Some(SyntacticError::UnexpectedToken {
                                 message: render_error(Diagnostic {
                                     headline: format!(
                                         "Unexpected identifier '{}'",
                                         current_token.value.clone().unwrap()
                                     ),
-                                    line: current_token.source_loc.line,
-                                    source_line: &source_lines
-                                        [current_token.source_loc.line as usize - 1],
-                                    column: current_token.source_loc.column,
-                                    help: Some(match state {
+                                    loc: current_token.source_loc,
+                                    sources,
+                                    help: match state {
                                         DFAState::AfterOperand => {
-                                            "Perhaps you meant to use comma(,) instead?"
+                                            Some("Perhaps you meant to use comma(,) instead?")
                                         }
-                                        _ => "",
-                                    }),
+                                        _ => None,
+                                    },
                                 }),
                             });
                         }
                     }
                 }
                 TokenType::Symbol => {
-                    if state == DFAState::AfterOperand
-                        && current_token.value.clone().unwrap().as_str() == ","
-                    {
+                    let symbol = current_token.value.clone().unwrap();
+                    let next_state = match symbol.as_str() {
+                        ")" | "]" => DFAState::AfterOperand,
+                        _ => DFAState::InExpression,
+                    };
+                    if state == DFAState::AfterOperand && symbol == "," {
                         state = DFAState::ExpectOperand;
                         tokens.next();
+                    } else if is_operator(&symbol)
+                        && matches!(state, DFAState::AfterOperand | DFAState::InExpression)
+                    {
+                        statement.extend_operand(&symbol);
+                        tokens.next();
+                        state = next_state;
+                    } else if is_operator(&symbol)
+                        && matches!(state, DFAState::AfterOpcode | DFAState::ExpectOperand)
+                    {
+                        statement.add_operand(symbol, current_token.source_loc);
+                        tokens.next();
+                        state = next_state;
                     } else {
-                        return Err(SyntacticError::UnexpectedToken {
+                        error = Some(SyntacticError::UnexpectedToken {
                             message: render_error(Diagnostic {
                                 headline: format!(
                                     "Unexpected symbol '{}'",
                                     current_token.value.clone().unwrap()
                                 ),
-                                line: current_token.source_loc.line,
-                                source_line: &source_lines
-                                    [current_token.source_loc.line as usize - 1],
-                                column: current_token.source_loc.column,
-                                help: Some(match state {
-                                    DFAState::AfterLabel => {
-                                        "Labels must be followed by single colon(:) and then an identifier (opcode) should follow"
-                                    }
+                                loc: current_token.source_loc,
+                                sources,
+                                help: match state {
+                                    DFAState::AfterLabel => Some(
+                                        "Labels must be followed by single colon(:) and then an identifier (opcode) should follow",
+                                    ),
                                     DFAState::AfterOpcode => {
-                                        "An identifier (operand) is expected after opcode"
+                                        Some("An identifier (operand) is expected after opcode")
                                     }
                                     DFAState::ExpectOperand => {
-                                        "An identifier is expected after comma"
+                                        Some("An identifier is expected after comma")
                                     }
-                                    _ => "",
-                                }),
+                                    DFAState::InExpression => {
+                                        Some("An operand is expected after an operator")
+                                    }
+                                    _ => None,
+                                },
                             }),
                         });
                     }
                 }
                 TokenType::Newline => {
                     match state {
-                        DFAState::ExpectOperand => {
-                            return Err(SyntacticError::UnexpectedToken {
-                                message: render_error(Diagnostic {
-                                    headline: "An identifier is expected after comma".to_string(),
-                                    line: current_token.source_loc.line,
-                                    source_line: &source_lines
-                                        [current_token.source_loc.line as usize - 1],
-                                    column: current_token.source_loc.column,
-                                    help: None,
-                                }),
-                            });
+                        DFAState::ExpectOperand | DFAState::InExpression => {
+                            self.errors.push((
+                                loc,
+                                SyntacticError::UnexpectedToken {
+                                    message: render_error(Diagnostic {
+                                        headline: match state {
+                                            DFAState::InExpression => "Incomplete expression",
+                                            _ => "An identifier is expected after comma",
+                                        }
+                                        .to_string(),
+                                        loc,
+                                        sources,
+                                        help: None,
+                                    }),
+                                },
+                            ));
+                            if keep_label(&mut statement) == DFAState::AfterLabel {
+                                self.statements.push(statement);
+                            }
                         }
                         DFAState::Start => {}
                         _ => self.statements.push(statement),
@@ -157,25 +229,42 @@ impl SyntacticParser {
                 }
                 TokenType::Eof => {
                     match state {
-                        DFAState::ExpectOperand => {
-                            return Err(SyntacticError::UnexpectedToken {
-                                message: render_error(Diagnostic {
-                                    headline: "An identifier is expected after comma".to_string(),
-                                    line: current_token.source_loc.line,
-                                    source_line: &source_lines
-                                        [current_token.source_loc.line as usize - 1],
-                                    column: current_token.source_loc.column,
-                                    help: None,
-                                }),
-                            });
+                        DFAState::ExpectOperand | DFAState::InExpression => {
+                            self.errors.push((
+                                loc,
+                                SyntacticError::UnexpectedToken {
+                                    message: render_error(Diagnostic {
+                                        headline: match state {
+                                            DFAState::InExpression => "Incomplete expression",
+                                            _ => "An identifier is expected after comma",
+                                        }
+                                        .to_string(),
+                                        loc,
+                                        sources,
+                                        help: None,
+                                    }),
+                                },
+                            ));
+                            if keep_label(&mut statement) == DFAState::AfterLabel {
+                                self.statements.push(statement);
+                            }
                         }
                         DFAState::Start => {}
                         _ => self.statements.push(statement),
                     }
-                    return Ok(mem::take(&mut self.statements));
+                    return (mem::take(&mut self.statements), mem::take(&mut self.errors));
                 }
                 _ => tokens.next(),
             }
+            if let Some(error) = error {
+                self.errors.push((loc, error));
+                state = keep_label(&mut statement);
+                while tokens.seek(0).is_some_and(|token| {
+                    !matches!(token.token_type, TokenType::Newline | TokenType::Eof)
+                }) {
+                    tokens.next();
+                }
+            }
         }
     }
 }
@@ -194,118 +283,179 @@ mod tests {
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("MOVE".to_string()),
-            source_loc: SourceLoc { line: 1, column: 1 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 1,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Symbol,
             value: Some(":".to_string()),
-            source_loc: SourceLoc { line: 1, column: 6 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 6,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("MOVER".to_string()),
-            source_loc: SourceLoc { line: 1, column: 7 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 7,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("R0".to_string()),
             source_loc: SourceLoc {
+                file: 0,
                 line: 1,
                 column: 13,
+                expansion: None,
             },
         });
         tokens.push(Token {
             token_type: TokenType::Symbol,
             value: Some(",".to_string()),
             source_loc: SourceLoc {
+                file: 0,
                 line: 1,
                 column: 15,
+                expansion: None,
             },
         });
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("0".to_string()),
             source_loc: SourceLoc {
+                file: 0,
                 line: 1,
                 column: 17,
+                expansion: None,
             },
         });
         tokens.push(Token {
             token_type: TokenType::Newline,
             value: None,
             source_loc: SourceLoc {
+                file: 0,
                 line: 1,
                 column: 18,
+                expansion: None,
             },
         });
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("MOVE1".to_string()),
-            source_loc: SourceLoc { line: 2, column: 1 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 2,
+                column: 1,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Symbol,
             value: Some(":".to_string()),
-            source_loc: SourceLoc { line: 2, column: 7 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 2,
+                column: 7,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("MOVER".to_string()),
-            source_loc: SourceLoc { line: 2, column: 8 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 2,
+                column: 8,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("R0".to_string()),
             source_loc: SourceLoc {
+                file: 0,
                 line: 2,
                 column: 14,
+                expansion: None,
             },
         });
         tokens.push(Token {
             token_type: TokenType::Symbol,
             value: Some(",".to_string()),
             source_loc: SourceLoc {
+                file: 0,
                 line: 2,
                 column: 16,
+                expansion: None,
             },
         });
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("0".to_string()),
             source_loc: SourceLoc {
+                file: 0,
                 line: 2,
                 column: 18,
+                expansion: None,
             },
         });
         tokens.push(Token {
             token_type: TokenType::Newline,
             value: None,
             source_loc: SourceLoc {
+                file: 0,
                 line: 2,
                 column: 19,
+                expansion: None,
             },
         });
         tokens.push(Token {
             token_type: TokenType::Eof,
             value: None,
-            source_loc: SourceLoc { line: 3, column: 1 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 3,
+                column: 1,
+                expansion: None,
+            },
         });
         let mut parser = SyntacticParser::new();
-        let source_lines = ["", ""].map(|s| s.to_string()).to_vec();
-        let statements = parser.parse(tokens, &source_lines).unwrap();
+        let sources = SourceMap::from_program("\n");
+        let (statements, _) = parser.parse(tokens, &sources);
         assert_eq!(statements.len(), 2);
         assert_eq!(
             statements[0].label,
             Some(StatementField {
                 value: "MOVE".to_string(),
-                loc: SourceLoc { line: 1, column: 1 }
+                loc: SourceLoc {
+                    file: 0,
+                    line: 1,
+                    column: 1,
+                    expansion: None,
+                }
             })
         );
         assert_eq!(
             statements[0].operation_name,
             Some(StatementField {
                 value: "MOVER".to_string(),
-                loc: SourceLoc { line: 1, column: 7 }
+                loc: SourceLoc {
+                    file: 0,
+                    line: 1,
+                    column: 7,
+                    expansion: None,
+                }
             })
         );
         assert_eq!(
@@ -314,15 +464,19 @@ mod tests {
                 StatementField {
                     value: "R0".to_string(),
                     loc: SourceLoc {
+                        file: 0,
                         line: 1,
-                        column: 13
+                        column: 13,
+                        expansion: None,
                     }
                 },
                 StatementField {
                     value: "0".to_string(),
                     loc: SourceLoc {
+                        file: 0,
                         line: 1,
-                        column: 17
+                        column: 17,
+                        expansion: None,
                     }
                 }
             ])
@@ -331,14 +485,24 @@ mod tests {
             statements[1].label,
             Some(StatementField {
                 value: "MOVE1".to_string(),
-                loc: SourceLoc { line: 2, column: 1 }
+                loc: SourceLoc {
+                    file: 0,
+                    line: 2,
+                    column: 1,
+                    expansion: None,
+                }
             })
         );
         assert_eq!(
             statements[1].operation_name,
             Some(StatementField {
                 value: "MOVER".to_string(),
-                loc: SourceLoc { line: 2, column: 8 }
+                loc: SourceLoc {
+                    file: 0,
+                    line: 2,
+                    column: 8,
+                    expansion: None,
+                }
             })
         );
         assert_eq!(
@@ -347,15 +511,19 @@ mod tests {
                 StatementField {
                     value: "R0".to_string(),
                     loc: SourceLoc {
+                        file: 0,
                         line: 2,
-                        column: 14
+                        column: 14,
+                        expansion: None,
                     }
                 },
                 StatementField {
                     value: "0".to_string(),
                     loc: SourceLoc {
+                        file: 0,
                         line: 2,
-                        column: 18
+                        column: 18,
+                        expansion: None,
                     }
                 }
             ])
@@ -368,35 +536,60 @@ mod tests {
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("CALL".to_string()),
-            source_loc: SourceLoc { line: 1, column: 1 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 1,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("R0".to_string()),
-            source_loc: SourceLoc { line: 1, column: 6 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 6,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Eof,
             value: None,
-            source_loc: SourceLoc { line: 1, column: 8 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 8,
+                expansion: None,
+            },
         });
         let mut parser = SyntacticParser::new();
-        let source_lines = ["", ""].map(|s| s.to_string()).to_vec();
-        let statements = parser.parse(tokens, &source_lines).unwrap();
+        let sources = SourceMap::from_program("\n");
+        let (statements, _) = parser.parse(tokens, &sources);
         assert_eq!(statements.len(), 1);
         assert_eq!(statements[0].label, None);
         assert_eq!(
             statements[0].operation_name,
             Some(StatementField {
                 value: "CALL".to_string(),
-                loc: SourceLoc { line: 1, column: 1 }
+                loc: SourceLoc {
+                    file: 0,
+                    line: 1,
+                    column: 1,
+                    expansion: None,
+                }
             })
         );
         assert_eq!(
             statements[0].operands,
             Some(vec![StatementField {
                 value: "R0".to_string(),
-                loc: SourceLoc { line: 1, column: 6 }
+                loc: SourceLoc {
+                    file: 0,
+                    line: 1,
+                    column: 6,
+                    expansion: None,
+                }
             }])
         );
     }
@@ -407,23 +600,38 @@ mod tests {
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("RET".to_string()),
-            source_loc: SourceLoc { line: 1, column: 1 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 1,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Eof,
             value: None,
-            source_loc: SourceLoc { line: 1, column: 4 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 4,
+                expansion: None,
+            },
         });
         let mut parser = SyntacticParser::new();
-        let source_lines = ["", ""].map(|s| s.to_string()).to_vec();
-        let statements = parser.parse(tokens, &source_lines).unwrap();
+        let sources = SourceMap::from_program("\n");
+        let (statements, _) = parser.parse(tokens, &sources);
         assert_eq!(statements.len(), 1);
         assert_eq!(statements[0].label, None);
         assert_eq!(
             statements[0].operation_name,
             Some(StatementField {
                 value: "RET".to_string(),
-                loc: SourceLoc { line: 1, column: 1 }
+                loc: SourceLoc {
+                    file: 0,
+                    line: 1,
+                    column: 1,
+                    expansion: None,
+                }
             })
         );
         assert_eq!(statements[0].operands, None);
@@ -435,28 +643,48 @@ mod tests {
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("MOVE".to_string()),
-            source_loc: SourceLoc { line: 1, column: 1 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 1,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Symbol,
             value: Some(":".to_string()),
-            source_loc: SourceLoc { line: 1, column: 5 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 5,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Symbol,
             value: Some(":".to_string()),
-            source_loc: SourceLoc { line: 1, column: 6 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 6,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Eof,
             value: None,
-            source_loc: SourceLoc { line: 1, column: 8 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 8,
+                expansion: None,
+            },
         });
         let mut parser = SyntacticParser::new();
-        let source_lines = ["", ""].map(|s| s.to_string()).to_vec();
+        let sources = SourceMap::from_program("\n");
         // should fail
-        let statements = parser.parse(tokens, &source_lines);
-        assert!(statements.is_err());
+        let statements = parser.parse(tokens, &sources);
+        assert!(!statements.1.is_empty());
     }
     #[test]
     fn test_unusual_statement2() {
@@ -464,26 +692,38 @@ mod tests {
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("MOVER".to_string()),
-            source_loc: SourceLoc { line: 1, column: 1 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 1,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("R0".to_string()),
-            source_loc: SourceLoc { line: 1, column: 7 },
+            source_loc: SourceLoc {
+                file: 0,
+                line: 1,
+                column: 7,
+                expansion: None,
+            },
         });
         tokens.push(Token {
             token_type: TokenType::Identifier,
             value: Some("0".to_string()),
             source_loc: SourceLoc {
+                file: 0,
                 line: 1,
                 column: 10,
+                expansion: None,
             },
         });
 
         let mut parser = SyntacticParser::new();
-        let source_lines = ["MOVER R0 0"].map(|s| s.to_string()).to_vec();
+        let sources = SourceMap::from_program("MOVER R0 0");
         // should fail
-        let statements = parser.parse(tokens, &source_lines);
-        assert!(statements.is_err());
+        let statements = parser.parse(tokens, &sources);
+        assert!(!statements.1.is_empty());
     }
 }
@@ -1,8 +1,12 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    mem,
+};
 
 use super::{
-    lexer::token::{Token, TokenStream, TokenType},
-    render_error::{Diagnostic, render_error},
+    lexer::token::{SourceLoc, Token, TokenStream, TokenType},
+    render_error::{Diagnostic, Located, render_error},
+    source_map::SourceMap,
 };
 
 #[derive(PartialEq, Debug)]
@@ -12,6 +16,7 @@ pub enum DefinitionDFA {
     ExpectSpaceOrNewline,
     ExpectAmpersandOrNewline,
     AfterParameter,
+    ExpectDefault,
     ExpectAmpersand,
     ExpectParameter,
     ModelStatements,
@@ -23,11 +28,32 @@ pub enum DefinitionDFA {
 pub enum PreProcessorError {
     #[error("{message}")]
     InvalidToken { message: String },
+    #[error("{message}")]
+    RecursiveInvocation { message: String },
+    #[error("{message}")]
+    ArityMismatch { message: String },
+}
+
+struct MacroParameter {
+    name: String,
+    default: Option<Token>,
+}
+
+struct Macro {
+    parameters: Vec<MacroParameter>,
+    body: Vec<Token>,
+}
+
+struct Argument {
+    keyword: Option<Token>,
+    value: Vec<Token>,
 }
 
 pub struct PreProcessor {
-    macros: HashMap<String, Vec<Token>>,
+    macros: HashMap<String, Macro>,
     macro_name: String,
+    // macros whose definition failed, their invocations are dropped without another error
+    failed: HashSet<String>,
 }
 
 impl PreProcessor {
@@ -35,14 +61,17 @@ impl PreProcessor {
         Self {
             macros: HashMap::new(),
             macro_name: String::new(),
+            failed: HashSet::new(),
         }
     }
 
+    // reads and removes every definition, a broken one is reported and skipped up to its MEND
     pub fn definition(
         &mut self,
         tokens: &mut TokenStream,
-        source_lines: &Vec<String>,
-    ) -> Result<(), PreProcessorError> {
+        sources: &SourceMap,
+    ) -> Vec<Located<PreProcessorError>> {
+        let mut errors = Vec::new();
         loop {
             while let Some(token) = tokens.seek(0) {
                 if let Some(token) = &token.value
@@ -52,264 +81,783 @@ impl PreProcessor {
                 }
                 if token.token_type == TokenType::Eof {
                     tokens.reset();
-                    return Ok(());
+                    return errors;
                 }
                 tokens.next();
             }
-            tokens.remove();
+            let mut loc = tokens.remove().source_loc;
+            self.macro_name.clear();
             let mut state = DefinitionDFA::AfterMacroKeyword;
-            loop {
-                let current_token = tokens.seek(0).unwrap().clone();
-
-                if current_token.token_type != TokenType::Eof {
-                    tokens.remove();
+            if let Err(error) = self.read_macro(tokens, sources, &mut state, &mut loc) {
+                errors.push((loc, error));
+                if let Some(name) = self
+                    .macros
+                    .remove(&self.macro_name)
+                    .map(|_| &self.macro_name)
+                {
+                    self.failed.insert(name.clone());
                 }
+                skip_definition(tokens, &state);
+            }
+        }
+    }
 
-                match state {
-                    DefinitionDFA::AfterMacroKeyword => match current_token.token_type {
-                        TokenType::Newline => {
-                            state = DefinitionDFA::MacroHeader;
-                        }
-                        TokenType::Whitespace => {}
-                        _ => {
+    // the header, body and MEND of one definition, `loc` follows the token being read
+    fn read_macro(
+        &mut self,
+        tokens: &mut TokenStream,
+        sources: &SourceMap,
+        state: &mut DefinitionDFA,
+        loc: &mut SourceLoc,
+    ) -> Result<(), PreProcessorError> {
+        loop {
+            let current_token = tokens.seek(0).unwrap().clone();
+            *loc = current_token.source_loc;
+
+            if current_token.token_type != TokenType::Eof {
+                tokens.remove();
+            }
+
+            match *state {
+                DefinitionDFA::AfterMacroKeyword => match current_token.token_type {
+                    TokenType::Newline => {
+                        *state = DefinitionDFA::MacroHeader;
+                    }
+                    TokenType::Whitespace => {}
+                    _ => {
+                        return Err(PreProcessorError::InvalidToken {
+                            message: render_error(Diagnostic {
+                                headline: format!("Invalid token or EOF encountered"),
+                                loc: current_token.source_loc,
+                                sources,
+                                help: Some("A newline is expected after macro keyword"),
+                            }),
+                        });
+                    }
+                },
+                DefinitionDFA::MacroHeader => match current_token.token_type {
+                    TokenType::Identifier => {
+                        self.macro_name = current_token.value.unwrap();
+                        self.macros.insert(
+                            self.macro_name.clone(),
+                            Macro {
+                                parameters: Vec::new(),
+                                body: Vec::new(),
+                            },
+                        );
+                        *state = DefinitionDFA::ExpectSpaceOrNewline;
+                    }
+                    TokenType::Whitespace | TokenType::Newline => {}
+                    TokenType::Symbol | TokenType::Eof => {
+                        return Err(PreProcessorError::InvalidToken {
+                            message: "A macro name is expected".to_string(),
+                        });
+                    }
+                },
+                DefinitionDFA::ExpectSpaceOrNewline => match current_token.token_type {
+                    TokenType::Whitespace => *state = DefinitionDFA::ExpectAmpersandOrNewline,
+                    TokenType::Newline => *state = DefinitionDFA::ModelStatements,
+                    _ => {
+                        return Err(PreProcessorError::InvalidToken {
+                            message: render_error(Diagnostic {
+                                headline: format!("Invalid token or EOF encountered"),
+                                loc: current_token.source_loc,
+                                sources,
+                                help: Some("A space or newline is expected"),
+                            }),
+                        });
+                    }
+                },
+                DefinitionDFA::ExpectAmpersandOrNewline => match current_token.token_type {
+                    TokenType::Symbol => {
+                        if current_token.value.unwrap() != "&" {
                             return Err(PreProcessorError::InvalidToken {
                                 message: render_error(Diagnostic {
                                     headline: format!("Invalid token or EOF encountered"),
-                                    line: current_token.source_loc.line,
-                                    source_line: &source_lines
-                                        [current_token.source_loc.line as usize - 1],
-                                    column: current_token.source_loc.column,
-                                    help: Some("A newline is expected after macro keyword"),
+                                    loc: current_token.source_loc,
+                                    sources,
+                                    help: Some("A parameter or newline is expected"),
                                 }),
                             });
                         }
-                    },
-                    DefinitionDFA::MacroHeader => match current_token.token_type {
-                        TokenType::Identifier => {
-                            self.macro_name = current_token.value.unwrap();
-                            self.macros.insert(self.macro_name.clone(), Vec::new());
-                            state = DefinitionDFA::ExpectSpaceOrNewline;
-                        }
-                        TokenType::Whitespace | TokenType::Newline => {}
-                        TokenType::Symbol | TokenType::Eof => {
-                            return Err(PreProcessorError::InvalidToken {
-                                message: "A macro name is expected".to_string(),
+                        *state = DefinitionDFA::ExpectParameter;
+                    }
+                    TokenType::Newline => *state = DefinitionDFA::ModelStatements,
+                    _ => {
+                        return Err(PreProcessorError::InvalidToken {
+                            message: render_error(Diagnostic {
+                                headline: format!("Invalid token or EOF encountered"),
+                                loc: current_token.source_loc,
+                                sources,
+                                help: Some("A parameter or newline is expected"),
+                            }),
+                        });
+                    }
+                },
+                DefinitionDFA::ExpectParameter => match current_token.token_type {
+                    TokenType::Identifier => {
+                        self.macros
+                            .get_mut(&self.macro_name)
+                            .unwrap()
+                            .parameters
+                            .push(MacroParameter {
+                                name: current_token.value.unwrap(),
+                                default: None,
                             });
+                        *state = DefinitionDFA::AfterParameter;
+                    }
+                    _ => {
+                        return Err(PreProcessorError::InvalidToken {
+                            message: render_error(Diagnostic {
+                                headline: format!("Invalid token or EOF encountered"),
+                                loc: current_token.source_loc,
+                                sources,
+                                help: Some("A parameter is expected"),
+                            }),
+                        });
+                    }
+                },
+                DefinitionDFA::AfterParameter => match current_token.token_type {
+                    TokenType::Symbol => {
+                        if current_token.value == Some("=".to_string()) {
+                            *state = DefinitionDFA::ExpectDefault;
+                            continue;
                         }
-                    },
-                    DefinitionDFA::ExpectSpaceOrNewline => match current_token.token_type {
-                        TokenType::Whitespace => state = DefinitionDFA::ExpectAmpersandOrNewline,
-                        TokenType::Newline => state = DefinitionDFA::ModelStatements,
-                        _ => {
+                        if current_token.value.unwrap() != "," {
                             return Err(PreProcessorError::InvalidToken {
                                 message: render_error(Diagnostic {
                                     headline: format!("Invalid token or EOF encountered"),
-                                    line: current_token.source_loc.line,
-                                    source_line: &source_lines
-                                        [current_token.source_loc.line as usize - 1],
-                                    column: current_token.source_loc.column,
-                                    help: Some("A space or newline is expected"),
+                                    loc: current_token.source_loc,
+                                    sources,
+                                    help: Some(
+                                        "A comma followed by another parameter, a default value or newline is expected",
+                                    ),
                                 }),
                             });
                         }
-                    },
-                    DefinitionDFA::ExpectAmpersandOrNewline => match current_token.token_type {
-                        TokenType::Symbol => {
-                            if current_token.value.unwrap() != "&" {
-                                return Err(PreProcessorError::InvalidToken {
-                                    message: render_error(Diagnostic {
-                                        headline: format!("Invalid token or EOF encountered"),
-                                        line: current_token.source_loc.line,
-                                        source_line: &source_lines
-                                            [current_token.source_loc.line as usize - 1],
-                                        column: current_token.source_loc.column,
-                                        help: Some("A parameter or newline is expected"),
-                                    }),
-                                });
-                            }
-                            state = DefinitionDFA::ExpectParameter;
-                        }
-                        TokenType::Newline => state = DefinitionDFA::ModelStatements,
-                        _ => {
+                        *state = DefinitionDFA::ExpectAmpersand;
+                    }
+                    TokenType::Whitespace => {}
+                    TokenType::Newline => *state = DefinitionDFA::ModelStatements,
+                    _ => {
+                        return Err(PreProcessorError::InvalidToken {
+                            message: render_error(Diagnostic {
+                                headline: format!("Invalid token or EOF encountered"),
+                                loc: current_token.source_loc,
+                                sources,
+                                help: Some(
+                                    "A comma followed by another parameter, a default value or newline is expected",
+                                ),
+                            }),
+                        });
+                    }
+                },
+                DefinitionDFA::ExpectDefault => match current_token.token_type {
+                    TokenType::Identifier => {
+                        let parameters =
+                            &mut self.macros.get_mut(&self.macro_name).unwrap().parameters;
+                        parameters.last_mut().unwrap().default = Some(current_token);
+                        *state = DefinitionDFA::AfterParameter;
+                    }
+                    TokenType::Whitespace => {}
+                    _ => {
+                        return Err(PreProcessorError::InvalidToken {
+                            message: render_error(Diagnostic {
+                                headline: "Invalid token or EOF encountered".to_string(),
+                                loc: current_token.source_loc,
+                                sources,
+                                help: Some("A default value is expected after '='"),
+                            }),
+                        });
+                    }
+                },
+                DefinitionDFA::ExpectAmpersand => match current_token.token_type {
+                    TokenType::Symbol => {
+                        if current_token.value.unwrap() != "&" {
                             return Err(PreProcessorError::InvalidToken {
                                 message: render_error(Diagnostic {
                                     headline: format!("Invalid token or EOF encountered"),
-                                    line: current_token.source_loc.line,
-                                    source_line: &source_lines
-                                        [current_token.source_loc.line as usize - 1],
-                                    column: current_token.source_loc.column,
+                                    loc: current_token.source_loc,
+                                    sources,
                                     help: Some("A parameter or newline is expected"),
                                 }),
                             });
                         }
-                    },
-                    DefinitionDFA::ExpectParameter => match current_token.token_type {
-                        TokenType::Identifier => {
-                            //TODO introduce parameters
-                            state = DefinitionDFA::AfterParameter;
-                        }
-                        _ => {
-                            return Err(PreProcessorError::InvalidToken {
-                                message: render_error(Diagnostic {
-                                    headline: format!("Invalid token or EOF encountered"),
-                                    line: current_token.source_loc.line,
-                                    source_line: &source_lines
-                                        [current_token.source_loc.line as usize - 1],
-                                    column: current_token.source_loc.column,
-                                    help: Some("A parameter is expected"),
-                                }),
-                            });
-                        }
-                    },
-                    DefinitionDFA::AfterParameter => match current_token.token_type {
-                        TokenType::Symbol => {
-                            if current_token.value.unwrap() != "," {
-                                return Err(PreProcessorError::InvalidToken {
-                                    message: render_error(Diagnostic {
-                                        headline: format!("Invalid token or EOF encountered"),
-                                        line: current_token.source_loc.line,
-                                        source_line: &source_lines
-                                            [current_token.source_loc.line as usize - 1],
-                                        column: current_token.source_loc.column,
-                                        help: Some(
-                                            "A comma followed by another parameter or newline is expected",
-                                        ),
-                                    }),
-                                });
-                            }
-                            state = DefinitionDFA::ExpectAmpersand;
-                        }
-                        TokenType::Whitespace => {}
-                        TokenType::Newline => state = DefinitionDFA::ModelStatements,
-                        _ => {
-                            return Err(PreProcessorError::InvalidToken {
-                                message: render_error(Diagnostic {
-                                    headline: format!("Invalid token or EOF encountered"),
-                                    line: current_token.source_loc.line,
-                                    source_line: &source_lines
-                                        [current_token.source_loc.line as usize - 1],
-                                    column: current_token.source_loc.column,
-                                    help: Some(
-                                        "A comma followed by another parameter or newline is expected",
-                                    ),
-                                }),
-                            });
-                        }
-                    },
-                    DefinitionDFA::ExpectAmpersand => match current_token.token_type {
-                        TokenType::Symbol => {
-                            if current_token.value.unwrap() != "&" {
-                                return Err(PreProcessorError::InvalidToken {
-                                    message: render_error(Diagnostic {
-                                        headline: format!("Invalid token or EOF encountered"),
-                                        line: current_token.source_loc.line,
-                                        source_line: &source_lines
-                                            [current_token.source_loc.line as usize - 1],
-                                        column: current_token.source_loc.column,
-                                        help: Some("A parameter or newline is expected"),
-                                    }),
-                                });
-                            }
-                            state = DefinitionDFA::ExpectParameter;
+                        *state = DefinitionDFA::ExpectParameter;
+                    }
+                    TokenType::Whitespace => {}
+                    _ => {}
+                },
+                DefinitionDFA::ModelStatements => {
+                    if current_token.token_type == TokenType::Eof {
+                        return Err(PreProcessorError::InvalidToken {
+                            message: render_error(Diagnostic {
+                                headline: format!(
+                                    "EOF encountered before the end of the macro definition"
+                                ),
+                                loc: current_token.source_loc,
+                                sources,
+                                help: Some(
+                                    "MEND keyword should follow newline after model statements to end the macro definition",
+                                ),
+                            }),
+                        });
+                    } else {
+                        if current_token.token_type == TokenType::Newline {
+                            *state = DefinitionDFA::MENDOrModelStatements;
                         }
-                        TokenType::Whitespace => {}
-                        _ => {}
-                    },
-                    DefinitionDFA::ModelStatements => {
-                        if current_token.token_type == TokenType::Eof {
-                            return Err(PreProcessorError::InvalidToken {
-                                message: render_error(Diagnostic {
-                                    headline: format!(
-                                        "EOF encountered before the end of the macro definition"
-                                    ),
-                                    line: current_token.source_loc.line,
-                                    source_line: &source_lines
-                                        [current_token.source_loc.line as usize - 1],
-                                    column: current_token.source_loc.column,
-                                    help: Some(
-                                        "MEND keyword should follow newline after model statements to end the macro definition",
-                                    ),
-                                }),
-                            });
+                        self.macros
+                            .get_mut(&self.macro_name)
+                            .unwrap()
+                            .body
+                            .push(current_token);
+                    }
+                }
+                DefinitionDFA::MENDOrModelStatements => match current_token.token_type {
+                    TokenType::Identifier => {
+                        if current_token.value == Some("MEND".to_string()) {
+                            *state = DefinitionDFA::ExpectNewlineOrEof;
                         } else {
-                            if current_token.token_type == TokenType::Newline {
-                                state = DefinitionDFA::MENDOrModelStatements;
-                            }
                             self.macros
                                 .get_mut(&self.macro_name)
                                 .unwrap()
+                                .body
                                 .push(current_token);
+                            *state = DefinitionDFA::ModelStatements;
                         }
                     }
-                    DefinitionDFA::MENDOrModelStatements => match current_token.token_type {
-                        TokenType::Identifier => {
-                            if current_token.value == Some("MEND".to_string()) {
-                                state = DefinitionDFA::ExpectNewlineOrEof;
-                            } else {
-                                self.macros
-                                    .get_mut(&self.macro_name)
-                                    .unwrap()
-                                    .push(current_token);
-                                state = DefinitionDFA::ModelStatements;
-                            }
-                        }
-                        TokenType::Eof => {
-                            return Err(PreProcessorError::InvalidToken {
-                                message: render_error(Diagnostic {
-                                    headline: format!(
-                                        "EOF encountered before the end of the macro definition"
-                                    ),
-                                    line: current_token.source_loc.line,
-                                    source_line: &source_lines
-                                        [current_token.source_loc.line as usize - 1],
-                                    column: current_token.source_loc.column,
-                                    help: Some(
-                                        "MEND keyword is required to denote the end of the macro definition",
+                    TokenType::Eof => {
+                        return Err(PreProcessorError::InvalidToken {
+                            message: render_error(Diagnostic {
+                                headline: format!(
+                                    "EOF encountered before the end of the macro definition"
+                                ),
+                                loc: current_token.source_loc,
+                                sources,
+                                help: Some(
+                                    "MEND keyword is required to denote the end of the macro definition",
+                                ),
+                            }),
+                        });
+                    }
+                    TokenType::Newline => {}
+                    _ => {
+                        self.macros
+                            .get_mut(&self.macro_name)
+                            .unwrap()
+                            .body
+                            .push(current_token);
+                        *state = DefinitionDFA::ModelStatements;
+                    }
+                },
+                DefinitionDFA::ExpectNewlineOrEof => match current_token.token_type {
+                    TokenType::Newline | TokenType::Eof => return Ok(()),
+                    _ => {
+                        return Err(PreProcessorError::InvalidToken {
+                            message: render_error(Diagnostic {
+                                headline: format!("Invalid token encountered"),
+                                loc: current_token.source_loc,
+                                sources,
+                                help: Some("A newline is expected"),
+                            }),
+                        });
+                    }
+                },
+            }
+        }
+    }
+
+    // expands every invocation, one that fails is reported at its line and left out
+    pub fn invocation(
+        &mut self,
+        tokens: &mut TokenStream,
+        sources: &SourceMap,
+    ) -> Vec<Located<PreProcessorError>> {
+        let mut errors = Vec::new();
+        if !self.macros.is_empty() || !self.failed.is_empty() {
+            let mut expanded = Vec::new();
+            for line in lines(mem::take(&mut tokens.tokens)) {
+                let loc = line
+                    .iter()
+                    .find(|token| token.token_type != TokenType::Whitespace)
+                    .map(|token| token.source_loc)
+                    .unwrap_or_default();
+                let terminator = line.last().filter(|token| is_terminator(token)).cloned();
+                let mut expanded_line = Vec::new();
+                match self.expand_line(line, &mut vec![], sources, &mut expanded_line) {
+                    Ok(()) => expanded.extend(expanded_line),
+                    Err(error) => {
+                        errors.push((loc, error));
+                        expanded.extend(terminator);
+                    }
+                }
+            }
+            tokens.tokens = expanded;
+        }
+        tokens.reset();
+        errors
+    }
+
+    fn expand(
+        &self,
+        tokens: Vec<Token>,
+        call_stack: &mut Vec<String>,
+        sources: &SourceMap,
+    ) -> Result<Vec<Token>, PreProcessorError> {
+        let mut expanded = Vec::new();
+        for line in lines(tokens) {
+            self.expand_line(line, call_stack, sources, &mut expanded)?;
+        }
+        Ok(expanded)
+    }
+
+    fn expand_line(
+        &self,
+        mut line: Vec<Token>,
+        call_stack: &mut Vec<String>,
+        sources: &SourceMap,
+        expanded: &mut Vec<Token>,
+    ) -> Result<(), PreProcessorError> {
+        let operation_index = match operation_index(&line) {
+            Some(index)
+                if self
+                    .macros
+                    .contains_key(line[index].value.as_ref().unwrap()) =>
+            {
+                index
+            }
+            // the definition was reported already, only a label in front is kept
+            Some(index) if self.failed.contains(line[index].value.as_ref().unwrap()) => {
+                let rest = line.split_off(index);
+                expanded.extend(line);
+                expanded.extend(rest.into_iter().filter(is_terminator));
+                return Ok(());
+            }
+            _ => {
+                expanded.extend(line);
+                return Ok(());
+            }
+        };
+        let terminator = match line.last() {
+            Some(token) if is_terminator(token) => line.pop(),
+            _ => None,
+        };
+        let call_site = line[operation_index].clone();
+        let name = call_site.value.clone().unwrap();
+        if call_stack.contains(&name) {
+            return Err(PreProcessorError::RecursiveInvocation {
+                message: error_at(
+                    &call_site,
+                    sources,
+                    format!("Recursive invocation of macro '{}'", name),
+                    Some(&format!(
+                        "Expansion chain: {} -> {}",
+                        call_stack.join(" -> "),
+                        name
+                    )),
+                ),
+            });
+        }
+
+        let arguments = split_arguments(line.split_off(operation_index + 1), sources)?;
+        line.truncate(operation_index);
+        let bindings = self.bind_arguments(&name, &call_site, arguments, sources)?;
+        let body = self.substitute(&name, &bindings, sources)?;
+
+        expanded.extend(line);
+        call_stack.push(name);
+        let call_line = sources.main_line(&call_site.source_loc);
+        for mut token in self.expand(body, call_stack, sources)? {
+            token.source_loc.expansion = Some(call_line);
+            expanded.push(token);
+        }
+        call_stack.pop();
+        expanded.extend(terminator);
+        Ok(())
+    }
+
+    fn bind_arguments(
+        &self,
+        name: &str,
+        call_site: &Token,
+        arguments: Vec<Argument>,
+        sources: &SourceMap,
+    ) -> Result<HashMap<String, Vec<Token>>, PreProcessorError> {
+        let parameters = &self.macros[name].parameters;
+        let mut bindings: HashMap<String, Vec<Token>> = HashMap::new();
+        let mut position = 0;
+        for argument in arguments {
+            let (parameter, at) = match argument.keyword {
+                Some(keyword) => {
+                    let keyword_name = keyword.value.as_ref().unwrap();
+                    match parameters.iter().find(|p| &p.name == keyword_name) {
+                        Some(parameter) => (parameter, keyword),
+                        None => {
+                            return Err(PreProcessorError::ArityMismatch {
+                                message: error_at(
+                                    &keyword,
+                                    sources,
+                                    format!(
+                                        "Macro '{}' has no parameter '&{}'",
+                                        name, keyword_name
                                     ),
-                                }),
+                                    None,
+                                ),
                             });
                         }
-                        TokenType::Newline => {}
-                        _ => {
-                            self.macros
-                                .get_mut(&self.macro_name)
-                                .unwrap()
-                                .push(current_token);
-                            state = DefinitionDFA::ModelStatements;
-                        }
-                    },
-                    DefinitionDFA::ExpectNewlineOrEof => match current_token.token_type {
-                        TokenType::Newline | TokenType::Eof => break,
-                        _ => {
-                            return Err(PreProcessorError::InvalidToken {
-                                message: render_error(Diagnostic {
-                                    headline: format!("Invalid token encountered"),
-                                    line: current_token.source_loc.line,
-                                    source_line: &source_lines
-                                        [current_token.source_loc.line as usize - 1],
-                                    column: current_token.source_loc.column,
-                                    help: Some("A newline is expected"),
-                                }),
+                    }
+                }
+                None => {
+                    while position < parameters.len()
+                        && bindings.contains_key(&parameters[position].name)
+                    {
+                        position += 1;
+                    }
+                    match parameters.get(position) {
+                        Some(parameter) => (parameter, argument.value[0].clone()),
+                        None => {
+                            return Err(PreProcessorError::ArityMismatch {
+                                message: error_at(
+                                    &argument.value[0],
+                                    sources,
+                                    format!("Too many arguments for macro '{}'", name),
+                                    Some(&format!(
+                                        "Macro '{}' takes {} parameter(s)",
+                                        name,
+                                        parameters.len()
+                                    )),
+                                ),
                             });
                         }
-                    },
+                    }
                 }
+            };
+            if bindings
+                .insert(parameter.name.clone(), argument.value)
+                .is_some()
+            {
+                return Err(PreProcessorError::ArityMismatch {
+                    message: error_at(
+                        &at,
+                        sources,
+                        format!("Parameter '&{}' is bound more than once", parameter.name),
+                        None,
+                    ),
+                });
             }
         }
+
+        for parameter in parameters {
+            if bindings.contains_key(&parameter.name) {
+                continue;
+            }
+            match &parameter.default {
+                Some(default) => {
+                    bindings.insert(parameter.name.clone(), vec![default.clone()]);
+                }
+                None => {
+                    return Err(PreProcessorError::ArityMismatch {
+                        message: error_at(
+                            call_site,
+                            sources,
+                            format!(
+                                "Missing argument for parameter '&{}' of macro '{}'",
+                                parameter.name, name
+                            ),
+                            Some(&format!(
+                                "Pass it positionally or as {}=<value>",
+                                parameter.name
+                            )),
+                        ),
+                    });
+                }
+            }
+        }
+        Ok(bindings)
     }
 
-    pub fn invocation(&mut self, tokens: &mut TokenStream) -> Result<(), PreProcessorError> {
-        tokens.reset();
-        Ok(())
+    fn substitute(
+        &self,
+        name: &str,
+        bindings: &HashMap<String, Vec<Token>>,
+        sources: &SourceMap,
+    ) -> Result<Vec<Token>, PreProcessorError> {
+        let body = &self.macros[name].body;
+        let mut substituted = Vec::new();
+        let mut i = 0;
+        while i < body.len() {
+            let token = &body[i];
+            let is_reference = is_symbol(token, "&")
+                && body
+                    .get(i + 1)
+                    .is_some_and(|next| next.token_type == TokenType::Identifier)
+                // `&X=value` inside a body is a keyword argument of a nested call
+                && !body.get(i + 2).is_some_and(|next| is_symbol(next, "="));
+            if !is_reference {
+                substituted.push(token.clone());
+                i += 1;
+                continue;
+            }
+            let parameter = &body[i + 1];
+            match bindings.get(parameter.value.as_ref().unwrap()) {
+                Some(value) => substituted.extend(value.iter().cloned()),
+                None => {
+                    return Err(PreProcessorError::InvalidToken {
+                        message: error_at(
+                            parameter,
+                            sources,
+                            format!(
+                                "Unknown parameter '&{}' in the body of macro '{}'",
+                                parameter.value.as_ref().unwrap(),
+                                name
+                            ),
+                            None,
+                        ),
+                    });
+                }
+            }
+            i += 2;
+        }
+        Ok(substituted)
     }
 
     pub fn preprocess(
         &mut self,
         tokens: &mut TokenStream,
-        source_lines: &Vec<String>,
-    ) -> Result<(), PreProcessorError> {
-        self.definition(tokens, source_lines)?;
-        self.invocation(tokens)?;
-        Ok(())
+        sources: &SourceMap,
+    ) -> Result<(), Vec<Located<PreProcessorError>>> {
+        let mut errors = self.definition(tokens, sources);
+        errors.extend(self.invocation(tokens, sources));
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+}
+
+fn is_symbol(token: &Token, symbol: &str) -> bool {
+    token.token_type == TokenType::Symbol && token.value.as_deref() == Some(symbol)
+}
+
+fn is_terminator(token: &Token) -> bool {
+    matches!(token.token_type, TokenType::Newline | TokenType::Eof)
+}
+
+// tokens split after every newline, each line keeps its terminator
+fn lines(tokens: Vec<Token>) -> Vec<Vec<Token>> {
+    let mut lines = Vec::new();
+    let mut line = Vec::new();
+    for token in tokens {
+        let end_of_line = is_terminator(&token);
+        line.push(token);
+        if end_of_line {
+            lines.push(mem::take(&mut line));
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+// removes the rest of a broken definition up to and including the line of its MEND
+fn skip_definition(tokens: &mut TokenStream, state: &DefinitionDFA) {
+    let mut after_mend = *state == DefinitionDFA::ExpectNewlineOrEof;
+    while let Some(token) = tokens.seek(0) {
+        match token.token_type {
+            TokenType::Eof => return,
+            TokenType::Newline if after_mend => {
+                tokens.remove();
+                return;
+            }
+            TokenType::Identifier if token.value.as_deref() == Some("MEND") => after_mend = true,
+            _ => {}
+        }
+        tokens.remove();
+    }
+}
+
+fn error_at(token: &Token, sources: &SourceMap, headline: String, help: Option<&str>) -> String {
+    render_error(Diagnostic {
+        headline,
+        loc: token.source_loc,
+        sources,
+        help,
+    })
+}
+
+// index of the token in operation position, skipping an optional `LABEL:` prefix
+fn operation_index(line: &[Token]) -> Option<usize> {
+    let mut significant = line
+        .iter()
+        .enumerate()
+        .filter(|(_, token)| token.token_type != TokenType::Whitespace);
+    let (index, first) = significant.next()?;
+    if first.token_type != TokenType::Identifier {
+        return None;
+    }
+    match significant.next() {
+        Some((_, token)) if is_symbol(token, ":") => match significant.next() {
+            Some((index, token)) if token.token_type == TokenType::Identifier => Some(index),
+            _ => None,
+        },
+        _ => Some(index),
+    }
+}
+
+fn split_arguments(
+    tokens: Vec<Token>,
+    sources: &SourceMap,
+) -> Result<Vec<Argument>, PreProcessorError> {
+    let mut arguments = Vec::new();
+    let mut current = Vec::new();
+    let mut last_comma = None;
+    for token in tokens {
+        if is_symbol(&token, ",") {
+            arguments.push(argument(mem::take(&mut current), &token, sources)?);
+            last_comma = Some(token);
+        } else {
+            current.push(token);
+        }
+    }
+    match last_comma {
+        Some(comma) => arguments.push(argument(current, &comma, sources)?),
+        None => {
+            if current
+                .iter()
+                .any(|token| token.token_type != TokenType::Whitespace)
+            {
+                let first = current[0].clone();
+                arguments.push(argument(current, &first, sources)?);
+            }
+        }
+    }
+    Ok(arguments)
+}
+
+fn argument(
+    mut value: Vec<Token>,
+    separator: &Token,
+    sources: &SourceMap,
+) -> Result<Argument, PreProcessorError> {
+    trim_whitespace(&mut value);
+    let mut keyword = None;
+    let skip = if value.first().is_some_and(|token| is_symbol(token, "&")) {
+        1
+    } else {
+        0
+    };
+    let name = value
+        .get(skip)
+        .filter(|t| t.token_type == TokenType::Identifier);
+    let equals = value.get(skip + 1).filter(|t| is_symbol(t, "="));
+    if name.is_some() && equals.is_some() {
+        keyword = name.cloned();
+        value.drain(..skip + 2);
+        trim_whitespace(&mut value);
+    }
+    if value.is_empty() {
+        return Err(PreProcessorError::ArityMismatch {
+            message: error_at(
+                keyword.as_ref().unwrap_or(separator),
+                sources,
+                "Empty macro argument".to_string(),
+                Some("Every argument must have a value"),
+            ),
+        });
+    }
+    Ok(Argument { keyword, value })
+}
+
+fn trim_whitespace(tokens: &mut Vec<Token>) {
+    while tokens
+        .last()
+        .is_some_and(|token| token.token_type == TokenType::Whitespace)
+    {
+        tokens.pop();
+    }
+    let leading = tokens
+        .iter()
+        .take_while(|token| token.token_type == TokenType::Whitespace)
+        .count();
+    tokens.drain(..leading);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::lexer::{Lexer, token::TokenType},
+        *,
+    };
+
+    fn expand(program: &str) -> Result<String, PreProcessorError> {
+        let sources = SourceMap::from_program(program);
+        let (mut tokens, _) = Lexer::new().lex(0, &sources);
+        PreProcessor::new()
+            .preprocess(&mut tokens, &sources)
+            .map_err(|mut errors| errors.remove(0).1)?;
+        Ok(tokens
+            .tokens
+            .iter()
+            .map(|token| match token.token_type {
+                TokenType::Whitespace => " ".to_string(),
+                TokenType::Newline => "\n".to_string(),
+                TokenType::Eof => String::new(),
+                _ => token.value.clone().unwrap(),
+            })
+            .collect::<String>()
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+
+    #[test]
+    fn test_positional_arguments() {
+        let program =
+            "MACRO\nLOAD &REG, &VALUE\nMOVEI &REG, &VALUE\nOUT &REG\nMEND\nLOAD R1, 5\nHALT";
+        assert_eq!(expand(program).unwrap(), "MOVEI R1, 5\nOUT R1\nHALT");
+    }
+
+    #[test]
+    fn test_keyword_arguments_and_defaults() {
+        let program = "MACRO\nLOAD &REG=R0, &VALUE=1\nMOVEI &REG, &VALUE\nMEND\nLOAD VALUE=7\nSTART: LOAD &REG=R2";
+        assert_eq!(expand(program).unwrap(), "MOVEI R0, 7\nSTART: MOVEI R2, 1");
+    }
+
+    #[test]
+    fn test_nested_invocation() {
+        let program = "MACRO\nSHOW &REG\nOUT &REG\nMEND\nMACRO\nLOAD &REG, &VALUE\nMOVEI &REG, &VALUE\nSHOW &REG\nMEND\nLOAD R3, -2";
+        assert_eq!(expand(program).unwrap(), "MOVEI R3, -2\nOUT R3");
+    }
+
+    #[test]
+    fn test_recursive_invocation() {
+        let program = "MACRO\nPING\nPONG\nMEND\nMACRO\nPONG\nPING\nMEND\nPING";
+        assert!(matches!(
+            expand(program),
+            Err(PreProcessorError::RecursiveInvocation { .. })
+        ));
+    }
+
+    #[test]
+    fn test_arity_mismatch() {
+        let program = "MACRO\nLOAD &REG, &VALUE\nMOVEI &REG, &VALUE\nMEND\n";
+        let missing = expand(&format!("{program}LOAD R1"));
+        assert!(matches!(
+            missing,
+            Err(PreProcessorError::ArityMismatch { .. })
+        ));
+        let too_many = expand(&format!("{program}LOAD R1, 2, 3"));
+        assert!(matches!(
+            too_many,
+            Err(PreProcessorError::ArityMismatch { .. })
+        ));
+        let unknown = expand(&format!("{program}LOAD R1, COUNT=2"));
+        assert!(matches!(
+            unknown,
+            Err(PreProcessorError::ArityMismatch { .. })
+        ));
     }
 }
@@ -1,20 +1,44 @@
+use crate::{lexer::token::SourceLoc, source_map::SourceMap};
+
+// an error with where it was found, so the errors of every stage can be reported in source order
+pub type Located<E> = (SourceLoc, E);
+
 pub struct Diagnostic<'a> {
     pub headline: String,
-    pub line: u32,
-    pub source_line: &'a str,
-    pub column: u32,
+    pub loc: SourceLoc,
+    pub sources: &'a SourceMap,
     pub help: Option<&'a str>,
 }
 pub fn render_error(diagnostic: Diagnostic) -> String {
-    let digit_count = diagnostic.line.to_string().len();
+    let loc = diagnostic.loc;
+    let digit_count = loc.line.to_string().len();
+    let name = diagnostic.sources.name(loc.file);
+    let includes: String = diagnostic
+        .sources
+        .include_chain(&loc)
+        .iter()
+        .map(|include| {
+            format!(
+                "note: included from {}:{}\n",
+                diagnostic.sources.name(include.file),
+                include.line
+            )
+        })
+        .collect();
     format!(
-        "{} at:\n{} |\n{} | {}\n{} | {}\n{}",
+        "{} at{}:\n{} |\n{} | {}\n{} | {}\n{}{}",
         diagnostic.headline,
+        if name.is_empty() {
+            String::new()
+        } else {
+            format!(" {}:{}:{}", name, loc.line, loc.column)
+        },
         " ".repeat(digit_count),
-        diagnostic.line,
-        diagnostic.source_line,
+        loc.line,
+        diagnostic.sources.line(&loc),
         " ".repeat(digit_count),
-        " ".repeat(diagnostic.column as usize - 1) + "^",
+        " ".repeat(loc.column as usize - 1) + "^",
+        includes,
         match diagnostic.help {
             Some(help) => format!("help: {}", help),
             None => "".to_string(),
@@ -0,0 +1,85 @@
+use std::path::{Path, PathBuf};
+
+use crate::lexer::token::SourceLoc;
+
+pub struct SourceFile {
+    // as written on the command line or in the INCLUDE, empty for an unnamed program
+    pub name: String,
+    pub path: PathBuf,
+    pub lines: Vec<String>,
+    pub included_from: Option<SourceLoc>,
+}
+
+// every file of one assembly, a `SourceLoc` refers to them by their index
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self { files: Vec::new() }
+    }
+
+    // a single unnamed program, for tests that skip the includer
+    #[cfg(test)]
+    pub fn from_program(text: &str) -> Self {
+        let mut sources = Self::new();
+        sources.add("", Path::new(""), text, None);
+        sources
+    }
+
+    pub fn add(
+        &mut self,
+        name: &str,
+        path: &Path,
+        text: &str,
+        included_from: Option<SourceLoc>,
+    ) -> u32 {
+        self.files.push(SourceFile {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+            lines: text.split('\n').map(|line| line.to_string()).collect(),
+            included_from,
+        });
+        self.files.len() as u32 - 1
+    }
+
+    pub fn file(&self, file: u32) -> &SourceFile {
+        &self.files[file as usize]
+    }
+
+    pub fn name(&self, file: u32) -> &str {
+        &self.file(file).name
+    }
+
+    pub fn lines(&self, file: u32) -> &[String] {
+        &self.file(file).lines
+    }
+
+    pub fn line(&self, loc: &SourceLoc) -> &str {
+        self.files
+            .get(loc.file as usize)
+            .and_then(|file| file.lines.get((loc.line as usize).wrapping_sub(1)))
+            .map(|line| line.as_str())
+            .unwrap_or("")
+    }
+
+    // the line of the file being assembled that `loc` ends up on, which is the
+    // outermost INCLUDE for code from included files and the call for expanded code
+    pub fn main_line(&self, loc: &SourceLoc) -> u32 {
+        loc.expansion
+            .unwrap_or_else(|| self.include_chain(loc).last().unwrap_or(loc).line)
+    }
+
+    // the INCLUDE lines leading to `loc`, innermost first
+    pub fn include_chain(&self, loc: &SourceLoc) -> Vec<SourceLoc> {
+        let mut chain = Vec::new();
+        let mut file = loc.file;
+        while let Some(include) = self.file(file).included_from {
+            chain.push(include);
+            file = include.file;
+        }
+        chain
+    }
+}
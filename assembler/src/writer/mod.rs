@@ -1,9 +1,12 @@
 use std::{
+    collections::HashMap,
     fs::File,
-    io::{self, Write},
+    io::{self, BufWriter, Write},
+    path::Path,
 };
 
-use super::encoder::delimiter::DelimiterTable;
+use super::{encoder::delimiter::DelimiterTable, listing::Listing};
+use container::{Container, Object};
 
 #[derive(Debug, thiserror::Error)]
 pub enum WriterError {
@@ -11,38 +14,61 @@ pub enum WriterError {
     Io(#[from] io::Error),
 }
 
+pub const DEFAULT_OUTPUT: &str = "output.bin";
+pub const DEFAULT_OBJECT_OUTPUT: &str = "output.o";
+// stands for stdin as an input and stdout as an output
+pub const STDIO: &str = "-";
+
 pub struct Writer {
     debug: bool,
     pretty: bool,
-    bin_file: File,
+    bin_file: BufWriter<Box<dyn Write>>,
     debug_file: Option<File>,
 }
 
+// `out.bin` gets `out.debug.txt`, so parallel builds don't share one debug file
+fn debug_path(output: &str) -> String {
+    if output == DEFAULT_OUTPUT || output == STDIO {
+        String::from("debug.txt")
+    } else {
+        Path::new(output)
+            .with_extension("debug.txt")
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
 impl Writer {
-    pub fn new(debug: bool, pretty: bool) -> Result<Self, WriterError> {
+    pub fn new(output: &str, debug: bool, pretty: bool) -> Result<Self, WriterError> {
+        let bin_file: Box<dyn Write> = if output == STDIO {
+            Box::new(io::stdout())
+        } else {
+            Box::new(File::create(output)?)
+        };
         Ok(Self {
             debug,
             pretty,
-            bin_file: File::create("output.bin")?,
+            bin_file: BufWriter::new(bin_file),
             debug_file: if debug {
-                Some(File::create("debug.txt")?)
+                Some(File::create(debug_path(output))?)
             } else {
                 None
             },
         })
     }
 
+    // the debug file only shows the code section, the container framing is left out
     pub fn write(
         &mut self,
-        bytes_stream: Vec<u8>,
+        container: &Container,
         delimiter_table: &mut DelimiterTable,
     ) -> Result<(), WriterError> {
+        self.bin_file.write_all(&container.to_bytes())?;
         let mut bits_written = 0 as usize;
         if let None = delimiter_table.get_current() {
             delimiter_table.next();
         }
-        bytes_stream.iter().try_for_each(|&byte| {
-            self.bin_file.write_all(&[byte])?;
+        container.code.iter().try_for_each(|&byte| {
             if self.debug {
                 let mut debug_file: &File;
                 match &self.debug_file {
@@ -64,6 +90,35 @@ impl Writer {
             }
             Ok::<(), WriterError>(())
         })?;
+        self.bin_file.flush()?;
         Ok(())
     }
 }
+
+// one `LABEL BIT-ADDRESS` line per label in address order, as read by the debugger and disassembler
+pub fn write_symbols(path: &str, symbols: &HashMap<String, u32>) -> Result<(), WriterError> {
+    let mut symbols: Vec<(&String, &u32)> = symbols.iter().collect();
+    symbols.sort_by_key(|(label, address)| (**address, label.as_str()));
+    let mut file = File::create(path)?;
+    for (label, address) in symbols {
+        writeln!(file, "{} {}", label, address)?;
+    }
+    Ok(())
+}
+
+pub fn write_object(output: &str, object: &Object) -> Result<(), WriterError> {
+    if output == STDIO {
+        let mut stdout = io::stdout();
+        stdout.write_all(&object.to_bytes())?;
+        stdout.flush()?;
+    } else {
+        File::create(output)?.write_all(&object.to_bytes())?;
+    }
+    Ok(())
+}
+
+pub fn write_listing(path: &str, listing: &Listing) -> Result<(), WriterError> {
+    let mut file = File::create(path)?;
+    write!(file, "{}", listing)?;
+    Ok(())
+}
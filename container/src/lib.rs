@@ -0,0 +1,384 @@
+mod object;
+
+use std::collections::BTreeMap;
+
+pub use object::{OBJECT_MAGIC, Object, Relocation, Section, Target};
+
+pub const MAGIC: &[u8; 4] = b"VMBN";
+pub const VERSION: u16 = 1;
+// ISA identifier of binaries read by the legacy loader, which never recorded one
+pub const UNKNOWN_ISA: u32 = 0;
+
+const CODE_SECTION: u8 = 1;
+const DATA_SECTION: u8 = 2;
+const SYMBOL_SECTION: u8 = 3;
+const DEBUG_SECTION: u8 = 4;
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum ContainerError {
+    #[error("Binary is truncated")]
+    Truncated,
+    #[error("Not a VM binary (bad magic bytes)")]
+    BadMagic,
+    #[error("Unsupported binary version {0}, expected {VERSION}")]
+    UnsupportedVersion(u16),
+    #[error("Checksum mismatch: stored {stored:08X}, computed {computed:08X}")]
+    ChecksumMismatch { stored: u32, computed: u32 },
+    #[error("Binary was built for ISA {found:08X}, this VM implements {expected:08X}")]
+    IsaMismatch { expected: u32, found: u32 },
+    #[error("Binary has no code section")]
+    MissingCode,
+    #[error("Section {0} appears more than once")]
+    DuplicateSection(u8),
+    #[error("Unknown section {0}")]
+    UnknownSection(u8),
+    #[error("Section {kind} has {count} unexpected trailing bytes")]
+    TrailingBytes { kind: u8, count: usize },
+    #[error("Code claims {bits} bits but only holds {available}")]
+    CodeLength { bits: u32, available: u32 },
+    #[error("Entry point {entry} is outside the {bits} bits of code")]
+    InvalidEntry { entry: u32, bits: u32 },
+    #[error("Invalid symbol name in symbol section")]
+    InvalidSymbol,
+    #[error("Invalid relocation target in object file")]
+    InvalidRelocation,
+    #[error("Relocation at bit {offset} is outside the {bits} bits of code")]
+    RelocationOutOfCode { offset: u32, bits: u32 },
+}
+
+// Layout (version 1), integers are big endian:
+//   "VMBN" version:u16 isa:u32 entry:u32 section_count:u8
+//   { kind:u8 length:u32 payload:[u8] } crc32:u32 (over everything before it)
+// Payloads:
+//   code    bits:u32 bytes:[u8]
+//   data    bytes:[u8], loaded at data memory address 0
+//   symbols count:u16 { name_len:u8 name:[u8] address:u32 }
+//   debug   count:u32 { address:u32 line:u32 }
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Container {
+    pub isa: u32,
+    pub entry: u32,
+    pub code: Vec<u8>,
+    pub code_bits: u32,
+    pub data: Vec<u8>,
+    pub symbols: Option<BTreeMap<String, u32>>,
+    // (bit address, source line) for every instruction
+    pub debug: Option<Vec<(u32, u32)>>,
+}
+
+impl Container {
+    pub fn new(isa: u32, code: Vec<u8>, code_bits: u32) -> Self {
+        Self {
+            isa,
+            code,
+            code_bits,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_container(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    // containers by magic, anything else through the legacy loader
+    pub fn parse(bytes: &[u8]) -> Result<Self, ContainerError> {
+        if Self::is_container(bytes) {
+            Self::from_bytes(bytes)
+        } else {
+            Self::from_legacy(bytes)
+        }
+    }
+
+    // the original format: the packed bitstream followed by its bit length as a big endian u32
+    pub fn from_legacy(bytes: &[u8]) -> Result<Self, ContainerError> {
+        if bytes.len() < 4 {
+            return Err(ContainerError::Truncated);
+        }
+        let (code, length) = bytes.split_at(bytes.len() - 4);
+        let code_bits = u32::from_be_bytes([length[0], length[1], length[2], length[3]]);
+        let container = Self::new(UNKNOWN_ISA, code.to_vec(), code_bits);
+        container.validate()?;
+        Ok(container)
+    }
+
+    pub fn check_isa(&self, expected: u32) -> Result<(), ContainerError> {
+        if self.isa != UNKNOWN_ISA && self.isa != expected {
+            return Err(ContainerError::IsaMismatch {
+                expected,
+                found: self.isa,
+            });
+        }
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), ContainerError> {
+        let available = self.code.len() as u32 * 8;
+        if self.code_bits > available {
+            return Err(ContainerError::CodeLength {
+                bits: self.code_bits,
+                available,
+            });
+        }
+        if self.entry != 0 && self.entry >= self.code_bits {
+            return Err(ContainerError::InvalidEntry {
+                entry: self.entry,
+                bits: self.code_bits,
+            });
+        }
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut sections = Vec::new();
+
+        let mut code = self.code_bits.to_be_bytes().to_vec();
+        code.extend(&self.code);
+        sections.push((CODE_SECTION, code));
+        if !self.data.is_empty() {
+            sections.push((DATA_SECTION, self.data.clone()));
+        }
+        if let Some(symbols) = &self.symbols {
+            let mut payload = (symbols.len() as u16).to_be_bytes().to_vec();
+            for (name, address) in symbols {
+                payload.push(name.len() as u8);
+                payload.extend(name.as_bytes());
+                payload.extend(address.to_be_bytes());
+            }
+            sections.push((SYMBOL_SECTION, payload));
+        }
+        if let Some(debug) = &self.debug {
+            let mut payload = (debug.len() as u32).to_be_bytes().to_vec();
+            for (address, line) in debug {
+                payload.extend(address.to_be_bytes());
+                payload.extend(line.to_be_bytes());
+            }
+            sections.push((DEBUG_SECTION, payload));
+        }
+
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(VERSION.to_be_bytes());
+        bytes.extend(self.isa.to_be_bytes());
+        bytes.extend(self.entry.to_be_bytes());
+        bytes.push(sections.len() as u8);
+        for (kind, payload) in sections {
+            bytes.push(kind);
+            bytes.extend((payload.len() as u32).to_be_bytes());
+            bytes.extend(payload);
+        }
+        bytes.extend(crc32(&bytes).to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ContainerError> {
+        if bytes.len() < MAGIC.len() + 4 {
+            return Err(ContainerError::Truncated);
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 4);
+        let mut reader = Reader { bytes: body };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(ContainerError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(ContainerError::UnsupportedVersion(version));
+        }
+        let stored = u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+        let computed = crc32(body);
+        if stored != computed {
+            return Err(ContainerError::ChecksumMismatch { stored, computed });
+        }
+
+        let mut container = Self {
+            isa: reader.u32()?,
+            entry: reader.u32()?,
+            ..Default::default()
+        };
+        let mut seen = Vec::new();
+        for _ in 0..reader.u8()? {
+            let kind = reader.u8()?;
+            if seen.contains(&kind) {
+                return Err(ContainerError::DuplicateSection(kind));
+            }
+            seen.push(kind);
+            let length = reader.u32()? as usize;
+            let mut section = Reader {
+                bytes: reader.take(length)?,
+            };
+            match kind {
+                CODE_SECTION => {
+                    container.code_bits = section.u32()?;
+                    container.code = section.take(section.bytes.len())?.to_vec();
+                }
+                DATA_SECTION => container.data = section.take(section.bytes.len())?.to_vec(),
+                SYMBOL_SECTION => {
+                    let mut symbols = BTreeMap::new();
+                    for _ in 0..section.u16()? {
+                        let name_len = section.u8()? as usize;
+                        let name = String::from_utf8(section.take(name_len)?.to_vec())
+                            .map_err(|_| ContainerError::InvalidSymbol)?;
+                        symbols.insert(name, section.u32()?);
+                    }
+                    container.symbols = Some(symbols);
+                }
+                DEBUG_SECTION => {
+                    let mut debug = Vec::new();
+                    for _ in 0..section.u32()? {
+                        debug.push((section.u32()?, section.u32()?));
+                    }
+                    container.debug = Some(debug);
+                }
+                kind => return Err(ContainerError::UnknownSection(kind)),
+            }
+            if !section.bytes.is_empty() {
+                return Err(ContainerError::TrailingBytes {
+                    kind,
+                    count: section.bytes.len(),
+                });
+            }
+        }
+        if !seen.contains(&CODE_SECTION) {
+            return Err(ContainerError::MissingCode);
+        }
+        if !reader.bytes.is_empty() {
+            return Err(ContainerError::TrailingBytes {
+                kind: 0,
+                count: reader.bytes.len(),
+            });
+        }
+        container.validate()?;
+        Ok(container)
+    }
+}
+
+// CRC-32 (IEEE 802.3), as used by zip and png
+pub fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF_u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], ContainerError> {
+        if self.bytes.len() < count {
+            return Err(ContainerError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, ContainerError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ContainerError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, ContainerError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn container() -> Container {
+        let mut container = Container::new(0xC0FFEE, vec![4, 0], 12);
+        container.data = vec![1, 2, 3];
+        container.symbols = Some(BTreeMap::from([(String::from("MOVE"), 0)]));
+        container.debug = Some(vec![(0, 2)]);
+        container
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let container = container();
+        let bytes = container.to_bytes();
+        assert!(Container::is_container(&bytes));
+        assert_eq!(Container::parse(&bytes).unwrap(), container);
+
+        let stripped = Container::new(0xC0FFEE, vec![4, 0], 12);
+        assert_eq!(Container::parse(&stripped.to_bytes()).unwrap(), stripped);
+    }
+
+    #[test]
+    fn test_legacy() {
+        let container = Container::parse(&[4, 0, 0, 0, 0, 12]).unwrap();
+        assert_eq!(container.code, vec![4, 0]);
+        assert_eq!(container.code_bits, 12);
+        assert_eq!(container.isa, UNKNOWN_ISA);
+        assert!(container.check_isa(0x1234).is_ok());
+        assert_eq!(Container::parse(&[0, 0, 9]), Err(ContainerError::Truncated));
+        assert_eq!(
+            Container::parse(&[4, 0, 0, 0, 0, 17]),
+            Err(ContainerError::CodeLength {
+                bits: 17,
+                available: 16
+            })
+        );
+    }
+
+    #[test]
+    fn test_invalid_containers() {
+        let bytes = container().to_bytes();
+
+        let mut corrupted = bytes.clone();
+        corrupted[20] ^= 0xFF;
+        assert!(matches!(
+            Container::from_bytes(&corrupted),
+            Err(ContainerError::ChecksumMismatch { .. })
+        ));
+
+        let mut version = bytes.clone();
+        version[5] = 9;
+        assert_eq!(
+            Container::from_bytes(&version),
+            Err(ContainerError::UnsupportedVersion(9))
+        );
+
+        assert_eq!(
+            Container::from_bytes(&bytes[..6]),
+            Err(ContainerError::Truncated)
+        );
+        assert_eq!(
+            Container::from_bytes(b"ELF\x7f1234"),
+            Err(ContainerError::BadMagic)
+        );
+
+        let mut entry = container();
+        entry.entry = 12;
+        assert_eq!(
+            Container::from_bytes(&entry.to_bytes()),
+            Err(ContainerError::InvalidEntry {
+                entry: 12,
+                bits: 12
+            })
+        );
+
+        assert_eq!(
+            container().check_isa(0x1234),
+            Err(ContainerError::IsaMismatch {
+                expected: 0x1234,
+                found: 0xC0FFEE
+            })
+        );
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+}
@@ -0,0 +1,305 @@
+use std::collections::BTreeMap;
+
+use super::{ContainerError, Reader, crc32};
+
+pub const OBJECT_MAGIC: &[u8; 4] = b"VMOB";
+pub const OBJECT_VERSION: u16 = 1;
+
+const CODE_SECTION: u8 = 1;
+const DATA_SECTION: u8 = 2;
+const GLOBAL_SECTION: u8 = 3;
+const EXTERN_SECTION: u8 = 4;
+const RELOCATION_SECTION: u8 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Section {
+    Code,
+    Data,
+}
+
+// what a relocated field holds once the objects are laid out, plus the relocation's addend
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    // the start of this object's code or data
+    Section(Section),
+    // a GLOBAL of any object
+    Symbol(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relocation {
+    // bit address of the field in this object's code
+    pub offset: u32,
+    pub bit_count: u8,
+    pub target: Target,
+    pub addend: i32,
+}
+
+// Layout (version 1), integers are big endian:
+//   "VMOB" version:u16 isa:u32 section_count:u8
+//   { kind:u8 length:u32 payload:[u8] } crc32:u32 (over everything before it)
+// Payloads:
+//   code         bits:u32 bytes:[u8]
+//   data         bytes:[u8]
+//   globals      count:u16 { name_len:u8 name:[u8] section:u8 address:u32 }
+//   externs      count:u16 { name_len:u8 name:[u8] }
+//   relocations  count:u32 { offset:u32 bit_count:u8 target:u8 [name_len:u8 name:[u8]] addend:i32 }
+// section and target are 1 for code and 2 for data, target 3 is a symbol followed by its name
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Object {
+    pub isa: u32,
+    pub code: Vec<u8>,
+    pub code_bits: u32,
+    pub data: Vec<u8>,
+    // exported symbols, addresses are relative to the start of their section
+    pub globals: BTreeMap<String, (Section, u32)>,
+    // symbols this object uses but another object defines
+    pub externs: Vec<String>,
+    pub relocations: Vec<Relocation>,
+}
+
+fn push_name(payload: &mut Vec<u8>, name: &str) {
+    payload.push(name.len() as u8);
+    payload.extend(name.as_bytes());
+}
+
+fn read_name(reader: &mut Reader) -> Result<String, ContainerError> {
+    let length = reader.u8()? as usize;
+    String::from_utf8(reader.take(length)?.to_vec()).map_err(|_| ContainerError::InvalidSymbol)
+}
+
+fn section_kind(section: Section) -> u8 {
+    match section {
+        Section::Code => CODE_SECTION,
+        Section::Data => DATA_SECTION,
+    }
+}
+
+fn read_section(kind: u8) -> Result<Section, ContainerError> {
+    match kind {
+        CODE_SECTION => Ok(Section::Code),
+        DATA_SECTION => Ok(Section::Data),
+        _ => Err(ContainerError::InvalidRelocation),
+    }
+}
+
+impl Object {
+    pub fn is_object(bytes: &[u8]) -> bool {
+        bytes.starts_with(OBJECT_MAGIC)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut sections = Vec::new();
+
+        let mut code = self.code_bits.to_be_bytes().to_vec();
+        code.extend(&self.code);
+        sections.push((CODE_SECTION, code));
+        if !self.data.is_empty() {
+            sections.push((DATA_SECTION, self.data.clone()));
+        }
+        if !self.globals.is_empty() {
+            let mut payload = (self.globals.len() as u16).to_be_bytes().to_vec();
+            for (name, (section, address)) in &self.globals {
+                push_name(&mut payload, name);
+                payload.push(section_kind(*section));
+                payload.extend(address.to_be_bytes());
+            }
+            sections.push((GLOBAL_SECTION, payload));
+        }
+        if !self.externs.is_empty() {
+            let mut payload = (self.externs.len() as u16).to_be_bytes().to_vec();
+            for name in &self.externs {
+                push_name(&mut payload, name);
+            }
+            sections.push((EXTERN_SECTION, payload));
+        }
+        if !self.relocations.is_empty() {
+            let mut payload = (self.relocations.len() as u32).to_be_bytes().to_vec();
+            for relocation in &self.relocations {
+                payload.extend(relocation.offset.to_be_bytes());
+                payload.push(relocation.bit_count);
+                match &relocation.target {
+                    Target::Section(section) => payload.push(section_kind(*section)),
+                    Target::Symbol(name) => {
+                        payload.push(3);
+                        push_name(&mut payload, name);
+                    }
+                }
+                payload.extend(relocation.addend.to_be_bytes());
+            }
+            sections.push((RELOCATION_SECTION, payload));
+        }
+
+        let mut bytes = OBJECT_MAGIC.to_vec();
+        bytes.extend(OBJECT_VERSION.to_be_bytes());
+        bytes.extend(self.isa.to_be_bytes());
+        bytes.push(sections.len() as u8);
+        for (kind, payload) in sections {
+            bytes.push(kind);
+            bytes.extend((payload.len() as u32).to_be_bytes());
+            bytes.extend(payload);
+        }
+        bytes.extend(crc32(&bytes).to_be_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ContainerError> {
+        if bytes.len() < OBJECT_MAGIC.len() + 4 {
+            return Err(ContainerError::Truncated);
+        }
+        let (body, checksum) = bytes.split_at(bytes.len() - 4);
+        let mut reader = Reader { bytes: body };
+        if reader.take(OBJECT_MAGIC.len())? != OBJECT_MAGIC {
+            return Err(ContainerError::BadMagic);
+        }
+        let version = reader.u16()?;
+        if version != OBJECT_VERSION {
+            return Err(ContainerError::UnsupportedVersion(version));
+        }
+        let stored = u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+        let computed = crc32(body);
+        if stored != computed {
+            return Err(ContainerError::ChecksumMismatch { stored, computed });
+        }
+
+        let mut object = Self {
+            isa: reader.u32()?,
+            ..Default::default()
+        };
+        let mut seen = Vec::new();
+        for _ in 0..reader.u8()? {
+            let kind = reader.u8()?;
+            if seen.contains(&kind) {
+                return Err(ContainerError::DuplicateSection(kind));
+            }
+            seen.push(kind);
+            let length = reader.u32()? as usize;
+            let mut section = Reader {
+                bytes: reader.take(length)?,
+            };
+            match kind {
+                CODE_SECTION => {
+                    object.code_bits = section.u32()?;
+                    object.code = section.take(section.bytes.len())?.to_vec();
+                }
+                DATA_SECTION => object.data = section.take(section.bytes.len())?.to_vec(),
+                GLOBAL_SECTION => {
+                    for _ in 0..section.u16()? {
+                        let name = read_name(&mut section)?;
+                        let kind = read_section(section.u8()?)?;
+                        object.globals.insert(name, (kind, section.u32()?));
+                    }
+                }
+                EXTERN_SECTION => {
+                    for _ in 0..section.u16()? {
+                        object.externs.push(read_name(&mut section)?);
+                    }
+                }
+                RELOCATION_SECTION => {
+                    for _ in 0..section.u32()? {
+                        let offset = section.u32()?;
+                        let bit_count = section.u8()?;
+                        let target = match section.u8()? {
+                            3 => Target::Symbol(read_name(&mut section)?),
+                            kind => Target::Section(read_section(kind)?),
+                        };
+                        let addend = section.u32()? as i32;
+                        object.relocations.push(Relocation {
+                            offset,
+                            bit_count,
+                            target,
+                            addend,
+                        });
+                    }
+                }
+                kind => return Err(ContainerError::UnknownSection(kind)),
+            }
+            if !section.bytes.is_empty() {
+                return Err(ContainerError::TrailingBytes {
+                    kind,
+                    count: section.bytes.len(),
+                });
+            }
+        }
+        if !seen.contains(&CODE_SECTION) {
+            return Err(ContainerError::MissingCode);
+        }
+        if !reader.bytes.is_empty() {
+            return Err(ContainerError::TrailingBytes {
+                kind: 0,
+                count: reader.bytes.len(),
+            });
+        }
+        let available = object.code.len() as u32 * 8;
+        if object.code_bits > available {
+            return Err(ContainerError::CodeLength {
+                bits: object.code_bits,
+                available,
+            });
+        }
+        if let Some(relocation) = object.relocations.iter().find(|relocation| {
+            relocation.bit_count > 32
+                || relocation
+                    .offset
+                    .saturating_add(relocation.bit_count as u32)
+                    > object.code_bits
+        }) {
+            return Err(ContainerError::RelocationOutOfCode {
+                offset: relocation.offset,
+                bits: object.code_bits,
+            });
+        }
+        Ok(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object_round_trip() {
+        let object = Object {
+            isa: 0xC0FFEE,
+            code: vec![0x54, 0x00],
+            code_bits: 14,
+            data: vec![7],
+            globals: BTreeMap::from([
+                (String::from("MAIN"), (Section::Code, 0)),
+                (String::from("COUNT"), (Section::Data, 0)),
+            ]),
+            externs: vec![String::from("PRINT")],
+            relocations: vec![
+                Relocation {
+                    offset: 6,
+                    bit_count: 8,
+                    target: Target::Symbol(String::from("PRINT")),
+                    addend: -1,
+                },
+                Relocation {
+                    offset: 6,
+                    bit_count: 8,
+                    target: Target::Section(Section::Data),
+                    addend: 0,
+                },
+            ],
+        };
+        let bytes = object.to_bytes();
+        assert!(Object::is_object(&bytes));
+        assert_eq!(Object::from_bytes(&bytes).unwrap(), object);
+
+        let mut outside = object.clone();
+        outside.relocations[0].offset = 10;
+        assert_eq!(
+            Object::from_bytes(&outside.to_bytes()),
+            Err(ContainerError::RelocationOutOfCode {
+                offset: 10,
+                bits: 14
+            })
+        );
+        assert_eq!(
+            Object::from_bytes(&Object::default().to_bytes()[..8]),
+            Err(ContainerError::Truncated)
+        );
+    }
+}
@@ -6,10 +6,28 @@ def generateBinary(byte_value):
     padded_binary = binary.zfill(8)
     return padded_binary
 
+# return the code section of a container, legacy binaries are returned as they are
+def codeSection(content):
+    if not content.startswith(b'VMBN'):
+        return content
+    # magic, version, isa and entry point come before the section count
+    offset = 14
+    section_count = content[offset]
+    offset += 1
+    for _ in range(section_count):
+        kind = content[offset]
+        length = int.from_bytes(content[offset + 1:offset + 5], 'big')
+        offset += 5
+        if kind == 1:
+            # skip the bit length at the start of the code section
+            return content[offset + 4:offset + length]
+        offset += length
+    return b''
+
 def readFile(filename):
     try:
         with open(filename, 'rb') as file:
-            content = file.read()
+            content = codeSection(file.read())
             # Convert each byte to its binary representation
             binary_content = ''.join(generateBinary(byte_value) for byte_value in content)
             print(f"Binary content of {filename}:\n{binary_content}")
@@ -0,0 +1,400 @@
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    ops::Range,
+};
+
+use container::{Container, ContainerError, UNKNOWN_ISA};
+use isa::{IsaError, OperandType, OptSpec};
+use vm::{
+    instruction::{DecodedInstruction, InstructionError},
+    symbols::{SymbolError, parse_symbols},
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum DisassemblerError {
+    #[error("Invalid binary: {0}")]
+    Container(#[from] ContainerError),
+    #[error("Failed to decode the instruction at bit {address}: {source}")]
+    Decode {
+        address: u32,
+        source: InstructionError,
+    },
+    #[error("{0}")]
+    Symbols(#[from] SymbolError),
+    #[error("{0}")]
+    Isa(#[from] IsaError),
+}
+
+#[derive(Clone)]
+pub struct Disassembler {
+    optspec: OptSpec,
+    // names from a symbol file, keyed by bit address
+    symbols: BTreeMap<u32, String>,
+}
+
+impl Default for Disassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Disassembler {
+    pub fn new() -> Self {
+        Self {
+            optspec: OptSpec::clone(),
+            symbols: BTreeMap::new(),
+        }
+    }
+
+    pub fn set_isa(&mut self, optspec: OptSpec) {
+        self.optspec = optspec;
+    }
+
+    pub fn load_symbols(&mut self, contents: &str) -> Result<(), DisassemblerError> {
+        add_symbols(&mut self.symbols, parse_symbols(contents)?);
+        Ok(())
+    }
+
+    // binaries without an ISA id are read with the ISA they were built for, as the VM runs them
+    fn for_container(&self, container: &Container) -> Result<Cow<'_, Self>, DisassemblerError> {
+        if container.isa == UNKNOWN_ISA
+            && let Some(legacy) = self.optspec.for_legacy_binary()?
+        {
+            let mut disassembler = self.clone();
+            disassembler.set_isa(legacy);
+            return Ok(Cow::Owned(disassembler));
+        }
+        Ok(Cow::Borrowed(self))
+    }
+
+    pub fn decode(
+        &self,
+        container: &Container,
+    ) -> Result<Vec<(u32, DecodedInstruction)>, DisassemblerError> {
+        self.for_container(container)?.decode_with(container)
+    }
+
+    fn decode_with(
+        &self,
+        container: &Container,
+    ) -> Result<Vec<(u32, DecodedInstruction)>, DisassemblerError> {
+        container.check_isa(self.optspec.identifier())?;
+        let mut instructions = Vec::new();
+        let mut address = 0;
+        while address < container.code_bits {
+            let instruction = DecodedInstruction::decode(&container.code, address, &self.optspec)
+                .map_err(|source| DisassemblerError::Decode { address, source })?;
+            instructions.push((address, instruction));
+            address += instruction.size;
+        }
+        Ok(instructions)
+    }
+
+    pub fn disassemble(&self, binary: &[u8]) -> Result<String, DisassemblerError> {
+        let container = Container::parse(binary)?;
+        self.for_container(&container)?
+            .disassemble_container(container)
+    }
+
+    fn disassemble_container(&self, container: Container) -> Result<String, DisassemblerError> {
+        let instructions = self.decode_with(&container)?;
+        // names from a symbol file win over the binary's own symbol section
+        let mut symbols = BTreeMap::new();
+        add_symbols(&mut symbols, container.symbols.unwrap_or_default());
+        symbols.extend(self.symbols.clone());
+        let labels = label_targets(&instructions, &symbols, &[], "L", |instruction| {
+            self.targets(instruction, OperandType::Label)
+        });
+        // memory operands pointing into the data image get a data label of their own
+        let data_size = container.data.len() as u32;
+        let reserved = self.optspec.reserved_data();
+        let code_names: Vec<String> = labels.values().cloned().collect();
+        let data_labels = label_targets(
+            &instructions,
+            &BTreeMap::new(),
+            &code_names,
+            "V",
+            |instruction| {
+                self.targets(instruction, OperandType::Memory)
+                    .into_iter()
+                    .filter(|target| *target < data_size && !reserved.contains(target))
+                    .collect()
+            },
+        );
+
+        let mut output = String::new();
+        if container.entry != 0 {
+            output.push_str(&format!("; entry point {}\n", container.entry));
+        }
+        for (address, instruction) in &instructions {
+            let label = match labels.get(address) {
+                Some(label) => format!("{}:", label),
+                None => String::new(),
+            };
+            let line = format!(
+                "{:<8} {}",
+                label,
+                self.render(instruction, &labels, &data_labels)
+            );
+            output.push_str(&format!("{:<32} ; {}\n", line.trim_end(), address));
+        }
+        // data addresses are prefixed with d, like in the assembler's listing
+        for (address, bytes) in data_lines(&container.data, &data_labels, &reserved) {
+            let label = match data_labels.get(&address) {
+                Some(label) => format!("{}:", label),
+                None => String::new(),
+            };
+            let bytes: Vec<String> = bytes.iter().map(u8::to_string).collect();
+            let line = format!("{:<8} DB {}", label, bytes.join(", "));
+            output.push_str(&format!("{:<32} ; d{}\n", line, address));
+        }
+        Ok(output)
+    }
+
+    fn targets(&self, instruction: &DecodedInstruction, kind: OperandType) -> Vec<u32> {
+        instruction
+            .operand_types(&self.optspec)
+            .into_iter()
+            .zip(instruction.operands())
+            .filter(|(operand_type, _)| *operand_type == kind)
+            .map(|(_, target)| *target)
+            .collect()
+    }
+
+    fn render(
+        &self,
+        instruction: &DecodedInstruction,
+        labels: &HashMap<u32, String>,
+        data_labels: &HashMap<u32, String>,
+    ) -> String {
+        instruction.render(&self.optspec, |operand_type, target| match operand_type {
+            OperandType::Label => labels.get(&target).cloned(),
+            _ => data_labels.get(&target).cloned(),
+        })
+    }
+}
+
+// every named label plus a synthesized one, `prefix` and letters, for each target without a
+// name, skipping the names already in use
+fn label_targets(
+    instructions: &[(u32, DecodedInstruction)],
+    symbols: &BTreeMap<u32, String>,
+    taken: &[String],
+    prefix: &str,
+    targets: impl Fn(&DecodedInstruction) -> Vec<u32>,
+) -> HashMap<u32, String> {
+    let mut labels: HashMap<u32, String> = symbols
+        .iter()
+        .map(|(address, label)| (*address, label.clone()))
+        .collect();
+    let mut targets: Vec<u32> = instructions
+        .iter()
+        .flat_map(|(_, instruction)| targets(instruction))
+        .filter(|target| !labels.contains_key(target))
+        .collect();
+    targets.sort();
+    targets.dedup();
+
+    let mut index = 0;
+    for target in targets {
+        // synthesized names must still match the assembler's label regex, ^[A-Z]+$
+        let name = loop {
+            let name = format!("{}{}", prefix, letters(index));
+            index += 1;
+            if !symbols.values().chain(taken).any(|label| *label == name) {
+                break name;
+            }
+        };
+        labels.insert(target, name);
+    }
+    labels
+}
+
+// the data image as DB lines of at most 8 bytes, starting a new line at every label, the
+// reserved cells are left out as the assembler places the data after them again
+fn data_lines<'a>(
+    data: &'a [u8],
+    labels: &HashMap<u32, String>,
+    reserved: &Range<u32>,
+) -> Vec<(u32, &'a [u8])> {
+    let mut lines = Vec::new();
+    let mut start = 0;
+    for address in 0..=data.len() as u32 {
+        let boundary = address == data.len() as u32
+            || address == reserved.start
+            || address == reserved.end
+            || address - start == 8
+            || labels.contains_key(&address);
+        if !boundary {
+            continue;
+        }
+        if address > start && !reserved.contains(&start) {
+            lines.push((start, &data[start as usize..address as usize]));
+        }
+        start = address;
+    }
+    lines
+}
+
+// keeps one name per address, preferring the alphabetically first
+fn add_symbols(
+    symbols: &mut BTreeMap<u32, String>,
+    labels: impl IntoIterator<Item = (String, u32)>,
+) {
+    for (label, address) in labels {
+        let name = symbols.entry(address).or_insert_with(|| label.clone());
+        if label < *name {
+            *name = label;
+        }
+    }
+}
+
+// bijective base 26: 0 -> A, 25 -> Z, 26 -> AA
+fn letters(mut index: usize) -> String {
+    let mut letters = Vec::new();
+    loop {
+        letters.push(b'A' + (index % 26) as u8);
+        if index < 26 {
+            break;
+        }
+        index = index / 26 - 1;
+    }
+    letters.reverse();
+    String::from_utf8(letters).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::MyAssembler;
+    use std::{fs, path::Path};
+
+    // assembled from:
+    // SUB: RET
+    // LOOP: SUBI R2, R2, 1
+    // JNZ LOOP
+    // CALL SUB
+    // HALT
+    fn binary() -> Vec<u8> {
+        let code = vec![
+            0b10001100, 0b11101010, 0b00000001, 0b01011100, 0b00000000, 0b00011010, 0b00100000,
+            0b00000000, 0b00000000, 0b00000000,
+        ];
+        Container::new(OptSpec::clone().identifier(), code, 74).to_bytes()
+    }
+
+    #[test]
+    fn test_synthesized_labels() {
+        let output = Disassembler::new().disassemble(&binary()).unwrap();
+        assert_eq!(
+            output,
+            "\
+LA:      RET                     ; 0
+LB:      SUBI R2, R2, 1          ; 6
+         JNZ LB                  ; 24
+         CALL LA                 ; 46
+         HALT                    ; 68
+"
+        );
+    }
+
+    #[test]
+    fn test_symbol_file_names() {
+        let mut disassembler = Disassembler::new();
+        disassembler
+            .load_symbols("SUB 0\nLOOP 6\nDONE 68\n")
+            .unwrap();
+        let output = disassembler.disassemble(&binary()).unwrap();
+        assert!(output.starts_with("SUB:     RET"));
+        assert!(output.contains("LOOP:    SUBI R2, R2, 1"));
+        assert!(output.contains("         JNZ LOOP"));
+        assert!(output.contains("CALL SUB"));
+        assert!(output.contains("DONE:    HALT"));
+    }
+
+    #[test]
+    fn test_data_section() {
+        let (binary, _) = MyAssembler::new()
+            .unwrap()
+            .assemble(
+                "MOVER R0, COUNT\nHALT\nTABLE: DS 4\nCOUNT: DB 3, 4, 5\nDB 9, 9, 9, 9, 9, 9\n",
+            )
+            .unwrap();
+        let output = Disassembler::new().disassemble(&binary.to_bytes()).unwrap();
+        assert_eq!(
+            output,
+            "         MOVER R0, VA            ; 0
+         HALT                    ; 12
+         DB 0, 0, 0, 0           ; d0
+VA:      DB 3, 4, 5, 0, 0, 0, 0, 0 ; d4
+         DB 9, 9, 9, 9, 9, 9     ; d24
+"
+        );
+    }
+
+    #[test]
+    fn test_legacy_binary() {
+        // written by the assembler before binaries recorded their ISA, with 8 bit labels
+        let binary = [0x08, 0x03, 0x18, 0x38, 0x00, 0x57, 0x10, 0x00, 0, 0, 0, 62];
+        let output = Disassembler::new().disassemble(&binary).unwrap();
+        assert_eq!(
+            output,
+            "         MOVEI R0, 3             ; 0
+LA:      OUT R0                  ; 16
+         SUBI R0, R0, 1          ; 24
+         JNZ LA                  ; 42
+         HALT                    ; 56
+"
+        );
+    }
+
+    #[test]
+    fn test_invalid_binaries() {
+        let disassembler = Disassembler::new();
+        assert!(matches!(
+            disassembler.disassemble(&[0, 0]),
+            Err(DisassemblerError::Container(ContainerError::Truncated))
+        ));
+        assert!(matches!(
+            disassembler.disassemble(&[0, 0, 0, 0, 9]),
+            Err(DisassemblerError::Container(ContainerError::CodeLength {
+                bits: 9,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            disassembler.disassemble(&[0b11111100, 0, 0, 0, 6]),
+            Err(DisassemblerError::Decode { address: 0, .. })
+        ));
+        assert_eq!(letters(27), "AB");
+    }
+
+    // the listing of every example must assemble back to the same binary
+    #[test]
+    fn test_examples_round_trip() {
+        let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("../examples");
+        for entry in fs::read_dir(examples).unwrap() {
+            let path = entry.unwrap().path();
+            // examples/lib only holds files for the INCLUDE example
+            if !path.is_file() {
+                continue;
+            }
+            let source = fs::read_to_string(&path).unwrap();
+            let (binary, _) = MyAssembler::new()
+                .unwrap()
+                .assemble_source(path.to_str().unwrap(), &source)
+                .unwrap();
+            let listing = Disassembler::new().disassemble(&binary.to_bytes()).unwrap();
+            let (reassembled, _) = MyAssembler::new().unwrap().assemble(&listing).unwrap();
+            // the debug section maps to listing lines, so only the images are compared
+            assert_eq!(
+                (reassembled.code, reassembled.code_bits, reassembled.data),
+                (binary.code, binary.code_bits, binary.data),
+                "{}:\n{}",
+                path.display(),
+                listing
+            );
+        }
+    }
+}
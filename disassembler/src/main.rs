@@ -0,0 +1,64 @@
+use args::Args;
+use disassembler::Disassembler;
+use isa::OptSpec;
+use std::{
+    fs::File,
+    io::{self, Read},
+    process,
+};
+
+pub fn main() {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(err) => {
+            println!("Failed to parse arguments:\n\t{}", err);
+            process::exit(1);
+        }
+    };
+    let Some(input_filename) = &args.input_filename else {
+        println!("Usage: disassembler <filename.bin|-> [--symbols=<file.sym>] [--isa <file.toml>]");
+        process::exit(1);
+    };
+
+    let mut disassembler = Disassembler::new();
+    if let Some(path) = &args.isa {
+        match OptSpec::load(path) {
+            Ok(isa) => disassembler.set_isa(isa),
+            Err(err) => {
+                println!("Failed to load ISA:\n\t{}", err);
+                process::exit(1);
+            }
+        }
+    }
+    if let Some(symbols) = &args.symbols {
+        let result = std::fs::read_to_string(symbols)
+            .map_err(|err| err.to_string())
+            .and_then(|contents| {
+                disassembler
+                    .load_symbols(&contents)
+                    .map_err(|err| err.to_string())
+            });
+        if let Err(err) = result {
+            println!("Failed to load symbols from {}:\n\t{}", symbols, err);
+            process::exit(1);
+        }
+    }
+
+    let mut binary = Vec::new();
+    let result = if input_filename == "-" {
+        io::stdin().read_to_end(&mut binary)
+    } else {
+        File::open(input_filename).and_then(|mut file| file.read_to_end(&mut binary))
+    };
+    if let Err(err) = result {
+        println!("Failed to read {}:\n\t{}", input_filename, err);
+        process::exit(1);
+    }
+    match disassembler.disassemble(&binary) {
+        Ok(listing) => print!("{}", listing),
+        Err(err) => {
+            println!("Failed to disassemble {}:\n\t{}", input_filename, err);
+            process::exit(1);
+        }
+    }
+}
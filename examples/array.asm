@@ -0,0 +1,17 @@
+; Register-indirect, base+offset and post-increment addressing walk a table
+MOVEI R1, TABLE     ; R1 points at the first entry
+MOVEI R2, 0         ; Running sum
+MOVEI R3, SIZE
+LOOP: MOVER R0, [R1+] ; Load the entry and step to the next one
+ADD R2, R0
+SUBI R3, R3, 1
+JNZ LOOP
+OUT R2
+MOVEM R2, [R1]      ; R1 now points just past the table
+MOVEI R1, 0
+MOVER R0, [R1+TABLE+SIZE]
+OUT R0
+HALT
+
+SIZE EQU 4
+TABLE: DB 3, 5, 7, 9
@@ -0,0 +1,16 @@
+; Data directives fill data memory before the program starts
+STARS EQU 3
+MOVER R0, COUNT     ; Data labels are memory operands
+LOOP: MOVER R1, CHAR
+OUT_CHAR R1
+SUBI R0, R0, 1
+JNZ LOOP
+MOVER R2, TOTAL     ; High byte of the word
+MOVER R3, TOTAL + 1 ; Low byte of the word
+OUT R3
+HALT
+
+COUNT: DB STARS
+CHAR: ASCII "*"
+SPARE: DS 2
+TOTAL: DW 300
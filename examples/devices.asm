@@ -0,0 +1,22 @@
+; Memory mapped devices, run the VM with --devices
+; 12: terminal, 13: keyboard, 14: timer, 15: random number generator
+MOVEI R0, 5
+MOVEM R0, 14        ; Start the timer, it counts down once per instruction
+WAIT: MOVER R1, 14
+CMP R1, R0          ; Plain memory reads back 5, the timer has already counted down
+JE NODEV
+CMPI R1, 0
+JNZ WAIT            ; Busy wait until the timer runs out
+MOVEI R0, 'H'
+MOVEM R0, 12        ; Writing to the terminal prints the character
+MOVEI R0, 'i'
+MOVEM R0, 12
+MOVEI R0, '\n'
+MOVEM R0, 12
+MOVER R2, 15        ; Read a random byte
+OUT R2
+MOVER R3, 13        ; Read one key from the keyboard buffer
+OUT_CHAR R3
+HALT
+NODEV: OUT R1       ; Without --devices this prints the 5 that was stored and stops
+HALT
@@ -1,4 +1,5 @@
-MOVEMI 1, 0         ; Constants; declare a constant of value 1 at memory location 1, so zero flag is false
-JZ HALT             ; Jump to HALT
-JNZ HALT            ; Jump to HALT
-HALT: HALT          ; END of program
+MOVEI R0, 1         ; Constants; load 1 into R0
+CMPI R0, 0          ; R0 is not zero, so the zero flag is false
+JZ END              ; Jump to END, which is only defined further down
+JNZ END             ; Jump to END
+END: HALT           ; END of program
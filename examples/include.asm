@@ -0,0 +1,5 @@
+; The included file is assembled in place of the INCLUDE line
+INCLUDE "lib/print.asm"
+PRINT R1, 4
+PRINT R2, 2
+HALT
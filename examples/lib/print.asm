@@ -0,0 +1,6 @@
+; Shared macros, pulled in with INCLUDE "lib/print.asm"
+MACRO
+PRINT &REG, &VALUE
+MOVEI &REG, &VALUE
+OUT &REG
+MEND
@@ -0,0 +1,9 @@
+MACRO
+SHOW &REG, &VALUE=1
+MOVEI &REG, &VALUE
+OUT &REG
+MEND
+SHOW R1, 5              ; positional arguments
+SHOW R2                 ; &VALUE falls back to its default
+SHOW VALUE=9, REG=R0    ; keyword arguments
+HALT
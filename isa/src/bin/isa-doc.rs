@@ -0,0 +1,40 @@
+use std::process;
+
+use args::Args;
+use isa::{Format, OptSpec};
+
+// writes the reference of the built-in ISA, or of --isa, as Markdown, or as HTML when the
+// output file ends in .html
+pub fn main() {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(err) => {
+            println!("Failed to parse arguments:\n\t{}", err);
+            process::exit(1);
+        }
+    };
+    let spec = match &args.isa {
+        Some(path) => match OptSpec::load(path) {
+            Ok(spec) => spec,
+            Err(err) => {
+                println!("Failed to load ISA:\n\t{}", err);
+                process::exit(1);
+            }
+        },
+        None => OptSpec::clone(),
+    };
+
+    let output = args.output.as_deref().unwrap_or("-");
+    let format = if output.ends_with(".html") {
+        Format::Html
+    } else {
+        Format::Markdown
+    };
+    let reference = spec.reference(format);
+    if output == "-" {
+        print!("{}", reference);
+    } else if let Err(err) = std::fs::write(output, reference) {
+        println!("Failed to write {}:\n\t{}", output, err);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+
+use super::{MAX_OPERANDS, Opcode, OperandSpec, OperandType, Operation, OptSpec, number_pattern};
+
+#[derive(Debug, thiserror::Error)]
+pub enum IsaError {
+    #[error("Cannot read ISA description {path}: {source}")]
+    Io {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Invalid ISA description: {0}")]
+    Syntax(String),
+    #[error("opcode_bit_count must be between 1 and 32, got {0}")]
+    OpcodeWidth(u8),
+    #[error("address_bit_count must be between 8 and 24, got {0}")]
+    AddressWidth(u8),
+    #[error("data_memory_size must be between 64 and 65536 bytes, got {0}")]
+    DataMemorySize(u32),
+    #[error("Operand type '{operand_type}' must be between 1 and 32 bits wide, got {bit_count}")]
+    OperandWidth { operand_type: String, bit_count: u8 },
+    #[error("{register_count} registers cannot be numbered in {bit_count} bits")]
+    RegisterCount { register_count: u32, bit_count: u8 },
+    // `place` is the mnemonic that uses the type or the [operands] table
+    #[error("Unknown operand type '{operand_type}' in {place}")]
+    UnknownOperandType { operand_type: String, place: String },
+    #[error("{mnemonic} uses operand type '{operand_type}', which has no bit width in [operands]")]
+    MissingOperandWidth {
+        mnemonic: String,
+        operand_type: String,
+    },
+    #[error("Unknown mnemonic '{0}', the VM has no such instruction")]
+    UnknownMnemonic(String),
+    #[error("{mnemonic} has no form with operands [{operands}]")]
+    UnknownForm { mnemonic: String, operands: String },
+    #[error("{0} is described more than once")]
+    DuplicateMnemonic(String),
+    #[error("{first} and {second} share opcode {opcode}")]
+    DuplicateOpcode {
+        opcode: u32,
+        first: String,
+        second: String,
+    },
+    #[error("Opcode {opcode} of {mnemonic} does not fit in {opcode_bit_count} bits")]
+    OpcodeOverflow {
+        mnemonic: String,
+        opcode: u32,
+        opcode_bit_count: u8,
+    },
+    #[error(
+        "The indexed operand of {mnemonic} is {bit_count} bits wide, which leaves no offset next to {register_bits} register bits"
+    )]
+    IndexedWidth {
+        mnemonic: String,
+        bit_count: u8,
+        register_bits: u8,
+    },
+    #[error("{mnemonic} has {count} operands, at most {MAX_OPERANDS} are supported")]
+    TooManyOperands { mnemonic: String, count: usize },
+    #[error("Operand regex {regex} of {mnemonic} does not compile: {error}")]
+    InvalidRegex {
+        mnemonic: String,
+        regex: String,
+        error: String,
+    },
+    #[error(
+        "Operand regex {regex} of {mnemonic} accepts {example}, which does not fit in {bit_count} bits"
+    )]
+    RegexRange {
+        mnemonic: String,
+        regex: String,
+        example: String,
+        bit_count: u8,
+    },
+    #[error(
+        "The binary records no ISA, so it was built with 8 bit labels, but {mnemonic} takes a {bit_count} bit label in this ISA"
+    )]
+    LegacyLabels { mnemonic: String, bit_count: u8 },
+}
+
+// the layout of isa.toml, JSON descriptions use the same keys
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Description {
+    opcode_bit_count: u8,
+    register_count: u32,
+    #[serde(default = "default_address_bit_count")]
+    address_bit_count: u8,
+    #[serde(default = "default_data_memory_size")]
+    data_memory_size: u32,
+    operands: BTreeMap<String, u8>,
+    operations: Vec<OperationDescription>,
+}
+
+// descriptions written before addresses were configurable get the 16 bit default
+fn default_address_bit_count() -> u8 {
+    16
+}
+
+// and the 256 bytes data memory always had
+fn default_data_memory_size() -> u32 {
+    256
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OperationDescription {
+    mnemonic: String,
+    opcode: u32,
+    #[serde(default)]
+    operands: Vec<String>,
+}
+
+fn operand_type(name: &str) -> Option<OperandType> {
+    match name {
+        "register" => Some(OperandType::Register),
+        "memory" => Some(OperandType::Memory),
+        "label" => Some(OperandType::Label),
+        "constant" => Some(OperandType::Constant),
+        "indirect" => Some(OperandType::Indirect),
+        "indexed" => Some(OperandType::Indexed),
+        "post_increment" => Some(OperandType::PostIncrement),
+        _ => None,
+    }
+}
+
+// R0 up to the last register, as a character class while the numbers are single digits
+fn register_pattern(register_count: u32) -> String {
+    if register_count <= 10 {
+        format!("R[0-{}]", register_count - 1)
+    } else {
+        let numbers: Vec<String> = (0..register_count).map(|n| n.to_string()).collect();
+        format!("R({})", numbers.join("|"))
+    }
+}
+
+fn register_regex(register_count: u32) -> String {
+    format!("^{}$", register_pattern(register_count))
+}
+
+impl Description {
+    fn operand_spec(&self, operand_type: OperandType, bit_count: u8) -> OperandSpec {
+        let regex = match operand_type {
+            OperandType::Register => register_regex(self.register_count),
+            OperandType::Memory => format!("^{}$", number_pattern(bit_count)),
+            OperandType::Label => String::from("^[A-Z]+$"),
+            OperandType::Constant => format!("^-?{}$", number_pattern(bit_count)),
+            OperandType::Indirect => format!(r"^\[{}\]$", register_pattern(self.register_count)),
+            // any expression may follow the plus, the assembler evaluates it
+            OperandType::Indexed => {
+                format!(r"^\[{}\+.+\]$", register_pattern(self.register_count))
+            }
+            OperandType::PostIncrement => {
+                format!(r"^\[{}\+\]$", register_pattern(self.register_count))
+            }
+        };
+        OperandSpec::new(&regex, bit_count, operand_type)
+    }
+
+    // checks what only the description knows, `OptSpec::validate` does the rest
+    fn build(self) -> Result<OptSpec, IsaError> {
+        for (name, bit_count) in &self.operands {
+            if operand_type(name).is_none() {
+                return Err(IsaError::UnknownOperandType {
+                    operand_type: name.clone(),
+                    place: String::from("[operands]"),
+                });
+            }
+            if !(1..=32).contains(bit_count) {
+                return Err(IsaError::OperandWidth {
+                    operand_type: name.clone(),
+                    bit_count: *bit_count,
+                });
+            }
+        }
+        if let Some(bit_count) = self.operands.get("register").copied()
+            && (self.register_count == 0 || self.register_count as u64 > 1u64 << bit_count)
+        {
+            return Err(IsaError::RegisterCount {
+                register_count: self.register_count,
+                bit_count,
+            });
+        }
+
+        let mut opttab: Vec<Operation> = Vec::new();
+        for operation in &self.operations {
+            if Opcode::from_mnemonic(&operation.mnemonic).is_none() {
+                return Err(IsaError::UnknownMnemonic(operation.mnemonic.clone()));
+            }
+            let mut operands = Vec::new();
+            for name in &operation.operands {
+                let Some(operand_type) = operand_type(name) else {
+                    return Err(IsaError::UnknownOperandType {
+                        operand_type: name.clone(),
+                        place: operation.mnemonic.clone(),
+                    });
+                };
+                let Some(bit_count) = self.operands.get(name) else {
+                    return Err(IsaError::MissingOperandWidth {
+                        mnemonic: operation.mnemonic.clone(),
+                        operand_type: name.clone(),
+                    });
+                };
+                operands.push(self.operand_spec(operand_type, *bit_count));
+            }
+            let operand_types: Vec<OperandType> = operands
+                .iter()
+                .map(|operand: &OperandSpec| operand.operand_type)
+                .collect();
+            let Some(kind) = Opcode::from_form(&operation.mnemonic, &operand_types) else {
+                return Err(IsaError::UnknownForm {
+                    mnemonic: operation.mnemonic.clone(),
+                    operands: operation.operands.join(", "),
+                });
+            };
+            opttab.push(Operation::new(kind, operation.opcode, operands));
+        }
+
+        let spec = OptSpec {
+            opcode_bit_count: self.opcode_bit_count,
+            register_count: self.register_count,
+            address_bit_count: self.address_bit_count,
+            data_memory_size: self.data_memory_size,
+            opttab,
+        };
+        spec.validate()?;
+        Ok(spec)
+    }
+}
+
+impl OptSpec {
+    pub fn from_toml(text: &str) -> Result<Self, IsaError> {
+        toml::from_str::<Description>(text)
+            .map_err(|error| IsaError::Syntax(error.to_string()))?
+            .build()
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, IsaError> {
+        serde_json::from_str::<Description>(text)
+            .map_err(|error| IsaError::Syntax(error.to_string()))?
+            .build()
+    }
+
+    // a `.json` file is read as JSON, anything else as TOML
+    pub fn load(path: &str) -> Result<Self, IsaError> {
+        let text = std::fs::read_to_string(path).map_err(|source| IsaError::Io {
+            path: path.to_string(),
+            source,
+        })?;
+        if path.ends_with(".json") {
+            Self::from_json(&text)
+        } else {
+            Self::from_toml(&text)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SMALL: &str = "opcode_bit_count = 2\nregister_count = 2\n\
+                         [operands]\nregister = 1\nlabel = 4\n\
+                         [[operations]]\nmnemonic = \"HALT\"\nopcode = 0\n\
+                         [[operations]]\nmnemonic = \"JMP\"\nopcode = 3\noperands = [\"label\"]\n";
+
+    #[test]
+    fn test_load_description() {
+        let spec = OptSpec::from_toml(SMALL).unwrap();
+        assert_eq!(spec.opcode_bit_count, 2);
+        assert_eq!(spec.register_count, 2);
+        assert_eq!(spec.address_bit_count, 16);
+        assert_eq!(spec.data_memory_size, 256);
+        let jump = spec.get_by_operation_name("JMP").unwrap();
+        assert_eq!(jump.kind, Opcode::Jmp);
+        assert_eq!(jump.operand_bit_count(), 4);
+        assert_eq!(spec.max_instruction_size(), 6);
+        assert!(spec.get_by_operation_name("MOVER").is_none());
+
+        let forms = SMALL.replace("label = 4", "label = 4\nindexed = 3\nmemory = 2")
+            + "[[operations]]\nmnemonic = \"MOVER\"\nopcode = 1\noperands = [\"register\", \"memory\"]\n\
+               [[operations]]\nmnemonic = \"MOVER\"\nopcode = 2\noperands = [\"register\", \"indexed\"]\n";
+        let spec = OptSpec::from_toml(&forms).unwrap();
+        let kinds: Vec<Opcode> = spec.get_forms("MOVER").map(|form| form.kind).collect();
+        assert_eq!(kinds, [Opcode::Mover, Opcode::MoverIndexed]);
+        assert_eq!(
+            spec.get_by_opcode(&2).unwrap().operands[1].operand_regex,
+            r"^\[R[0-1]\+.+\]$"
+        );
+
+        let json = r#"{"opcode_bit_count": 2, "register_count": 2, "operands": {"label": 4},
+                       "operations": [{"mnemonic": "JMP", "opcode": 3, "operands": ["label"]}]}"#;
+        assert_eq!(OptSpec::from_json(json).unwrap().get_operations().len(), 1);
+        assert_eq!(register_regex(4), "^R[0-3]$");
+        assert_eq!(register_regex(12), "^R(0|1|2|3|4|5|6|7|8|9|10|11)$");
+    }
+
+    #[test]
+    fn test_invalid_description() {
+        let errors = [
+            (
+                SMALL.replace("opcode = 3", "opcode = 0"),
+                "HALT and JMP share opcode 0",
+            ),
+            (
+                SMALL.replace("opcode = 3", "opcode = 4"),
+                "does not fit in 2 bits",
+            ),
+            (
+                SMALL.replace("[\"label\"]", "[\"index\"]"),
+                "'index' in JMP",
+            ),
+            (
+                SMALL.replace("label = 4", "index = 4"),
+                "'index' in [operands]",
+            ),
+            (
+                SMALL.replace("\"HALT\"", "\"NOP\""),
+                "Unknown mnemonic 'NOP'",
+            ),
+            (
+                SMALL.replace("\"HALT\"", "\"JMP\""),
+                "JMP is described more than once",
+            ),
+            (
+                SMALL.replace("register_count = 2", "register_count = 3"),
+                "3 registers",
+            ),
+            (
+                SMALL.replace("[\"label\"]", "[\"constant\"]"),
+                "has no bit width",
+            ),
+            (
+                SMALL.replace("\"HALT\"", "\"MOVER\"\noperands = [\"label\"]"),
+                "MOVER has no form with operands [label]",
+            ),
+            (
+                SMALL.replace("opcode_bit_count = 2", "opcode_bit_count = 0"),
+                "between 1 and 32",
+            ),
+            (
+                SMALL.replace("opcode = 0", "opcode = \"0\""),
+                "Invalid ISA description",
+            ),
+        ];
+        for (text, expected) in errors {
+            let Err(error) = OptSpec::from_toml(&text) else {
+                panic!("{} was accepted", text);
+            };
+            let error = error.to_string();
+            assert!(error.contains(expected), "{}: {}", expected, error);
+        }
+    }
+}
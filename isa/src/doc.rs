@@ -0,0 +1,337 @@
+use std::fmt::Write;
+
+use super::{Opcode, OperandType, Operation, OptSpec};
+
+// flags an instruction reads and writes, by name
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FlagUsage {
+    pub read: Vec<String>,
+    pub written: Vec<String>,
+}
+
+impl Opcode {
+    // the flags the VM's handler for the instruction reads and writes, in the bit order of
+    // the VM's flags byte. The VM's tests run every handler to check this table
+    pub fn flag_usage(&self) -> FlagUsage {
+        const ARITHMETIC: &[&str] = &["zero", "sign", "overflow", "carry"];
+        let (read, written): (&[&str], &[&str]) = match self {
+            Opcode::Add
+            | Opcode::Addi
+            | Opcode::Sub
+            | Opcode::Subi
+            | Opcode::Mult
+            | Opcode::Multi
+            | Opcode::Mult16
+            | Opcode::And
+            | Opcode::Or
+            | Opcode::Xor
+            | Opcode::Not
+            | Opcode::Shl
+            | Opcode::Shr
+            | Opcode::Cmp
+            | Opcode::Cmpi => (&[], ARITHMETIC),
+            Opcode::Adc | Opcode::Adci | Opcode::Sbc | Opcode::Sbci => (&["carry"], ARITHMETIC),
+            Opcode::Jz | Opcode::Jnz | Opcode::Je | Opcode::Jne => (&["zero"], &[]),
+            Opcode::Jg | Opcode::Jle => (&["zero", "sign", "overflow"], &[]),
+            Opcode::Jge | Opcode::Jl => (&["sign", "overflow"], &[]),
+            Opcode::Ei | Opcode::Di => (&[], &["interrupt_enable"]),
+            Opcode::Iret => (
+                &[],
+                &["zero", "sign", "overflow", "carry", "interrupt_enable"],
+            ),
+            _ => (&[], &[]),
+        };
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        FlagUsage {
+            read: names(read),
+            written: names(written),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Markdown,
+    Html,
+}
+
+// the reference is built as blocks first so both formats show the same content
+enum Block {
+    Heading(u8, String),
+    Paragraph(String),
+    Table(Vec<String>, Vec<Vec<String>>),
+}
+
+fn binary(value: u32, bit_count: u8) -> String {
+    format!("{:0width$b}", value, width = bit_count as usize)
+}
+
+fn operand_letter(operand_type: &OperandType) -> &'static str {
+    match operand_type {
+        OperandType::Register => "R",
+        OperandType::Memory => "M",
+        OperandType::Label => "L",
+        OperandType::Constant => "V",
+        OperandType::Indirect => "[R]",
+        OperandType::Indexed => "[R+V]",
+        OperandType::PostIncrement => "[R+]",
+    }
+}
+
+fn syntax(operation: &Operation) -> String {
+    let operands: Vec<&str> = operation
+        .operands
+        .iter()
+        .map(|operand| operand_letter(&operand.operand_type))
+        .collect();
+    format!("{} {}", operation.operation_name, operands.join(", "))
+        .trim_end()
+        .to_string()
+}
+
+fn list(names: &[String]) -> String {
+    match names.is_empty() {
+        true => String::from("-"),
+        false => names.join(", "),
+    }
+}
+
+fn bits(start: u32, bit_count: u32) -> String {
+    match bit_count {
+        1 => start.to_string(),
+        _ => format!("{}-{}", start, start + bit_count - 1),
+    }
+}
+
+fn blocks(spec: &OptSpec) -> Vec<Block> {
+    let mut operations: Vec<&Operation> = spec.get_operations().iter().collect();
+    operations.sort_by_key(|operation| operation.opcode);
+    let width = spec.opcode_bit_count;
+
+    let mut blocks = vec![
+        Block::Heading(1, String::from("ISA reference")),
+        Block::Paragraph(format!(
+            "Generated from the ISA description by isa-doc. ISA identifier {:08X}: {} instructions, {} bit opcodes, {} registers, {} bit code addresses counting bits, {} bytes of data memory. Operands are R (register), M (memory address), L (label) and V (constant), a memory operand in square brackets is addressed through a register. Fields are numbered from the first bit of the instruction.",
+            spec.identifier(),
+            operations.len(),
+            width,
+            spec.register_count,
+            spec.address_bit_count,
+            spec.data_memory_size,
+        )),
+        Block::Heading(2, String::from("Instructions")),
+    ];
+    let mut summary = Vec::new();
+    for operation in &operations {
+        let usage = operation.kind.flag_usage();
+        summary.push(vec![
+            syntax(operation),
+            operation.opcode.to_string(),
+            binary(operation.opcode, width),
+            (width as u32 + operation.operand_bit_count()).to_string(),
+            list(&usage.read),
+            list(&usage.written),
+        ]);
+    }
+    blocks.push(Block::Table(
+        [
+            "Instruction",
+            "Opcode",
+            "Binary",
+            "Bits",
+            "Flags read",
+            "Flags written",
+        ]
+        .map(String::from)
+        .to_vec(),
+        summary,
+    ));
+
+    blocks.push(Block::Heading(2, String::from("Encodings")));
+    for operation in &operations {
+        blocks.push(Block::Heading(3, operation.operation_name.clone()));
+        let mut rows = vec![vec![
+            bits(0, width as u32),
+            String::from("opcode"),
+            width.to_string(),
+            binary(operation.opcode, width),
+        ]];
+        let mut start = width as u32;
+        for (index, operand) in operation.operands.iter().enumerate() {
+            let field = format!("operand {} ({})", index + 1, operand.operand_type);
+            let parts = match operand.operand_type {
+                OperandType::Indexed => {
+                    let register_bits = spec.register_bits();
+                    vec![
+                        (
+                            format!("{} offset", field),
+                            operand.bit_count - register_bits,
+                        ),
+                        (format!("{} register", field), register_bits),
+                    ]
+                }
+                _ => vec![(field, operand.bit_count)],
+            };
+            for (field, bit_count) in parts {
+                rows.push(vec![
+                    bits(start, bit_count as u32),
+                    field,
+                    bit_count.to_string(),
+                    String::new(),
+                ]);
+                start += bit_count as u32;
+            }
+        }
+        blocks.push(Block::Paragraph(format!(
+            "`{}`, {} bits",
+            syntax(operation),
+            start
+        )));
+        blocks.push(Block::Table(
+            ["Bits", "Field", "Width", "Value"]
+                .map(String::from)
+                .to_vec(),
+            rows,
+        ));
+    }
+
+    // rows take the high half of the opcode, columns the low half
+    let column_bits = width.div_ceil(2).min(4);
+    let row_bits = width - column_bits;
+    let mut header = vec![String::new()];
+    header.extend((0..1u32 << column_bits).map(|column| binary(column, column_bits)));
+    let mut rows = Vec::new();
+    for row in 0..1u32 << row_bits.min(8) {
+        let mut cells = vec![binary(row, row_bits)];
+        for column in 0..1u32 << column_bits {
+            let opcode = row << column_bits | column;
+            cells.push(
+                spec.get_by_opcode(&opcode)
+                    .map(|operation| operation.operation_name.clone())
+                    .unwrap_or_default(),
+            );
+        }
+        rows.push(cells);
+    }
+    blocks.push(Block::Heading(2, String::from("Opcode map")));
+    blocks.push(Block::Paragraph(format!(
+        "Rows are the high {} bits of the opcode, columns the low {}. Empty cells are free opcodes.",
+        row_bits, column_bits
+    )));
+    blocks.push(Block::Table(header, rows));
+    blocks
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn markdown(blocks: &[Block]) -> String {
+    let mut out = String::new();
+    for block in blocks {
+        match block {
+            Block::Heading(level, text) => {
+                writeln!(out, "{} {}\n", "#".repeat(*level as usize), text).unwrap()
+            }
+            Block::Paragraph(text) => writeln!(out, "{}\n", text).unwrap(),
+            Block::Table(header, rows) => {
+                writeln!(out, "| {} |", header.join(" | ")).unwrap();
+                writeln!(out, "|{}", "---|".repeat(header.len())).unwrap();
+                for row in rows {
+                    writeln!(out, "| {} |", row.join(" | ")).unwrap();
+                }
+                out.push('\n');
+            }
+        }
+    }
+    out
+}
+
+fn html(blocks: &[Block]) -> String {
+    let mut out = String::from(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>ISA reference</title>\n\
+         <style>table { border-collapse: collapse; } td, th { border: 1px solid #999; padding: 2px 6px; }</style>\n\
+         </head>\n<body>\n",
+    );
+    for block in blocks {
+        match block {
+            Block::Heading(level, text) => {
+                writeln!(out, "<h{0}>{1}</h{0}>", level, escape(text)).unwrap()
+            }
+            Block::Paragraph(text) => {
+                // `code` spans are the only markup paragraphs use
+                let mut paragraph = String::new();
+                for (index, part) in escape(text).split('`').enumerate() {
+                    match index % 2 {
+                        0 => paragraph.push_str(part),
+                        _ => write!(paragraph, "<code>{}</code>", part).unwrap(),
+                    }
+                }
+                writeln!(out, "<p>{}</p>", paragraph).unwrap()
+            }
+            Block::Table(header, rows) => {
+                out.push_str("<table>\n<tr>");
+                for cell in header {
+                    write!(out, "<th>{}</th>", escape(cell)).unwrap();
+                }
+                out.push_str("</tr>\n");
+                for row in rows {
+                    out.push_str("<tr>");
+                    for cell in row {
+                        write!(out, "<td>{}</td>", escape(cell)).unwrap();
+                    }
+                    out.push_str("</tr>\n");
+                }
+                out.push_str("</table>\n");
+            }
+        }
+    }
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+impl OptSpec {
+    // a reference of every instruction and an opcode map
+    pub fn reference(&self, format: Format) -> String {
+        let blocks = blocks(self);
+        match format {
+            Format::Markdown => markdown(&blocks),
+            Format::Html => html(&blocks),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference() {
+        let spec = OptSpec::clone();
+        let markdown = spec.reference(Format::Markdown);
+        assert!(markdown.contains("| MOVER R, M | 1 | 000001 | 12 | - | - |"));
+        assert!(
+            markdown.contains(
+                "| ADC R, R, R | 10 | 001010 | 12 | carry | zero, sign, overflow, carry |"
+            )
+        );
+        assert!(markdown.contains("| JZ L | 22 | 010110 | 22 | zero | - |"));
+        assert!(markdown.contains("| 6-7 | operand 1 (Register) | 2 |  |"));
+        assert!(markdown.contains("4 registers, 16 bit code addresses"));
+        // HALT and MOVER open the map, opcode 12 is free
+        assert!(
+            markdown
+                .contains("| 000 | HALT | MOVER | MOVEI | MOVEM | OUT_CHAR | IN | OUT | OUT_16 |")
+        );
+        assert!(markdown.contains("| 001 | ADD | ADDI | ADC | ADCI |  | SUB | SUBI | SBC |"));
+        assert!(markdown.contains("| MOVER R, [R+V] | 47 | 101111 | 14 | - | - |"));
+        assert!(markdown.contains("| 8-11 | operand 2 (Indexed) offset | 4 |  |"));
+        assert!(markdown.contains("| 12-13 | operand 2 (Indexed) register | 2 |  |"));
+
+        let html = spec.reference(Format::Html);
+        assert!(html.contains("<h3>MOVER</h3>\n<p><code>MOVER R, M</code>, 12 bits</p>"));
+        assert!(html.ends_with("</html>\n"));
+    }
+}
@@ -1,11 +1,49 @@
-use std::fmt::Display;
+mod description;
+mod doc;
 
-#[derive(Clone, Debug, PartialEq)]
+use std::{collections::HashSet, fmt::Display, ops::Range};
+
+use regex::Regex;
+
+pub use description::IsaError;
+pub use doc::{FlagUsage, Format};
+
+pub const MAX_OPERANDS: usize = 3;
+// bytes at the top of data memory kept free of data for the stack
+pub const STACK_RESERVE: u32 = 32;
+
+pub const DEFAULT_ISA: &str = include_str!("../isa.toml");
+
+// the low end of data memory is laid out the same for every ISA: free cells, the standard
+// devices at 12-15, then one interrupt vector per line, each `OptSpec::address_bytes` wide
+pub const DEVICE_WINDOW: u32 = 12;
+pub const DEVICE_COUNT: u32 = 4;
+pub const VECTOR_TABLE: u32 = DEVICE_WINDOW + DEVICE_COUNT;
+pub const INTERRUPT_LINES: u32 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum OperandType {
     Register,
     Memory,
     Label,
     Constant,
+    // `[R1]`, the address is the value of the register
+    Indirect,
+    // `[R1+4]`, the offset takes the high bits of the field and the register the low
+    // `OptSpec::register_bits()`
+    Indexed,
+    // `[R1+]`, like `[R1]` and the register is incremented after the access
+    PostIncrement,
+}
+
+impl OperandType {
+    // the types written in square brackets, MOVER and MOVEM have a form for each
+    pub fn is_indirect(&self) -> bool {
+        matches!(
+            self,
+            OperandType::Indirect | OperandType::Indexed | OperandType::PostIncrement
+        )
+    }
 }
 
 impl Display for OperandType {
@@ -15,10 +53,294 @@ impl Display for OperandType {
             OperandType::Memory => write!(f, "Memory"),
             OperandType::Label => write!(f, "Label"),
             OperandType::Constant => write!(f, "Constant"),
+            OperandType::Indirect => write!(f, "Indirect"),
+            OperandType::Indexed => write!(f, "Indexed"),
+            OperandType::PostIncrement => write!(f, "PostIncrement"),
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    Halt,
+    Mover,
+    Movei,
+    Movem,
+    MoverIndirect,
+    MoverIndexed,
+    MoverPostIncrement,
+    MovemIndirect,
+    MovemIndexed,
+    MovemPostIncrement,
+    In,
+    Out,
+    Out16,
+    OutChar,
+    Add,
+    Addi,
+    Adc,
+    Adci,
+    Sub,
+    Subi,
+    Sbc,
+    Sbci,
+    Mult,
+    Multi,
+    Mult16,
+    Jmp,
+    Jz,
+    Jnz,
+    And,
+    Or,
+    Xor,
+    Not,
+    Shl,
+    Push,
+    Pop,
+    Call,
+    Ret,
+    Shr,
+    Cmp,
+    Cmpi,
+    Jg,
+    Jge,
+    Jl,
+    Jle,
+    Jne,
+    Je,
+    Ei,
+    Di,
+    Iret,
+}
+
+impl Opcode {
+    pub const ALL: [Opcode; 49] = [
+        Opcode::Halt,
+        Opcode::Mover,
+        Opcode::Movei,
+        Opcode::Movem,
+        Opcode::MoverIndirect,
+        Opcode::MoverIndexed,
+        Opcode::MoverPostIncrement,
+        Opcode::MovemIndirect,
+        Opcode::MovemIndexed,
+        Opcode::MovemPostIncrement,
+        Opcode::In,
+        Opcode::Out,
+        Opcode::Out16,
+        Opcode::OutChar,
+        Opcode::Add,
+        Opcode::Addi,
+        Opcode::Adc,
+        Opcode::Adci,
+        Opcode::Sub,
+        Opcode::Subi,
+        Opcode::Sbc,
+        Opcode::Sbci,
+        Opcode::Mult,
+        Opcode::Multi,
+        Opcode::Mult16,
+        Opcode::Jmp,
+        Opcode::Jz,
+        Opcode::Jnz,
+        Opcode::And,
+        Opcode::Or,
+        Opcode::Xor,
+        Opcode::Not,
+        Opcode::Shl,
+        Opcode::Push,
+        Opcode::Pop,
+        Opcode::Call,
+        Opcode::Ret,
+        Opcode::Shr,
+        Opcode::Cmp,
+        Opcode::Cmpi,
+        Opcode::Jg,
+        Opcode::Jge,
+        Opcode::Jl,
+        Opcode::Jle,
+        Opcode::Jne,
+        Opcode::Je,
+        Opcode::Ei,
+        Opcode::Di,
+        Opcode::Iret,
+    ];
+
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Opcode::Halt => "HALT",
+            Opcode::Mover
+            | Opcode::MoverIndirect
+            | Opcode::MoverIndexed
+            | Opcode::MoverPostIncrement => "MOVER",
+            Opcode::Movei => "MOVEI",
+            Opcode::Movem
+            | Opcode::MovemIndirect
+            | Opcode::MovemIndexed
+            | Opcode::MovemPostIncrement => "MOVEM",
+            Opcode::In => "IN",
+            Opcode::Out => "OUT",
+            Opcode::Out16 => "OUT_16",
+            Opcode::OutChar => "OUT_CHAR",
+            Opcode::Add => "ADD",
+            Opcode::Addi => "ADDI",
+            Opcode::Adc => "ADC",
+            Opcode::Adci => "ADCI",
+            Opcode::Sub => "SUB",
+            Opcode::Subi => "SUBI",
+            Opcode::Sbc => "SBC",
+            Opcode::Sbci => "SBCI",
+            Opcode::Mult => "MULT",
+            Opcode::Multi => "MULTI",
+            Opcode::Mult16 => "MULT_16",
+            Opcode::Jmp => "JMP",
+            Opcode::Jz => "JZ",
+            Opcode::Jnz => "JNZ",
+            Opcode::And => "AND",
+            Opcode::Or => "OR",
+            Opcode::Xor => "XOR",
+            Opcode::Not => "NOT",
+            Opcode::Shl => "SHL",
+            Opcode::Push => "PUSH",
+            Opcode::Pop => "POP",
+            Opcode::Call => "CALL",
+            Opcode::Ret => "RET",
+            Opcode::Shr => "SHR",
+            Opcode::Cmp => "CMP",
+            Opcode::Cmpi => "CMPI",
+            Opcode::Jg => "JG",
+            Opcode::Jge => "JGE",
+            Opcode::Jl => "JL",
+            Opcode::Jle => "JLE",
+            Opcode::Jne => "JNE",
+            Opcode::Je => "JE",
+            Opcode::Ei => "EI",
+            Opcode::Di => "DI",
+            Opcode::Iret => "IRET",
+        }
+    }
+
+    pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|opcode| opcode.mnemonic() == mnemonic)
+    }
+
+    // how the memory operand of a MOVER or MOVEM form is addressed
+    pub fn addressing(&self) -> Option<OperandType> {
+        match self {
+            Opcode::Mover | Opcode::Movem => Some(OperandType::Memory),
+            Opcode::MoverIndirect | Opcode::MovemIndirect => Some(OperandType::Indirect),
+            Opcode::MoverIndexed | Opcode::MovemIndexed => Some(OperandType::Indexed),
+            Opcode::MoverPostIncrement | Opcode::MovemPostIncrement => {
+                Some(OperandType::PostIncrement)
+            }
+            _ => None,
+        }
+    }
+
+    // the form of `mnemonic` whose addressing mode is among `operand_types`
+    pub fn from_form(mnemonic: &str, operand_types: &[OperandType]) -> Option<Self> {
+        Self::ALL.into_iter().find(|opcode| {
+            opcode.mnemonic() == mnemonic
+                && opcode
+                    .addressing()
+                    .is_none_or(|addressing| operand_types.contains(&addressing))
+        })
+    }
+}
+
+impl Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.mnemonic())
+    }
+}
+
+// how an operand of the type names register `number`, None for types without a register
+fn register_syntax(operand_type: OperandType, number: u64) -> Option<String> {
+    match operand_type {
+        OperandType::Register => Some(format!("R{}", number)),
+        OperandType::Indirect => Some(format!("[R{}]", number)),
+        OperandType::Indexed => Some(format!("[R{}+0]", number)),
+        OperandType::PostIncrement => Some(format!("[R{}+]", number)),
+        _ => None,
+    }
+}
+
+// an operand the regex accepts whose value needs more than `bit_count` bits. Registers are
+// probed with every number below 1024 and the first one past the width, numbers with the
+// first value past the width written in decimal, hexadecimal and binary
+fn too_wide(regex: &Regex, operand_type: OperandType, bit_count: u8) -> Option<String> {
+    let limit = 1u64 << bit_count.min(63);
+    if matches!(
+        operand_type,
+        OperandType::Memory | OperandType::Label | OperandType::Constant
+    ) {
+        return [
+            limit.to_string(),
+            format!("0x{:X}", limit),
+            format!("0b{:b}", limit),
+        ]
+        .into_iter()
+        .find(|number| regex.is_match(number));
+    }
+    (0..1024)
+        .chain(std::iter::once(limit))
+        .filter(|number| *number >= limit)
+        .filter_map(|number| register_syntax(operand_type, number))
+        .find(|register| regex.is_match(register))
+}
+
+// decimal numbers from 0 up to `max`, digit by digit: anything shorter, then every number
+// that matches a prefix of `max` and is smaller in the next digit, then `max` itself
+fn decimal_pattern(max: u64) -> String {
+    let max = max.to_string();
+    let mut alternatives = Vec::new();
+    if max.len() > 1 {
+        alternatives.push(format!("[0-9]{{1,{}}}", max.len() - 1));
+    }
+    for (index, digit) in max.bytes().enumerate() {
+        let rest = max.len() - index - 1;
+        let smaller = match digit {
+            b'0' => continue,
+            b'1' => String::from("0"),
+            _ => format!("[0-{}]", digit as char as u32 - 1 - '0' as u32),
+        };
+        alternatives.push(match rest {
+            0 => format!("{}{}", &max[..index], smaller),
+            _ => format!("{}{}[0-9]{{{}}}", &max[..index], smaller, rest),
+        });
+    }
+    alternatives.push(max);
+    format!("0*({})", alternatives.join("|"))
+}
+
+// decimal, hexadecimal, binary and character literals whose value fits in `bit_count` bits,
+// characters are range checked by the assembler
+fn number_pattern(bit_count: u8) -> String {
+    let max = (1u64 << bit_count) - 1;
+    let hex_digits = bit_count.div_ceil(4) as usize;
+    let top = match max >> (4 * (hex_digits - 1)) {
+        15 => String::from("[0-9A-Fa-f]"),
+        top => format!("[0-{}]", top),
+    };
+    let hex = match hex_digits {
+        1 => top,
+        _ => format!(
+            "([0-9A-Fa-f]{{1,{}}}|{}[0-9A-Fa-f]{{{}}})",
+            hex_digits - 1,
+            top,
+            hex_digits - 1
+        ),
+    };
+    format!(
+        "({}|0x0*{}|0b0*[01]{{1,{}}}|'.+')",
+        decimal_pattern(max),
+        hex,
+        bit_count
+    )
+}
+
 #[derive(Clone)]
 pub struct OperandSpec {
     pub operand_type: OperandType,
@@ -31,95 +353,237 @@ impl OperandSpec {
         Self {
             operand_type,
             operand_regex: operand_regex.to_string(),
-            bit_count: bit_count,
+            bit_count,
         }
     }
 }
 
+#[derive(Clone)]
 pub struct Operation {
+    pub kind: Opcode,
     pub operation_name: String,
     pub opcode: u32,
     pub operands: Vec<OperandSpec>,
 }
 
 impl Operation {
-    fn new(operation_name: &str, opcode: u32, operands: Vec<OperandSpec>) -> Self {
+    fn new(kind: Opcode, opcode: u32, operands: Vec<OperandSpec>) -> Self {
         Self {
-            operation_name: operation_name.to_string(),
+            kind,
+            operation_name: kind.mnemonic().to_string(),
             opcode,
             operands,
         }
     }
+
+    pub fn operand_bit_count(&self) -> u32 {
+        self.operands
+            .iter()
+            .map(|operand| operand.bit_count as u32)
+            .sum()
+    }
 }
 
+#[derive(Clone)]
 pub struct OptSpec {
     pub opcode_bit_count: u8,
+    pub register_count: u32,
+    // width of the program counter, code addresses are bit addresses
+    pub address_bit_count: u8,
+    // bytes of data memory, the stack grows down from its end
+    pub data_memory_size: u32,
     opttab: Vec<Operation>,
 }
 
 impl OptSpec {
+    // the ISA described by isa.toml, built into every crate that uses it
     pub fn clone() -> Self {
-        let reg = OperandSpec::new("^R[0-3]$", 2, OperandType::Register);
-        let mem = OperandSpec::new("^[0-9]+$", 4, OperandType::Memory);
-        let label = OperandSpec::new("^[A-Z]+$", 8, OperandType::Label);
-        let constant = OperandSpec::new("^-?[0-9]+$", 8, OperandType::Constant);
-
-        let no_operands = vec![];
-        let reg_mem = vec![reg.clone(), mem.clone()];
-        let reg_reg_reg = vec![reg.clone(), reg.clone(), reg.clone()];
-        let reg_reg_const = vec![reg.clone(), reg.clone(), constant.clone()];
-        let reg_only = vec![reg.clone()];
-        let reg_reg = vec![reg.clone(), reg.clone()];
-        let label = vec![label.clone()];
-        let reg_const = vec![reg.clone(), constant.clone()];
-        let constant_only = vec![constant.clone()];
+        Self::from_toml(DEFAULT_ISA).expect("the built-in isa.toml is valid")
+    }
 
-        Self {
-            opcode_bit_count: 6,
-            opttab: vec![
-                Operation::new("HALT", 0, no_operands.clone()),
-                Operation::new("MOVER", 1, reg_mem.clone()),
-                Operation::new("MOVEI", 2, reg_const.clone()),
-                Operation::new("MOVEM", 3, reg_mem.clone()),
-                Operation::new("IN", 5, reg_only.clone()),
-                Operation::new("OUT", 6, reg_only.clone()),
-                Operation::new("OUT_16", 7, no_operands.clone()),
-                Operation::new("OUT_CHAR", 4, reg_only.clone()),
-                Operation::new("ADD", 8, reg_reg_reg.clone()),
-                Operation::new("ADDI", 9, reg_reg_const.clone()),
-                Operation::new("ADC", 10, reg_reg_reg.clone()),
-                Operation::new("ADCI", 11, reg_reg_const.clone()),
-                Operation::new("SUB", 13, reg_reg_reg.clone()),
-                Operation::new("SUBI", 14, reg_reg_const.clone()),
-                Operation::new("SBC", 15, reg_reg_reg.clone()),
-                Operation::new("SBCI", 16, reg_reg_const.clone()),
-                Operation::new("MULT", 18, reg_reg_reg.clone()),
-                Operation::new("MULTI", 19, reg_reg_const.clone()),
-                Operation::new("MULT_16", 20, reg_only.clone()),
-                Operation::new("JMP", 21, label.clone()),
-                Operation::new("JZ", 22, label.clone()),
-                Operation::new("JNZ", 23, label.clone()),
-                Operation::new("AND", 24, reg_reg_reg.clone()),
-                Operation::new("OR", 25, reg_reg_reg.clone()),
-                Operation::new("XOR", 26, reg_reg_reg.clone()),
-                Operation::new("NOT", 27, reg_only.clone()),
-                Operation::new("SHL", 28, reg_only.clone()),
-                Operation::new("PUSH", 32, reg_only.clone()),
-                Operation::new("POP", 33, reg_only.clone()),
-                Operation::new("CALL", 34, label.clone()),
-                Operation::new("RET", 35, no_operands.clone()),
-                Operation::new("SHR", 29, reg_only.clone()),
-                Operation::new("CMP", 30, reg_reg.clone()),
-                Operation::new("CMPI", 31, reg_const.clone()),
-                Operation::new("JG", 38, label.clone()),
-                Operation::new("JGE", 36, label.clone()),
-                Operation::new("JL", 37, label.clone()),
-                Operation::new("JLE", 39, label.clone()),
-                Operation::new("JNE", 40, label.clone()),
-                Operation::new("JE", 41, label.clone()),
-                Operation::new("DB", 42, constant_only.clone()),
-            ],
+    // the ISA a binary without an ISA id runs with, if not this one. Such binaries predate
+    // wider code addresses and were built with 8 bit labels, so the built-in encoding is
+    // narrowed back to them, while any other description must already use 8 bit labels
+    pub fn for_legacy_binary(&self) -> Result<Option<Self>, IsaError> {
+        let wide_label = self
+            .opttab
+            .iter()
+            .flat_map(|operation| {
+                operation
+                    .operands
+                    .iter()
+                    .map(move |operand| (operation, operand))
+            })
+            .find(|(_, operand)| {
+                operand.operand_type == OperandType::Label && operand.bit_count != 8
+            });
+        let Some((operation, operand)) = wide_label else {
+            return Ok(None);
+        };
+        if self.identifier() != Self::clone().identifier() {
+            return Err(IsaError::LegacyLabels {
+                mnemonic: operation.operation_name.clone(),
+                bit_count: operand.bit_count,
+            });
+        }
+        let mut legacy = Clone::clone(self);
+        legacy.address_bit_count = 8;
+        for operand in legacy
+            .opttab
+            .iter_mut()
+            .flat_map(|operation| &mut operation.operands)
+        {
+            if operand.operand_type == OperandType::Label {
+                operand.bit_count = 8;
+            }
+        }
+        Ok(Some(legacy))
+    }
+
+    // checks the invariants the assembler and the VM rely on. Register numbers are taken
+    // straight from the operand, so their regex must not accept one that does not fit, and
+    // neither may a memory, label or constant regex accept a number past its width
+    pub fn validate(&self) -> Result<(), IsaError> {
+        if !(1..=32).contains(&self.opcode_bit_count) {
+            return Err(IsaError::OpcodeWidth(self.opcode_bit_count));
         }
+        if !(8..=24).contains(&self.address_bit_count) {
+            return Err(IsaError::AddressWidth(self.address_bit_count));
+        }
+        if !(64..=65536).contains(&self.data_memory_size) {
+            return Err(IsaError::DataMemorySize(self.data_memory_size));
+        }
+        let mut checked = HashSet::new();
+        for (index, operation) in self.opttab.iter().enumerate() {
+            let earlier = &self.opttab[..index];
+            // the forms of MOVER and MOVEM share their mnemonic, other instructions may not
+            if earlier.iter().any(|other| {
+                other.kind == operation.kind
+                    || (other.operation_name == operation.operation_name
+                        && other.kind.mnemonic() != operation.kind.mnemonic())
+            }) {
+                return Err(IsaError::DuplicateMnemonic(
+                    operation.operation_name.clone(),
+                ));
+            }
+            if let Some(other) = earlier
+                .iter()
+                .find(|other| other.opcode == operation.opcode)
+            {
+                return Err(IsaError::DuplicateOpcode {
+                    opcode: operation.opcode,
+                    first: other.operation_name.clone(),
+                    second: operation.operation_name.clone(),
+                });
+            }
+            if operation.opcode as u64 >= 1u64 << self.opcode_bit_count {
+                return Err(IsaError::OpcodeOverflow {
+                    mnemonic: operation.operation_name.clone(),
+                    opcode: operation.opcode,
+                    opcode_bit_count: self.opcode_bit_count,
+                });
+            }
+            if operation.operands.len() > MAX_OPERANDS {
+                return Err(IsaError::TooManyOperands {
+                    mnemonic: operation.operation_name.clone(),
+                    count: operation.operands.len(),
+                });
+            }
+            for operand in &operation.operands {
+                // the register of an indexed operand sits below its offset
+                let mut register_bits = operand.bit_count;
+                if operand.operand_type == OperandType::Indexed {
+                    register_bits = self.register_bits();
+                    if operand.bit_count <= register_bits {
+                        return Err(IsaError::IndexedWidth {
+                            mnemonic: operation.operation_name.clone(),
+                            bit_count: operand.bit_count,
+                            register_bits,
+                        });
+                    }
+                }
+                // operands mostly share a handful of specs, each is compiled and probed once
+                if !checked.insert((
+                    &operand.operand_regex,
+                    operand.bit_count,
+                    operand.operand_type,
+                )) {
+                    continue;
+                }
+                let regex =
+                    Regex::new(&operand.operand_regex).map_err(|error| IsaError::InvalidRegex {
+                        mnemonic: operation.operation_name.clone(),
+                        regex: operand.operand_regex.clone(),
+                        error: error.to_string(),
+                    })?;
+                if let Some(example) = too_wide(&regex, operand.operand_type, register_bits) {
+                    return Err(IsaError::RegexRange {
+                        mnemonic: operation.operation_name.clone(),
+                        regex: operand.operand_regex.clone(),
+                        example,
+                        bit_count: register_bits,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // bits that number every register, the register part of an indexed operand
+    pub fn register_bits(&self) -> u8 {
+        (u32::BITS - self.register_count.saturating_sub(1).leading_zeros()) as u8
+    }
+
+    // bits of code the program counter can address
+    pub fn address_space(&self) -> u32 {
+        1 << self.address_bit_count
+    }
+
+    // bytes CALL and interrupts push for a return address
+    pub fn address_bytes(&self) -> u32 {
+        (self.address_bit_count as u32).div_ceil(8)
+    }
+
+    // data cells the VM keeps for itself, the devices and the interrupt vectors, a data image
+    // skips them
+    pub fn reserved_data(&self) -> Range<u32> {
+        DEVICE_WINDOW..VECTOR_TABLE + INTERRUPT_LINES * self.address_bytes()
+    }
+
+    pub fn max_instruction_size(&self) -> u32 {
+        self.opcode_bit_count as u32
+            + self
+                .opttab
+                .iter()
+                .map(|operation| operation.operand_bit_count())
+                .max()
+                .unwrap_or(0)
+    }
+
+    // FNV-1a over the encoding of every operation, binaries built for another ISA won't match
+    pub fn identifier(&self) -> u32 {
+        let mut hash: u32 = 0x811c9dc5;
+        let mut feed = |bytes: &[u8]| {
+            for byte in bytes {
+                hash ^= *byte as u32;
+                hash = hash.wrapping_mul(0x01000193);
+            }
+        };
+        feed(&[self.opcode_bit_count, self.address_bit_count]);
+        let mut operations: Vec<&Operation> = self.opttab.iter().collect();
+        operations.sort_by_key(|operation| operation.opcode);
+        for operation in operations {
+            feed(&operation.opcode.to_be_bytes());
+            feed(operation.operation_name.as_bytes());
+            for operand in &operation.operands {
+                feed(&[operand.operand_type as u8, operand.bit_count]);
+            }
+        }
+        hash
+    }
+
+    pub fn get_operations(&self) -> &[Operation] {
+        &self.opttab
     }
 
     pub fn get_by_opcode(&self, opcode: &u32) -> Option<&Operation> {
@@ -131,4 +595,131 @@ impl OptSpec {
             .iter()
             .find(|op| op.operation_name == operation_name)
     }
+
+    // every form of an instruction, MOVER and MOVEM have one per addressing mode
+    pub fn get_forms<'a>(&'a self, operation_name: &str) -> impl Iterator<Item = &'a Operation> {
+        self.opttab
+            .iter()
+            .filter(move |op| op.operation_name == operation_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(spec: &OptSpec) -> String {
+        spec.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_validate() {
+        OptSpec::clone().validate().unwrap();
+
+        let mut spec = OptSpec::clone();
+        spec.opttab[1].opcode = 0;
+        assert_eq!(error(&spec), "HALT and MOVER share opcode 0");
+
+        let mut spec = OptSpec::clone();
+        spec.opttab[1].operation_name = String::from("HALT");
+        assert_eq!(error(&spec), "HALT is described more than once");
+
+        let mut spec = OptSpec::clone();
+        spec.opttab[0].opcode = 64;
+        assert!(error(&spec).contains("does not fit in 6 bits"));
+
+        let mut spec = OptSpec::clone();
+        spec.opttab[1].operands[1].operand_regex = String::from("^[0-9+$");
+        assert!(error(&spec).contains("does not compile"));
+
+        let mut spec = OptSpec::clone();
+        spec.opttab[1].operands[0].operand_regex = String::from("^R[0-7]$");
+        assert_eq!(
+            error(&spec),
+            "Operand regex ^R[0-7]$ of MOVER accepts R4, which does not fit in 2 bits"
+        );
+        spec.opttab[1].operands[0].operand_regex = String::from("^R[0-9]+$");
+        assert!(error(&spec).contains("accepts R4,"));
+
+        let mut spec = OptSpec::clone();
+        assert_eq!(spec.register_bits(), 2);
+        let indexed = spec
+            .opttab
+            .iter()
+            .position(|operation| operation.kind == Opcode::MoverIndexed)
+            .unwrap();
+        spec.opttab[indexed].operands[1].bit_count = 2;
+        assert!(error(&spec).contains("leaves no offset next to 2 register bits"));
+        spec.opttab[indexed].operands[1].bit_count = 6;
+        spec.opttab[indexed].operands[1].operand_regex = String::from(r"^\[R[0-7]\+.+\]$");
+        assert!(error(&spec).contains("accepts [R4+0],"));
+
+        let mut spec = OptSpec::clone();
+        assert_eq!(spec.address_space(), 1 << 16);
+        assert_eq!(spec.address_bytes(), 2);
+        assert_eq!(spec.reserved_data(), 12..24);
+        let legacy = spec.for_legacy_binary().unwrap().unwrap();
+        legacy.validate().unwrap();
+        assert_eq!(legacy.address_bit_count, 8);
+        assert!(legacy.for_legacy_binary().unwrap().is_none());
+        let mut custom = OptSpec::clone();
+        custom.opcode_bit_count = 7;
+        assert_eq!(
+            custom.for_legacy_binary().err().unwrap().to_string(),
+            "The binary records no ISA, so it was built with 8 bit labels, but JMP takes a 16 bit label in this ISA"
+        );
+        spec.address_bit_count = 24;
+        assert_eq!(spec.reserved_data(), 12..28);
+        spec.address_bit_count = 32;
+        assert_eq!(
+            error(&spec),
+            "address_bit_count must be between 8 and 24, got 32"
+        );
+        let mut spec = OptSpec::clone();
+        spec.data_memory_size = 32;
+        assert_eq!(
+            error(&spec),
+            "data_memory_size must be between 64 and 65536 bytes, got 32"
+        );
+
+        // numbers may not reach past their width either, in any base
+        let mut spec = OptSpec::clone();
+        spec.opttab[1].operands[1].operand_regex = String::from("^[0-9]+$");
+        assert_eq!(
+            error(&spec),
+            "Operand regex ^[0-9]+$ of MOVER accepts 16, which does not fit in 4 bits"
+        );
+        spec.opttab[1].operands[1].operand_regex = String::from("^(1[0-5]|[0-9]|0x[0-9A-F]+)$");
+        assert!(error(&spec).contains("accepts 0x10,"));
+        spec.opttab[1].operands[1].operand_regex = String::from("^0b[01]{1,8}$");
+        assert!(error(&spec).contains("accepts 0b10000,"));
+        let jump = spec
+            .opttab
+            .iter()
+            .position(|operation| operation.kind == Opcode::Jmp)
+            .unwrap();
+        spec.opttab[jump].operands[0].operand_regex = String::from("^([A-Z]+|[0-9]+)$");
+        spec.opttab[1].operands[1].operand_regex = format!("^{}$", number_pattern(4));
+        assert!(error(&spec).contains("accepts 65536,"));
+
+        // the built-in patterns accept exactly the values that fit
+        for bit_count in [1, 4, 8, 10, 16] {
+            let regex = Regex::new(&format!("^{}$", number_pattern(bit_count))).unwrap();
+            let max = (1u64 << bit_count) - 1;
+            for value in [0, max / 3, max] {
+                for literal in [
+                    value.to_string(),
+                    format!("0x{:x}", value),
+                    format!("0b{:b}", value),
+                ] {
+                    assert!(regex.is_match(&literal), "{} {}", bit_count, literal);
+                }
+            }
+            assert!(regex.is_match(&format!("00{}", max)));
+            assert!(too_wide(&regex, OperandType::Memory, bit_count).is_none());
+            for past in [max + 1, max + 10, max * 10] {
+                assert!(!regex.is_match(&past.to_string()), "{} {}", bit_count, past);
+            }
+        }
+    }
 }
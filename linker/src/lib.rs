@@ -0,0 +1,402 @@
+use std::collections::BTreeMap;
+
+use container::{Container, ContainerError, Object, Section, Target};
+use isa::{OptSpec, STACK_RESERVE};
+
+#[derive(Debug, thiserror::Error)]
+pub enum LinkerError {
+    #[error("Invalid object {name}: {source}")]
+    InvalidObject {
+        name: String,
+        source: ContainerError,
+    },
+    #[error("No objects to link")]
+    NoObjects,
+    #[error("{name} was built for ISA {found:08X}, but {first} for ISA {expected:08X}")]
+    IsaMismatch {
+        name: String,
+        found: u32,
+        first: String,
+        expected: u32,
+    },
+    #[error("Symbol '{symbol}' is defined in both {first} and {second}")]
+    DuplicateSymbol {
+        symbol: String,
+        first: String,
+        second: String,
+    },
+    #[error("Undefined symbols:\n{0}")]
+    UndefinedSymbols(String),
+    #[error(
+        "{name}: the field at bit {offset} gets {value}, which does not fit in {bit_count} bits"
+    )]
+    OutOfRange {
+        name: String,
+        offset: u32,
+        value: i64,
+        bit_count: u8,
+    },
+    #[error(
+        "Data sections take {size} bytes, but data memory holds {memory} and the top {STACK_RESERVE} are kept for the stack"
+    )]
+    DataOverflow { size: usize, memory: u32 },
+}
+
+// where one object ends up in the executable
+struct Placement {
+    code: u32,
+    data: u32,
+}
+
+struct Global<'a> {
+    address: u32,
+    section: Section,
+    object: &'a str,
+}
+
+fn bit(bytes: &[u8], index: u32) -> u8 {
+    (bytes[index as usize / 8] >> (7 - index % 8)) & 1
+}
+
+fn set_bit(bytes: &mut [u8], index: u32, value: u8) {
+    let mask = 1 << (7 - index % 8);
+    if value == 0 {
+        bytes[index as usize / 8] &= !mask;
+    } else {
+        bytes[index as usize / 8] |= mask;
+    }
+}
+
+// lays out the objects one after the other, code and data each in the order they were added
+pub struct Linker {
+    optspec: OptSpec,
+    objects: Vec<(String, Object)>,
+}
+
+impl Default for Linker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Self {
+            optspec: OptSpec::clone(),
+            objects: Vec::new(),
+        }
+    }
+
+    // the data layout, the reserved cells and the memory size, comes from the ISA
+    pub fn set_isa(&mut self, optspec: OptSpec) {
+        self.optspec = optspec;
+    }
+
+    // `name` only shows up in errors
+    pub fn add(&mut self, name: &str, bytes: &[u8]) -> Result<(), LinkerError> {
+        let object = Object::from_bytes(bytes).map_err(|source| LinkerError::InvalidObject {
+            name: name.to_string(),
+            source,
+        })?;
+        self.add_object(name, object);
+        Ok(())
+    }
+
+    pub fn add_object(&mut self, name: &str, object: Object) {
+        self.objects.push((name.to_string(), object));
+    }
+
+    fn placements(&self) -> Vec<Placement> {
+        let mut placements = Vec::new();
+        let (mut code, mut data) = (0, 0);
+        let reserved = self.optspec.reserved_data();
+        for (_, object) in &self.objects {
+            let size = object.data.len() as u32;
+            // as in the assembler, data that would reach into the reserved cells goes after them
+            if data < reserved.end && data + size > reserved.start {
+                data = reserved.end;
+            }
+            placements.push(Placement { code, data });
+            code += object.code_bits;
+            data += size;
+        }
+        placements
+    }
+
+    fn globals(&self, placements: &[Placement]) -> Result<BTreeMap<&str, Global<'_>>, LinkerError> {
+        let mut globals: BTreeMap<&str, Global> = BTreeMap::new();
+        for ((name, object), placement) in self.objects.iter().zip(placements) {
+            for (symbol, (section, address)) in &object.globals {
+                if let Some(first) = globals.get(symbol.as_str()) {
+                    return Err(LinkerError::DuplicateSymbol {
+                        symbol: symbol.clone(),
+                        first: first.object.to_string(),
+                        second: name.clone(),
+                    });
+                }
+                let base = match section {
+                    Section::Code => placement.code,
+                    Section::Data => placement.data,
+                };
+                globals.insert(
+                    symbol,
+                    Global {
+                        address: base + address,
+                        section: *section,
+                        object: name,
+                    },
+                );
+            }
+        }
+        Ok(globals)
+    }
+
+    pub fn link(&self) -> Result<Container, LinkerError> {
+        let Some((first, first_object)) = self.objects.first() else {
+            return Err(LinkerError::NoObjects);
+        };
+        if let Some((name, object)) = self
+            .objects
+            .iter()
+            .find(|(_, object)| object.isa != first_object.isa)
+        {
+            return Err(LinkerError::IsaMismatch {
+                name: name.clone(),
+                found: object.isa,
+                first: first.clone(),
+                expected: first_object.isa,
+            });
+        }
+
+        let placements = self.placements();
+        let globals = self.globals(&placements)?;
+        let mut undefined = String::new();
+        for (name, object) in &self.objects {
+            let mut symbols: Vec<&String> = object.externs.iter().collect();
+            symbols.extend(object.relocations.iter().filter_map(|relocation| {
+                match &relocation.target {
+                    Target::Symbol(symbol) => Some(symbol),
+                    Target::Section(_) => None,
+                }
+            }));
+            symbols.sort();
+            symbols.dedup();
+            for symbol in symbols {
+                if !globals.contains_key(symbol.as_str()) {
+                    undefined.push_str(&format!("  '{}' used in {}\n", symbol, name));
+                }
+            }
+        }
+        if !undefined.is_empty() {
+            return Err(LinkerError::UndefinedSymbols(undefined));
+        }
+
+        let code_bits: u32 = self
+            .objects
+            .iter()
+            .map(|(_, object)| object.code_bits)
+            .sum();
+        let mut code = vec![0; code_bits.div_ceil(8) as usize];
+        let mut data = Vec::new();
+        for ((name, object), placement) in self.objects.iter().zip(&placements) {
+            for index in 0..object.code_bits {
+                set_bit(&mut code, placement.code + index, bit(&object.code, index));
+            }
+            data.resize(placement.data as usize, 0);
+            data.extend(&object.data);
+            for relocation in &object.relocations {
+                let base = match &relocation.target {
+                    Target::Section(Section::Code) => placement.code,
+                    Target::Section(Section::Data) => placement.data,
+                    Target::Symbol(symbol) => globals[symbol.as_str()].address,
+                };
+                let value = base as i64 + relocation.addend as i64;
+                if !(0..1i64 << relocation.bit_count).contains(&value) {
+                    return Err(LinkerError::OutOfRange {
+                        name: name.clone(),
+                        offset: relocation.offset,
+                        value,
+                        bit_count: relocation.bit_count,
+                    });
+                }
+                for index in 0..relocation.bit_count as u32 {
+                    let shift = relocation.bit_count as u32 - 1 - index;
+                    set_bit(
+                        &mut code,
+                        placement.code + relocation.offset + index,
+                        (value >> shift) as u8 & 1,
+                    );
+                }
+            }
+        }
+        let memory = self.optspec.data_memory_size;
+        if data.len() > (memory - STACK_RESERVE) as usize {
+            return Err(LinkerError::DataOverflow {
+                size: data.len(),
+                memory,
+            });
+        }
+
+        let mut container = Container::new(first_object.isa, code, code_bits);
+        container.data = data;
+        // the symbol section holds code labels, as written by the assembler
+        container.symbols = Some(
+            globals
+                .iter()
+                .filter(|(_, global)| global.section == Section::Code)
+                .map(|(symbol, global)| (symbol.to_string(), global.address))
+                .collect(),
+        );
+        Ok(container)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use container::Relocation;
+
+    use super::*;
+
+    // JMP <8 bit target>: opcode 21 in 6 bits, the target field starts at bit 6
+    fn jump(target: Target, addend: i32) -> Object {
+        Object {
+            isa: 7,
+            code: vec![0b01010100, 0],
+            code_bits: 14,
+            relocations: vec![Relocation {
+                offset: 6,
+                bit_count: 8,
+                target,
+                addend,
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn global(object: &mut Object, symbol: &str, section: Section, address: u32) {
+        object
+            .globals
+            .insert(symbol.to_string(), (section, address));
+    }
+
+    #[test]
+    fn test_link() {
+        let mut main = jump(Target::Symbol(String::from("NEXT")), 1);
+        main.externs = vec![String::from("NEXT")];
+        main.data = vec![1, 2];
+        global(&mut main, "MAIN", Section::Code, 0);
+        let mut next = jump(Target::Section(Section::Code), 0);
+        next.data = vec![3];
+        global(&mut next, "NEXT", Section::Code, 0);
+        global(&mut next, "TABLE", Section::Data, 0);
+
+        let mut linker = Linker::new();
+        linker.add("main.o", &main.to_bytes()).unwrap();
+        linker.add_object("next.o", next);
+        let container = linker.link().unwrap();
+        assert_eq!(container.code_bits, 28);
+        // JMP 15 then JMP 14, packed back to back
+        assert_eq!(
+            container.code,
+            vec![0b01010100, 0b00111101, 0b01010000, 0b11100000]
+        );
+        assert_eq!(container.data, vec![1, 2, 3]);
+        assert_eq!(
+            container.symbols,
+            Some(BTreeMap::from([
+                (String::from("MAIN"), 0),
+                (String::from("NEXT"), 14)
+            ]))
+        );
+    }
+
+    #[test]
+    fn test_link_reserved_data() {
+        let mut first = jump(Target::Section(Section::Code), 0);
+        first.data = vec![1; 10];
+        let mut second = jump(Target::Section(Section::Data), 0);
+        second.data = vec![2; 3];
+        let mut linker = Linker::new();
+        linker.add_object("first.o", first);
+        linker.add_object("second.o", second);
+        let container = linker.link().unwrap();
+        // the second data section would reach the devices, so it starts after the vectors at 24
+        let mut data = vec![1; 10];
+        data.extend([0; 14]);
+        data.extend([2; 3]);
+        assert_eq!(container.data, data);
+        // JMP 0, then the second object's reference to its data is patched with 24
+        assert_eq!(
+            container.code,
+            vec![0b01010100, 0b00000001, 0b01010001, 0b10000000]
+        );
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let mut first = jump(Target::Section(Section::Code), 0);
+        global(&mut first, "MAIN", Section::Code, 0);
+        let mut linker = Linker::new();
+        linker.add_object("a.o", first.clone());
+        linker.add_object("b.o", first.clone());
+        assert!(matches!(
+            linker.link(),
+            Err(LinkerError::DuplicateSymbol { symbol, first, second })
+                if symbol == "MAIN" && first == "a.o" && second == "b.o"
+        ));
+
+        let mut linker = Linker::new();
+        linker.add_object("a.o", jump(Target::Symbol(String::from("GONE")), 0));
+        assert_eq!(
+            linker.link().unwrap_err().to_string(),
+            "Undefined symbols:\n  'GONE' used in a.o\n"
+        );
+
+        let mut linker = Linker::new();
+        linker.add_object("a.o", jump(Target::Section(Section::Code), 300));
+        assert!(matches!(
+            linker.link(),
+            Err(LinkerError::OutOfRange { value: 300, .. })
+        ));
+
+        let mut linker = Linker::new();
+        assert!(matches!(linker.link(), Err(LinkerError::NoObjects)));
+        assert!(matches!(
+            linker.add("bad.o", b"VMBN"),
+            Err(LinkerError::InvalidObject { .. })
+        ));
+    }
+
+    #[test]
+    fn test_link_assembled() {
+        let mut assembler = assembler::MyAssembler::new().unwrap();
+        let first = assembler
+            .assemble_object("f1.asm", "MOVEI R0, 1\nMOVEI R1, 2\nMOVEI R2, 3\nHALT\n")
+            .unwrap();
+        let second = assembler
+            .assemble_object("f2.asm", "GLOBAL T\nT: MOVEI R3, T + 1\nHALT\n")
+            .unwrap();
+        let mut linker = Linker::new();
+        linker.add_object("f1.o", first);
+        linker.add_object("f2.o", second);
+        let container = linker.link().unwrap();
+        assert_eq!(container.symbols.unwrap()["T"], 54);
+        // the constant of MOVEI R3 follows the opcode and the register, at bit 62
+        let bits: u32 = (62..70).fold(0, |value, bit| {
+            value << 1 | (container.code[bit / 8] >> (7 - bit % 8) & 1) as u32
+        });
+        assert_eq!(bits, 55);
+
+        // the linker only adds, so a byte of a label cannot be patched after linking
+        for operand in ["LOW(T)", "HIGH(T)", "T & 0xFF", "T >> 8"] {
+            let error = assembler
+                .assemble_object("f2.asm", &format!("T: MOVEI R3, {}\nHALT\n", operand))
+                .unwrap_err();
+            assert!(
+                error.to_string().contains("cannot be relocated"),
+                "{}",
+                error
+            );
+        }
+    }
+}
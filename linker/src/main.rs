@@ -0,0 +1,84 @@
+use args::Args;
+use isa::OptSpec;
+use linker::Linker;
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    process,
+};
+
+const DEFAULT_OUTPUT: &str = "output.bin";
+
+fn main() {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("Failed to parse arguments:\n\t{}", err);
+            process::exit(1);
+        }
+    };
+    if args.inputs.is_empty() {
+        eprintln!(
+            "Usage: linker <file.o|->... [-o|--output <file.bin|->] [--symbols=<file.sym>] [--isa <file.toml>]"
+        );
+        process::exit(1);
+    }
+
+    let mut linker = Linker::new();
+    if let Some(path) = &args.isa {
+        match OptSpec::load(path) {
+            Ok(isa) => linker.set_isa(isa),
+            Err(err) => {
+                eprintln!("Failed to load ISA:\n\t{}", err);
+                process::exit(1);
+            }
+        }
+    }
+    for input in &args.inputs {
+        let mut bytes = Vec::new();
+        let result = if input == "-" {
+            io::stdin().read_to_end(&mut bytes)
+        } else {
+            File::open(input).and_then(|mut file| file.read_to_end(&mut bytes))
+        };
+        if let Err(err) = result {
+            eprintln!("Failed to read {}:\n\t{}", input, err);
+            process::exit(1);
+        }
+        if let Err(err) = linker.add(input, &bytes) {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    }
+
+    let container = match linker.link() {
+        Ok(container) => container,
+        Err(err) => {
+            eprintln!("Failed to link:\n{}", err);
+            process::exit(1);
+        }
+    };
+    let output = args.output.as_deref().unwrap_or(DEFAULT_OUTPUT);
+    let result = if output == "-" {
+        io::stdout().write_all(&container.to_bytes())
+    } else {
+        fs::write(output, container.to_bytes())
+    };
+    if let Err(err) = result {
+        eprintln!("Failed to write {}:\n\t{}", output, err);
+        process::exit(1);
+    }
+    // same `LABEL BIT-ADDRESS` lines as the assembler writes
+    if let Some(symbols) = &args.symbols {
+        let mut labels: Vec<(&String, &u32)> = container.symbols.iter().flatten().collect();
+        labels.sort_by_key(|(label, address)| (**address, label.as_str()));
+        let lines: String = labels
+            .iter()
+            .map(|(label, address)| format!("{} {}\n", label, address))
+            .collect();
+        if let Err(err) = fs::write(symbols, lines) {
+            eprintln!("Failed to write symbols:\n\t{}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::{fs::File, process};
+
+use vm::trace::{TraceRecord, first_divergence, read_trace};
+
+fn load(path: &str) -> Vec<TraceRecord> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) => {
+            println!("Failed to open {}:\n\t{}", path, err);
+            process::exit(2);
+        }
+    };
+    match read_trace(file) {
+        Ok(records) => records,
+        Err(err) => {
+            println!("Failed to read {}:\n\t{}", path, err);
+            process::exit(2);
+        }
+    }
+}
+
+fn describe(record: &Option<TraceRecord>) -> String {
+    match record {
+        Some(record) => format!("{:?}", record),
+        None => String::from("<end of trace>"),
+    }
+}
+
+// exits with 0 when the traces match, 1 when they diverge and 2 when they cannot be read
+pub fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        println!("Usage: trace-diff <expected.trace> <actual.trace>");
+        process::exit(2);
+    }
+    let expected = load(&args[1]);
+    let actual = load(&args[2]);
+
+    let Some(divergence) = first_divergence(&expected, &actual) else {
+        println!("Traces match ({} steps)", expected.len());
+        return;
+    };
+    println!("Traces diverge at step {}", divergence.index);
+    if let (Some(expected), Some(actual)) = (&divergence.expected, &divergence.actual) {
+        println!(
+            "Differing fields: {}",
+            expected.differences(actual).join(", ")
+        );
+    }
+    println!("  expected: {}", describe(&divergence.expected));
+    println!("  actual:   {}", describe(&divergence.actual));
+    process::exit(1);
+}
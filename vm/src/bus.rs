@@ -0,0 +1,399 @@
+use std::{collections::VecDeque, io};
+
+use crate::{
+    interrupt::{INTERRUPT_LINES, TIMER_INTERRUPT},
+    io::{IoDevice, Output},
+    snapshot::DeviceState,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum BusError {
+    #[error("Device {name} at address {address} overlaps the device {other}")]
+    Overlap {
+        name: &'static str,
+        address: u32,
+        other: &'static str,
+    },
+    #[error("Device {name} cannot use interrupt line {line}")]
+    InvalidLine { name: &'static str, line: u8 },
+    #[error("Saved device {name} at address {address} is not mapped")]
+    Unmapped { name: String, address: u32 },
+    #[error("Saved state has {saved} devices but {mapped} are mapped")]
+    DeviceCount { saved: usize, mapped: usize },
+    #[error("Invalid state for device {name}: {source}")]
+    InvalidState {
+        name: &'static str,
+        source: io::Error,
+    },
+}
+
+// a peripheral occupying `size` consecutive data memory cells, `offset` is relative to its base
+pub trait Device {
+    fn name(&self) -> &'static str;
+    fn size(&self) -> u32 {
+        1
+    }
+    fn read(&mut self, offset: u32, io: &mut dyn IoDevice) -> io::Result<u8>;
+    fn write(&mut self, offset: u32, value: u8, io: &mut dyn IoDevice) -> io::Result<()>;
+    // called once after every executed instruction, returning true requests an interrupt
+    fn tick(&mut self) -> bool {
+        false
+    }
+    fn reset(&mut self) {}
+    fn save(&self) -> Vec<u8> {
+        Vec::new()
+    }
+    fn restore(&mut self, _state: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn invalid_state(device: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("unexpected {device} state length"),
+    )
+}
+
+struct Mapping {
+    start: u32,
+    device: Box<dyn Device>,
+    line: Option<u8>,
+}
+
+impl Mapping {
+    fn contains(&self, address: u32) -> bool {
+        address >= self.start && address < self.start + self.device.size()
+    }
+}
+
+// data memory addresses claimed by the standard device set, see `Bus::standard`
+pub const TERMINAL_ADDRESS: u32 = isa::DEVICE_WINDOW;
+pub const KEYBOARD_ADDRESS: u32 = isa::DEVICE_WINDOW + 1;
+pub const TIMER_ADDRESS: u32 = isa::DEVICE_WINDOW + 2;
+pub const RNG_ADDRESS: u32 = isa::DEVICE_WINDOW + 3;
+
+#[derive(Default)]
+pub struct Bus {
+    mappings: Vec<Mapping>,
+}
+
+impl Bus {
+    pub fn standard() -> Self {
+        let mut bus = Self::default();
+        let devices: [(u32, Box<dyn Device>); 3] = [
+            (TERMINAL_ADDRESS, Box::new(Terminal)),
+            (KEYBOARD_ADDRESS, Box::new(Keyboard::default())),
+            (RNG_ADDRESS, Box::new(Rng::default())),
+        ];
+        for (address, device) in devices {
+            bus.map(address, device)
+                .expect("standard devices do not overlap");
+        }
+        bus.map_with_interrupt(TIMER_ADDRESS, Box::new(Timer::default()), TIMER_INTERRUPT)
+            .expect("standard devices do not overlap");
+        bus
+    }
+
+    pub fn map(&mut self, start: u32, device: Box<dyn Device>) -> Result<(), BusError> {
+        self.insert(start, device, None)
+    }
+
+    pub fn map_with_interrupt(
+        &mut self,
+        start: u32,
+        device: Box<dyn Device>,
+        line: u8,
+    ) -> Result<(), BusError> {
+        if line >= INTERRUPT_LINES {
+            return Err(BusError::InvalidLine {
+                name: device.name(),
+                line,
+            });
+        }
+        self.insert(start, device, Some(line))
+    }
+
+    fn insert(
+        &mut self,
+        start: u32,
+        device: Box<dyn Device>,
+        line: Option<u8>,
+    ) -> Result<(), BusError> {
+        let end = start + device.size();
+        if let Some(other) = self
+            .mappings
+            .iter()
+            .find(|mapping| start < mapping.start + mapping.device.size() && mapping.start < end)
+        {
+            return Err(BusError::Overlap {
+                name: device.name(),
+                address: start,
+                other: other.device.name(),
+            });
+        }
+        self.mappings.push(Mapping {
+            start,
+            device,
+            line,
+        });
+        Ok(())
+    }
+
+    pub fn device_at(&self, address: u32) -> Option<&'static str> {
+        self.mappings
+            .iter()
+            .find(|mapping| mapping.contains(address))
+            .map(|mapping| mapping.device.name())
+    }
+
+    // `None` means the address is plain RAM
+    pub fn read(
+        &mut self,
+        address: u32,
+        io: &mut dyn IoDevice,
+    ) -> io::Result<Option<(u8, &'static str)>> {
+        match self.mapping(address) {
+            Some(mapping) => {
+                let value = mapping.device.read(address - mapping.start, io)?;
+                Ok(Some((value, mapping.device.name())))
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub fn write(
+        &mut self,
+        address: u32,
+        value: u8,
+        io: &mut dyn IoDevice,
+    ) -> io::Result<Option<&'static str>> {
+        match self.mapping(address) {
+            Some(mapping) => {
+                mapping.device.write(address - mapping.start, value, io)?;
+                Ok(Some(mapping.device.name()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // returns the interrupt lines raised during this tick as a bit mask
+    pub fn tick(&mut self) -> u8 {
+        let mut raised = 0;
+        for mapping in &mut self.mappings {
+            if mapping.device.tick()
+                && let Some(line) = mapping.line
+            {
+                raised |= 1 << line;
+            }
+        }
+        raised
+    }
+
+    pub fn reset(&mut self) {
+        for mapping in &mut self.mappings {
+            mapping.device.reset();
+        }
+    }
+
+    pub fn save(&self) -> Vec<DeviceState> {
+        self.mappings
+            .iter()
+            .map(|mapping| DeviceState {
+                address: mapping.start,
+                name: mapping.device.name().to_string(),
+                state: mapping.device.save(),
+            })
+            .collect()
+    }
+
+    pub fn restore(&mut self, states: &[DeviceState]) -> Result<(), BusError> {
+        if states.len() != self.mappings.len() {
+            return Err(BusError::DeviceCount {
+                saved: states.len(),
+                mapped: self.mappings.len(),
+            });
+        }
+        for state in states {
+            let mapping = self
+                .mappings
+                .iter_mut()
+                .find(|mapping| {
+                    mapping.start == state.address && mapping.device.name() == state.name
+                })
+                .ok_or_else(|| BusError::Unmapped {
+                    name: state.name.clone(),
+                    address: state.address,
+                })?;
+            mapping
+                .device
+                .restore(&state.state)
+                .map_err(|source| BusError::InvalidState {
+                    name: mapping.device.name(),
+                    source,
+                })?;
+        }
+        Ok(())
+    }
+
+    fn mapping(&mut self, address: u32) -> Option<&mut Mapping> {
+        self.mappings
+            .iter_mut()
+            .find(|mapping| mapping.contains(address))
+    }
+}
+
+// writing prints the byte as a character, reads return 0
+pub struct Terminal;
+
+impl Device for Terminal {
+    fn name(&self) -> &'static str {
+        "terminal"
+    }
+
+    fn read(&mut self, _: u32, _: &mut dyn IoDevice) -> io::Result<u8> {
+        Ok(0)
+    }
+
+    fn write(&mut self, _: u32, value: u8, io: &mut dyn IoDevice) -> io::Result<()> {
+        io.output(Output::Char(value as char))
+    }
+}
+
+// reads return the next buffered byte, or 0 once both the buffer and the host are out of input
+#[derive(Default)]
+pub struct Keyboard {
+    buffer: VecDeque<u8>,
+}
+
+impl Keyboard {
+    pub fn push(&mut self, input: &str) {
+        self.buffer.extend(input.bytes());
+    }
+}
+
+impl Device for Keyboard {
+    fn name(&self) -> &'static str {
+        "keyboard"
+    }
+
+    fn read(&mut self, _: u32, io: &mut dyn IoDevice) -> io::Result<u8> {
+        if self.buffer.is_empty() {
+            let line = io.read_line()?;
+            self.push(&line);
+        }
+        Ok(self.buffer.pop_front().unwrap_or(0))
+    }
+
+    // writing anything clears the buffer
+    fn write(&mut self, _: u32, _: u8, _: &mut dyn IoDevice) -> io::Result<()> {
+        self.buffer.clear();
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.buffer.clear();
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.buffer.iter().copied().collect()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> io::Result<()> {
+        self.buffer = state.iter().copied().collect();
+        Ok(())
+    }
+}
+
+// counts down by one per executed instruction and stops at 0, requesting an interrupt when it expires
+#[derive(Default)]
+pub struct Timer {
+    remaining: u8,
+}
+
+impl Device for Timer {
+    fn name(&self) -> &'static str {
+        "timer"
+    }
+
+    fn read(&mut self, _: u32, _: &mut dyn IoDevice) -> io::Result<u8> {
+        Ok(self.remaining)
+    }
+
+    fn write(&mut self, _: u32, value: u8, _: &mut dyn IoDevice) -> io::Result<()> {
+        self.remaining = value;
+        Ok(())
+    }
+
+    fn tick(&mut self) -> bool {
+        if self.remaining == 0 {
+            return false;
+        }
+        self.remaining -= 1;
+        self.remaining == 0
+    }
+
+    fn reset(&mut self) {
+        self.remaining = 0;
+    }
+
+    fn save(&self) -> Vec<u8> {
+        vec![self.remaining]
+    }
+
+    fn restore(&mut self, state: &[u8]) -> io::Result<()> {
+        match state {
+            [remaining] => {
+                self.remaining = *remaining;
+                Ok(())
+            }
+            _ => Err(invalid_state(self.name())),
+        }
+    }
+}
+
+const RNG_SEED: u32 = 0x2545_F491;
+
+// xorshift32, writing a byte reseeds it so runs can be reproduced
+pub struct Rng {
+    state: u32,
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self { state: RNG_SEED }
+    }
+}
+
+impl Device for Rng {
+    fn name(&self) -> &'static str {
+        "rng"
+    }
+
+    fn read(&mut self, _: u32, _: &mut dyn IoDevice) -> io::Result<u8> {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        Ok(self.state as u8)
+    }
+
+    fn write(&mut self, _: u32, value: u8, _: &mut dyn IoDevice) -> io::Result<()> {
+        // xorshift never leaves the all zero state
+        self.state = RNG_SEED ^ value as u32;
+        Ok(())
+    }
+
+    fn reset(&mut self) {
+        self.state = RNG_SEED;
+    }
+
+    fn save(&self) -> Vec<u8> {
+        self.state.to_be_bytes().to_vec()
+    }
+
+    fn restore(&mut self, state: &[u8]) -> io::Result<()> {
+        let state: [u8; 4] = state.try_into().map_err(|_| invalid_state(self.name()))?;
+        self.state = u32::from_be_bytes(state);
+        Ok(())
+    }
+}
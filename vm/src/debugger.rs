@@ -0,0 +1,557 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{self, Write, stdin, stdout},
+    num::ParseIntError,
+};
+
+use isa::OperandType;
+
+use crate::{
+    ExecutionStep, MyVM, Type, VMError, VMState,
+    symbols::{SymbolError, parse_symbols},
+};
+
+const HELP: &str = "\
+Commands:
+  break [label|bit-address]   set a breakpoint, or list them when no argument is given
+  delete <label|bit-address>  remove a breakpoint
+  step [n]                    execute n instructions (default 1)
+  continue                    run until a breakpoint is hit or the program halts
+  back [n]                    undo n instructions (default 1)
+  reverse-continue            undo instructions until a breakpoint is reached
+  regs                        show the registers
+  flags                       show the flags
+  mem <start>[..<end>]        dump data memory, end is exclusive
+  stack                       show the stack, top first
+  set <R0-R3|PC|SP> <value>   change a register, the program counter or the stack pointer
+  disasm [bit-address] [n]    disassemble n instructions (default 8) starting at the PC
+  irq <line>                  raise an interrupt line
+  save <file>                 write a snapshot of the VM state
+  load <file>                 restore a snapshot written by save or --save-state
+  quit                        leave the debugger";
+
+#[derive(Debug, thiserror::Error)]
+pub enum DebuggerError {
+    #[error("Unknown command '{0}', type 'help' for a list of commands")]
+    UnknownCommand(String),
+    #[error("Usage: {0}")]
+    Usage(&'static str),
+    #[error("Unknown label '{0}'")]
+    UnknownLabel(String),
+    #[error("Invalid number '{0}'")]
+    InvalidNumber(String),
+    #[error("{0}")]
+    Symbols(#[from] SymbolError),
+    #[error("SP must be between 0 and {0}, the end of data memory")]
+    StackPointer(u32),
+    #[error("The program has halted")]
+    Halted,
+    #[error("No history left to step back through")]
+    NoHistory,
+    #[error("{0}")]
+    VM(#[from] VMError),
+    #[error("{0}")]
+    IO(#[from] io::Error),
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<u32>,
+    symbols: HashMap<String, u32>,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            symbols: HashMap::new(),
+        }
+    }
+
+    pub fn load_symbols(&mut self, contents: &str) -> Result<(), DebuggerError> {
+        self.symbols.extend(parse_symbols(contents)?);
+        Ok(())
+    }
+
+    pub fn add_symbols(&mut self, symbols: &HashMap<String, u32>) {
+        self.symbols.extend(symbols.clone());
+    }
+
+    pub fn run(&mut self, vm: &mut MyVM) -> Result<(), DebuggerError> {
+        println!("Debugger ready, type 'help' for a list of commands.");
+        loop {
+            print!("(dbg) ");
+            stdout().flush()?;
+            let mut line = String::new();
+            if stdin().read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            match self.command(vm, line.trim()) {
+                Ok(Some(output)) => {
+                    if !output.is_empty() {
+                        println!("{}", output);
+                    }
+                }
+                Ok(None) => return Ok(()),
+                Err(err) => println!("{}", err),
+            }
+        }
+    }
+
+    // returns the text to print, or None once the user asks to quit
+    pub fn command(&mut self, vm: &mut MyVM, line: &str) -> Result<Option<String>, DebuggerError> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(Some(String::new()));
+        };
+        let arguments: Vec<&str> = words.collect();
+        let output = match command {
+            "break" | "b" => match arguments.as_slice() {
+                [] => self.list_breakpoints(),
+                [location] => {
+                    let address = self.resolve(location)?;
+                    self.breakpoints.insert(address);
+                    format!("Breakpoint set at {}", self.describe(address))
+                }
+                _ => return Err(DebuggerError::Usage("break [label|bit-address]")),
+            },
+            "delete" | "d" => match arguments.as_slice() {
+                [location] => {
+                    let address = self.resolve(location)?;
+                    if self.breakpoints.remove(&address) {
+                        format!("Breakpoint at {} deleted", self.describe(address))
+                    } else {
+                        format!("No breakpoint at {}", self.describe(address))
+                    }
+                }
+                _ => return Err(DebuggerError::Usage("delete <label|bit-address>")),
+            },
+            "step" | "s" => {
+                let count = match arguments.as_slice() {
+                    [] => 1,
+                    [count] => parse_number(count)?,
+                    _ => return Err(DebuggerError::Usage("step [n]")),
+                };
+                self.step(vm, count)?
+            }
+            "continue" | "c" => self.continue_(vm)?,
+            "back" | "bs" => {
+                let count = match arguments.as_slice() {
+                    [] => 1,
+                    [count] => parse_number(count)?,
+                    _ => return Err(DebuggerError::Usage("back [n]")),
+                };
+                self.step_back(vm, count)?
+            }
+            "reverse-continue" | "rc" => {
+                let undone = vm.reverse_continue(|address| self.breakpoints.contains(&address))?;
+                if self.breakpoints.contains(&vm.program_counter) && undone > 0 {
+                    format!(
+                        "Breakpoint hit at {} after undoing {} steps",
+                        self.describe(vm.program_counter),
+                        undone
+                    )
+                } else {
+                    format!(
+                        "Reached the start of the history after undoing {} steps, PC = {}",
+                        undone,
+                        self.describe(vm.program_counter)
+                    )
+                }
+            }
+            "regs" => self.registers(vm),
+            "flags" => self.flags(vm),
+            "mem" | "m" => match arguments.as_slice() {
+                [range] => self.memory(vm, range)?,
+                _ => return Err(DebuggerError::Usage("mem <start>[..<end>]")),
+            },
+            "stack" => self.stack(vm),
+            "set" => match arguments.as_slice() {
+                [target, value] => self.set(vm, target, value)?,
+                _ => return Err(DebuggerError::Usage("set <R0-R3|PC|SP> <value>")),
+            },
+            "disasm" => {
+                let (start, count) = match arguments.as_slice() {
+                    [] => (vm.program_counter, 8),
+                    [start] => (self.resolve(start)?, 8),
+                    [start, count] => (self.resolve(start)?, parse_number(count)?),
+                    _ => return Err(DebuggerError::Usage("disasm [bit-address] [n]")),
+                };
+                self.disassemble(vm, start, count)
+            }
+            "irq" => match arguments.as_slice() {
+                [line] => {
+                    let line = u8::try_from(parse_number(line)?)
+                        .map_err(|_| DebuggerError::InvalidNumber(line.to_string()))?;
+                    vm.raise_interrupt(line)?;
+                    format!("Interrupt line {} raised", line)
+                }
+                _ => return Err(DebuggerError::Usage("irq <line>")),
+            },
+            "save" => match arguments.as_slice() {
+                [path] => {
+                    vm.get_state_struct().save(path)?;
+                    format!("State saved to {}", path)
+                }
+                _ => return Err(DebuggerError::Usage("save <file>")),
+            },
+            "load" => match arguments.as_slice() {
+                [path] => {
+                    vm.restore(VMState::load(path)?)?;
+                    format!("State loaded from {}, PC = {}", path, vm.program_counter)
+                }
+                _ => return Err(DebuggerError::Usage("load <file>")),
+            },
+            "help" | "h" => HELP.to_string(),
+            "quit" | "q" => return Ok(None),
+            _ => return Err(DebuggerError::UnknownCommand(command.to_string())),
+        };
+        Ok(Some(output))
+    }
+
+    fn resolve(&self, location: &str) -> Result<u32, DebuggerError> {
+        match self.symbols.get(location) {
+            Some(address) => Ok(*address),
+            None if location.starts_with(|c: char| c.is_ascii_digit()) => parse_number(location),
+            None => Err(DebuggerError::UnknownLabel(location.to_string())),
+        }
+    }
+
+    fn label_at(&self, address: u32) -> Option<&str> {
+        self.symbols
+            .iter()
+            .filter(|(_, location)| **location == address)
+            .map(|(label, _)| label.as_str())
+            .min()
+    }
+
+    fn describe(&self, address: u32) -> String {
+        match self.label_at(address) {
+            Some(label) => format!("{} ({})", address, label),
+            None => address.to_string(),
+        }
+    }
+
+    fn list_breakpoints(&self) -> String {
+        if self.breakpoints.is_empty() {
+            return "No breakpoints".to_string();
+        }
+        self.breakpoints
+            .iter()
+            .map(|address| format!("  {}", self.describe(*address)))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn format_step(&self, step: &ExecutionStep) -> String {
+        let mut line = String::new();
+        if let Some(interrupt) = step.interrupt {
+            line.push_str(&format!("Interrupt {} taken\n", interrupt));
+        }
+        line.push_str(&format!("{:>5}: {}", step.address, step.instruction_str));
+        if !step.changed_regs.is_empty() {
+            line.push_str(&format!("  [{}]", step.changed_regs.join(", ")));
+        }
+        for access in &step.memory_accesses {
+            let direction = if access.type_ == Type::Read {
+                "read"
+            } else {
+                "write"
+            };
+            let target = match &access.device {
+                Some(device) => format!("{}@{}", device, access.address),
+                None => format!("mem[{}]", access.address),
+            };
+            line.push_str(&format!("  [{} {} = {}]", direction, target, access.value));
+        }
+        line
+    }
+
+    fn step(&mut self, vm: &mut MyVM, count: u32) -> Result<String, DebuggerError> {
+        if is_halted(vm) {
+            return Err(DebuggerError::Halted);
+        }
+        let mut lines = Vec::new();
+        for _ in 0..count {
+            let step = vm.step()?;
+            lines.push(self.format_step(&step));
+            if step.is_halted || is_halted(vm) {
+                lines.push("Program halted".to_string());
+                break;
+            }
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn continue_(&mut self, vm: &mut MyVM) -> Result<String, DebuggerError> {
+        if is_halted(vm) {
+            return Err(DebuggerError::Halted);
+        }
+        loop {
+            let step = vm.step()?;
+            if step.is_halted || is_halted(vm) {
+                return Ok(format!("{}\nProgram halted", self.format_step(&step)));
+            }
+            if self.breakpoints.contains(&vm.program_counter) {
+                return Ok(format!(
+                    "Breakpoint hit at {}",
+                    self.describe(vm.program_counter)
+                ));
+            }
+        }
+    }
+
+    fn step_back(&mut self, vm: &mut MyVM, count: u32) -> Result<String, DebuggerError> {
+        let mut undone = 0;
+        while undone < count && vm.step_back()? {
+            undone += 1;
+        }
+        if undone == 0 {
+            return Err(DebuggerError::NoHistory);
+        }
+        Ok(format!(
+            "Stepped back {} instructions, PC = {}",
+            undone,
+            self.describe(vm.program_counter)
+        ))
+    }
+
+    fn registers(&self, vm: &MyVM) -> String {
+        let mut lines: Vec<String> = vm
+            .register
+            .regs
+            .iter()
+            .enumerate()
+            .map(|(i, value)| format!("  R{}: {:>4}  (0x{:02X})", i, *value as i8, value))
+            .collect();
+        lines.push(format!("  PC: {}", self.describe(vm.program_counter)));
+        lines.push(format!("  SP: {}", vm.stack_pointer));
+        lines.join("\n")
+    }
+
+    fn flags(&self, vm: &MyVM) -> String {
+        let flags = vm.flags;
+        format!(
+            "  zero: {}  sign: {}  overflow: {}  carry: {}  interrupts: {}  pending: {:04b}",
+            flags.zero as u8,
+            flags.sign as u8,
+            flags.overflow as u8,
+            flags.carry as u8,
+            flags.interrupt_enable as u8,
+            vm.pending_interrupts()
+        )
+    }
+
+    fn memory(&self, vm: &MyVM, range: &str) -> Result<String, DebuggerError> {
+        let (start, end) = match range.split_once("..") {
+            Some((start, end)) => (parse_number(start)?, parse_number(end)?),
+            None => {
+                let start = parse_number(range)?;
+                (start, start + 1)
+            }
+        };
+        let end = end.min(vm.data_memory.size());
+        let mut lines = Vec::new();
+        let mut address = start;
+        while address < end {
+            let row_end = (address + 8).min(end);
+            let bytes: Result<Vec<String>, _> = (address..row_end)
+                .map(|cell| {
+                    vm.data_memory
+                        .get(cell)
+                        .map(|value| format!("{:02X}", value))
+                })
+                .collect();
+            lines.push(format!(
+                "  {:>3}: {}",
+                address,
+                bytes.map_err(VMError::from)?.join(" ")
+            ));
+            address = row_end;
+        }
+        Ok(lines.join("\n"))
+    }
+
+    fn stack(&self, vm: &MyVM) -> String {
+        if vm.stack_pointer >= vm.data_memory.size() {
+            return "Stack is empty".to_string();
+        }
+        (vm.stack_pointer..vm.data_memory.size())
+            .filter_map(|address| {
+                vm.data_memory
+                    .get(address)
+                    .ok()
+                    .map(|value| format!("  {:>3}: {:>4}  (0x{:02X})", address, value as i8, value))
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn set(&mut self, vm: &mut MyVM, target: &str, value: &str) -> Result<String, DebuggerError> {
+        let target = target.to_uppercase();
+        match target.as_str() {
+            "PC" => vm.program_counter = self.resolve(value)?,
+            "SP" => {
+                // SP points at the last pushed byte, so the end of data memory is an empty stack
+                let end = vm.data_memory.size();
+                let value = parse_number(value)?;
+                if value > end {
+                    return Err(DebuggerError::StackPointer(end));
+                }
+                vm.stack_pointer = value;
+            }
+            _ => {
+                let register = target
+                    .strip_prefix('R')
+                    .and_then(|index| index.parse::<u32>().ok())
+                    .ok_or(DebuggerError::Usage("set <R0-R3|PC|SP> <value>"))?;
+                let value = parse_signed(value)?;
+                vm.register.set(register, value).map_err(VMError::from)?;
+            }
+        }
+        Ok(format!("{} = {}", target, value))
+    }
+
+    fn disassemble(&self, vm: &mut MyVM, start: u32, count: u32) -> String {
+        let mut lines = Vec::new();
+        let mut address = start;
+        for _ in 0..count {
+            if address >= vm.eof {
+                break;
+            }
+            if let Some(label) = self.label_at(address) {
+                lines.push(format!("{}:", label));
+            }
+            let marker = match (
+                address == vm.program_counter,
+                self.breakpoints.contains(&address),
+            ) {
+                (true, true) => "*>",
+                (true, false) => "=>",
+                (false, true) => "* ",
+                (false, false) => "  ",
+            };
+            match vm.decode_at(address) {
+                Ok(instruction) => {
+                    let text =
+                        instruction.render(
+                            &vm.opt_spec,
+                            |operand_type, target| match operand_type {
+                                OperandType::Label => self.label_at(target).map(str::to_string),
+                                _ => None,
+                            },
+                        );
+                    lines.push(format!("{} {:>5}: {}", marker, address, text));
+                    address += instruction.size;
+                }
+                Err(err) => {
+                    lines.push(format!("{} {:>5}: <{}>", marker, address, err));
+                    break;
+                }
+            }
+        }
+        lines.join("\n")
+    }
+}
+
+fn is_halted(vm: &MyVM) -> bool {
+    vm.program_counter >= vm.eof
+}
+
+fn parse_number(value: &str) -> Result<u32, DebuggerError> {
+    let parsed: Result<u32, ParseIntError> = match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| DebuggerError::InvalidNumber(value.to_string()))
+}
+
+fn parse_signed(value: &str) -> Result<u8, DebuggerError> {
+    match value.parse::<i8>() {
+        Ok(value) => Ok(value as u8),
+        Err(_) => parse_number(value).and_then(|value| {
+            u8::try_from(value).map_err(|_| DebuggerError::InvalidNumber(value.to_string()))
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use args::Args;
+
+    // MOVEI R1, 5; ADDI R1, R1, 1; HALT
+    fn vm() -> MyVM {
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        vm.load_binary(vec![
+            0b00001001, 0b00000101, 0b00100101, 0b01000000, 0b01000000, 0, 0, 0, 0, 40,
+        ])
+        .unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let mut vm = vm();
+        let mut debugger = Debugger::new();
+        debugger.load_symbols("INC 16\n").unwrap();
+        debugger.command(&mut vm, "break INC").unwrap();
+        let output = debugger.command(&mut vm, "continue").unwrap().unwrap();
+        assert_eq!(output, "Breakpoint hit at 16 (INC)");
+        assert_eq!(vm.register.get(1).unwrap(), 5);
+
+        let output = debugger.command(&mut vm, "step 5").unwrap().unwrap();
+        assert!(output.ends_with("Program halted"));
+        assert_eq!(vm.register.get(1).unwrap(), 6);
+        assert!(matches!(
+            debugger.command(&mut vm, "step"),
+            Err(DebuggerError::Halted)
+        ));
+    }
+
+    #[test]
+    fn test_set_and_inspect() {
+        let mut vm = vm();
+        let mut debugger = Debugger::new();
+        debugger.command(&mut vm, "set R2 -3").unwrap();
+        assert_eq!(vm.register.get(2).unwrap() as i8, -3);
+        let regs = debugger.command(&mut vm, "regs").unwrap().unwrap();
+        assert!(regs.contains("R2:   -3  (0xFD)"));
+
+        vm.data_memory.set(3, 7).unwrap();
+        let memory = debugger.command(&mut vm, "mem 0..4").unwrap().unwrap();
+        assert_eq!(memory, "    0: 00 00 00 07");
+
+        let disasm = debugger.command(&mut vm, "disasm").unwrap().unwrap();
+        assert_eq!(
+            disasm,
+            "=>     0: MOVEI R1, 5\n      16: ADDI R1, R1, 1\n      34: HALT"
+        );
+        assert!(matches!(
+            debugger.command(&mut vm, "break NOWHERE"),
+            Err(DebuggerError::UnknownLabel(_))
+        ));
+    }
+
+    #[test]
+    fn test_stack_bounds() {
+        let mut vm = vm();
+        let mut debugger = Debugger::new();
+        assert!(matches!(
+            debugger.command(&mut vm, "set SP 257"),
+            Err(DebuggerError::StackPointer(256))
+        ));
+        assert_eq!(vm.stack_pointer, 256);
+
+        // a full stack refuses the next push instead of wrapping the stack pointer
+        debugger.command(&mut vm, "set SP 0").unwrap();
+        assert!(matches!(vm.push(&[1]), Err(VMError::StackOverflow)));
+        assert!(matches!(vm.call(&[0]), Err(VMError::StackOverflow)));
+        assert_eq!(vm.stack_pointer, 0);
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+
+use args::Args;
+use isa::{FlagUsage, Opcode, OperandType, OptSpec};
+
+use crate::{Flags, MyVM, VMError, handler, io::CallbackIo, trace::FLAG_NAMES};
+
+const TRIALS: u32 = 16;
+
+// machine state before one handler call, the flags are set separately
+struct Trial {
+    operands: Vec<u32>,
+    registers: Vec<u8>,
+    memory: Vec<u8>,
+    stack_pointer: u32,
+}
+
+struct Outcome {
+    registers: Vec<u8>,
+    flags: u8,
+    program_counter: u32,
+    stack_pointer: u32,
+    memory: Vec<u8>,
+}
+
+impl Outcome {
+    fn same_except(&self, other: &Outcome, flag: u8) -> bool {
+        self.flags & !flag == other.flags & !flag
+            && self.registers == other.registers
+            && self.program_counter == other.program_counter
+            && self.stack_pointer == other.stack_pointer
+            && self.memory == other.memory
+    }
+}
+
+// xorshift, the same trials on every run keep the generated documentation stable
+fn next(seed: &mut u32) -> u32 {
+    *seed ^= *seed << 13;
+    *seed ^= *seed >> 17;
+    *seed ^= *seed << 5;
+    *seed
+}
+
+fn run(vm: &mut MyVM, opcode: Opcode, trial: &Trial, flags: u8) -> Option<Outcome> {
+    vm.register.regs = trial.registers.clone();
+    vm.data_memory.mem = trial.memory.clone();
+    vm.flags = Flags::from_byte(flags);
+    vm.program_counter = 0;
+    vm.stack_pointer = trial.stack_pointer;
+    handler(opcode)(vm, &trial.operands).ok()?;
+    Some(Outcome {
+        registers: vm.register.regs.clone(),
+        flags: vm.flags.to_byte(),
+        program_counter: vm.program_counter,
+        stack_pointer: vm.stack_pointer,
+        memory: vm.data_memory.mem.clone(),
+    })
+}
+
+// which flags every instruction reads and writes, found by running its handler on a spread
+// of operands, registers, memory and flags, to check the table the ISA reference is built
+// from. A flag is written if it ever comes out different from how it went in, and read if
+// flipping it beforehand changes anything but the flag itself
+fn flag_usage(opt_spec: &OptSpec) -> Result<HashMap<Opcode, FlagUsage>, VMError> {
+    let mut vm = MyVM::new(&Args::default())?;
+    vm.set_isa(opt_spec.clone());
+    vm.set_io_device(CallbackIo::new(|_| Ok(String::from("3")), |_| Ok(())));
+
+    let mut usage = HashMap::new();
+    for operation in opt_spec.get_operations() {
+        let mut seed = 0x9E3779B9 ^ operation.opcode;
+        let (mut read, mut written) = ([false; 5], [false; 5]);
+        for _ in 0..TRIALS {
+            let trial = Trial {
+                operands: operation
+                    .operands
+                    .iter()
+                    .map(|operand| match operand.operand_type {
+                        OperandType::Register
+                        | OperandType::Indirect
+                        | OperandType::PostIncrement => next(&mut seed) % opt_spec.register_count,
+                        _ => next(&mut seed) % (1 << operand.bit_count.min(8)),
+                    })
+                    .collect(),
+                registers: (0..opt_spec.register_count)
+                    .map(|_| next(&mut seed) as u8)
+                    .collect(),
+                memory: (0..vm.data_memory.mem.len())
+                    .map(|_| next(&mut seed) as u8)
+                    .collect(),
+                stack_pointer: 128 + next(&mut seed) % 64,
+            };
+            let outcomes: Vec<Option<Outcome>> = (0..32)
+                .map(|flags| run(&mut vm, operation.kind, &trial, flags))
+                .collect();
+            for (flags, outcome) in outcomes.iter().enumerate() {
+                let Some(outcome) = outcome else {
+                    continue;
+                };
+                for bit in 0..5 {
+                    let mask = 1 << bit;
+                    if (outcome.flags ^ flags as u8) & mask != 0 {
+                        written[bit] = true;
+                    }
+                    if flags as u8 & mask == 0
+                        && let Some(flipped) = &outcomes[flags | mask as usize]
+                        && !outcome.same_except(flipped, mask)
+                    {
+                        read[bit] = true;
+                    }
+                }
+            }
+        }
+        let names = |used: [bool; 5]| {
+            (0..5)
+                .filter(|bit| used[*bit])
+                .map(|bit| FLAG_NAMES[bit].to_string())
+                .collect()
+        };
+        usage.insert(
+            operation.kind,
+            FlagUsage {
+                read: names(read),
+                written: names(written),
+            },
+        );
+    }
+    Ok(usage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flag_usage() {
+        let spec = OptSpec::clone();
+        let usage = flag_usage(&spec).unwrap();
+        for operation in spec.get_operations() {
+            assert_eq!(
+                usage[&operation.kind],
+                operation.kind.flag_usage(),
+                "{}",
+                operation.operation_name
+            );
+        }
+    }
+}
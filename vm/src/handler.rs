@@ -1,10 +1,11 @@
-use crate::{MemoryAccess, MyVM, VMError};
-use std::io::{Write, stdin, stdout};
+use isa::OperandType;
+
+use crate::{Flags, MemoryAccess, MyVM, VMError, io::Output};
 
 pub struct Delta {
     pub registers: Vec<String>,
     pub flags: Vec<String>,
-    pub memory_access: Option<MemoryAccess>,
+    pub memory_accesses: Vec<MemoryAccess>,
 }
 
 impl MyVM {
@@ -13,98 +14,113 @@ impl MyVM {
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
     pub fn input(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
         let register = operands[0];
-        let mut input = String::new();
-        print!("Enter value for register {register}: ");
-        stdout().flush()?;
-        stdin().read_line(&mut input)?;
+        let input = self.io.input(register)?;
         let input = input.trim().parse::<i8>()? as u8;
         self.register.set(register, input)?;
         Ok(Delta {
             registers: vec![format!("R{register}")],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
-    pub fn output(&self, operands: &[u32]) -> Result<Delta, VMError> {
+    pub fn output(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
         let register = operands[0];
         let value = self.register.get(register)? as i8;
-        println!("Output from register {register}: {value}");
-        stdout().flush()?;
+        self.io.output(Output::Register { register, value })?;
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
-    pub fn output_16(&self, _: &[u32]) -> Result<Delta, VMError> {
+    pub fn output_16(&mut self, _: &[u32]) -> Result<Delta, VMError> {
         let high_byte = self.register.get(1)? as u16;
         let low_byte = self.register.get(0)? as u16;
         let value = ((high_byte << 8) | low_byte) as i16;
-        println!("Combined output from registers 0 and 1: {value}");
-        stdout().flush()?;
+        self.io.output(Output::Combined(value))?;
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
-    pub fn output_char(&self, operands: &[u32]) -> Result<Delta, VMError> {
+    pub fn output_char(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
         let register = operands[0];
-        let value = self.register.get(register)? as i8;
-        print!("{}", value as u8 as char);
-        stdout().flush()?;
+        let value = self.register.get(register)?;
+        self.io.output(Output::Char(value as char))?;
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
-    pub fn mover(&mut self, operands: &[u32], immediate: bool) -> Result<Delta, VMError> {
+    // the data address of a memory operand, and the register a post-increment stepped
+    fn effective_address(
+        &mut self,
+        operand: u32,
+        addressing: OperandType,
+    ) -> Result<(u32, Option<u32>), VMError> {
+        match addressing {
+            OperandType::Indirect => Ok((self.register.get(operand)? as u32, None)),
+            OperandType::Indexed => {
+                let register_bits = self.opt_spec.register_bits();
+                let register = operand & ((1 << register_bits) - 1);
+                let offset = operand >> register_bits;
+                Ok((self.register.get(register)? as u32 + offset, None))
+            }
+            OperandType::PostIncrement => {
+                let address = self.register.get(operand)?;
+                self.register.set(operand, address.wrapping_add(1))?;
+                Ok((address as u32, Some(operand)))
+            }
+            _ => Ok((operand, None)),
+        }
+    }
+
+    // `source` is Constant for MOVEI, otherwise the addressing mode of the memory operand
+    pub fn mover(&mut self, operands: &[u32], source: OperandType) -> Result<Delta, VMError> {
         let register = operands[0];
-        let value = if immediate {
-            operands[1] as u8
+        let mut registers = vec![];
+        let (value, memory_accesses) = if source == OperandType::Constant {
+            (operands[1] as u8, vec![])
         } else {
-            self.data_memory.get(operands[1])?
+            let (address, stepped) = self.effective_address(operands[1], source)?;
+            registers.extend(stepped.map(|stepped| format!("R{stepped}")));
+            let access = self.load_data(address)?;
+            (access.value, vec![access])
         };
         self.register.set(register, value)?;
+        registers.push(format!("R{register}"));
+        registers.dedup();
         Ok(Delta {
-            registers: vec![format!("R{register}")],
+            registers,
             flags: vec![],
-            memory_access: if immediate {
-                None
-            } else {
-                Some(MemoryAccess {
-                    address: operands[1],
-                    value: value,
-                    type_: crate::Type::Read,
-                })
-            },
+            memory_accesses,
         })
     }
 
-    pub fn movem(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
+    pub fn movem(&mut self, operands: &[u32], destination: OperandType) -> Result<Delta, VMError> {
         let register = operands[0];
-        let memory = operands[1];
         let value = self.register.get(register)?;
-        self.data_memory.set(memory, value)?;
+        let (address, stepped) = self.effective_address(operands[1], destination)?;
+        let access = self.store_data(address, value)?;
         Ok(Delta {
-            registers: vec![],
+            registers: stepped
+                .map(|stepped| format!("R{stepped}"))
+                .into_iter()
+                .collect(),
             flags: vec![],
-            memory_access: Some(MemoryAccess {
-                address: memory,
-                value: value,
-                type_: crate::Type::Write,
-            }),
+            memory_accesses: vec![access],
         })
     }
 
@@ -126,7 +142,7 @@ impl MyVM {
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
@@ -148,7 +164,7 @@ impl MyVM {
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
@@ -170,7 +186,7 @@ impl MyVM {
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
@@ -186,13 +202,13 @@ impl MyVM {
         let diff_8 = diff_16 as i8;
         self.flags.zero = diff_8 == 0;
         self.flags.sign = diff_8 < 0;
-        self.flags.carry = num1 < (num2 + self.flags.carry as u8);
+        self.flags.carry = (num1 as u16) < num2 as u16 + self.flags.carry as u16;
         self.flags.overflow = ((num1 ^ num2) & (num1 ^ diff_8 as u8)) & (1 << 7) != 0;
         self.register.set(dest, diff_8 as u8)?;
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
@@ -219,19 +235,15 @@ impl MyVM {
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
-    pub fn mult_16(&mut self, operands: &[u32], immediate: bool) -> Result<Delta, VMError> {
-        let num1 = if immediate {
-            operands[0] as i8 as i16
-        } else {
-            self.register.get(operands[0])? as i8 as i16
-        };
+    pub fn mult_16(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
+        let num1 = self.register.get(operands[0])? as i8 as i16;
         let num2 =
             (((self.register.get(1)? as i8 as u16) << 8) | self.register.get(0)? as u16) as i16;
-        let product = num1 * num2;
+        let product = num1.wrapping_mul(num2);
         let highbyte = (product >> 8) as u8;
         let lowbyte = product as u8;
 
@@ -245,7 +257,7 @@ impl MyVM {
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
@@ -264,7 +276,7 @@ impl MyVM {
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
@@ -281,7 +293,7 @@ impl MyVM {
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
@@ -298,7 +310,7 @@ impl MyVM {
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
@@ -315,13 +327,13 @@ impl MyVM {
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
     pub fn not(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
         let dest = operands[0];
-        let num1 = self.register.get(operands[1])?;
+        let num1 = self.register.get(dest)?;
         let product = !num1;
         self.flags.zero = product == 0;
         self.flags.sign = (product & (1 << 7)) != 0;
@@ -331,7 +343,7 @@ impl MyVM {
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
@@ -347,7 +359,7 @@ impl MyVM {
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
@@ -363,19 +375,18 @@ impl MyVM {
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
     pub fn push(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
         let reg = operands[0];
         let value = self.register.get(reg)?;
-        self.stack_pointer -= 1;
-        self.data_memory.set(self.stack_pointer, value)?;
+        let access = self.push_byte(value)?;
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![access],
         })
     }
 
@@ -387,46 +398,26 @@ impl MyVM {
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
     pub fn call(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
-        self.stack_pointer -= 1;
-        self.data_memory
-            .set(self.stack_pointer, self.program_counter as u8)?;
-        // self.stack_pointer -= 1;
-        // self.data_memory
-        //     .set(self.stack_pointer, (self.program_counter >> 8) as u8)?;
-        // self.stack_pointer -= 1;
-        // self.data_memory
-        //     .set(self.stack_pointer, (self.program_counter >> 16) as u8)?;
-        // self.stack_pointer -= 1;
-        // self.data_memory
-        //     .set(self.stack_pointer, (self.program_counter >> 24) as u8)?;
+        let accesses = self.push_address(self.program_counter)?;
         self.program_counter = operands[0];
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: accesses,
         })
     }
 
     pub fn ret(&mut self, _: &[u32]) -> Result<Delta, VMError> {
-        let mut location: u32 = 0;
-        location |= self.data_memory.get(self.stack_pointer)? as u32;
-        self.stack_pointer += 1;
-        // location |= (self.data_memory.get(self.stack_pointer)? as u32) << 8;
-        // self.stack_pointer += 1;
-        // location |= (self.data_memory.get(self.stack_pointer)? as u32) << 16;
-        // self.stack_pointer += 1;
-        // location |= (self.data_memory.get(self.stack_pointer)? as u32) << 24;
-        // self.stack_pointer += 1;
-        self.program_counter = location;
+        self.program_counter = self.pop_address()?;
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
@@ -436,51 +427,84 @@ impl MyVM {
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
-    pub fn jz(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
-        let address = operands[0];
-        if self.flags.zero {
-            self.program_counter = address;
+    fn jump_if(&mut self, operands: &[u32], condition: bool) -> Result<Delta, VMError> {
+        if condition {
+            self.program_counter = operands[0];
         };
         Ok(Delta {
             registers: vec![],
             flags: vec![],
-            memory_access: None,
+            memory_accesses: vec![],
         })
     }
 
+    pub fn jz(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
+        self.jump_if(operands, self.flags.zero)
+    }
+
     pub fn jnz(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
-        let address = operands[0];
-        if !self.flags.zero {
-            self.program_counter = address;
-        };
+        self.jump_if(operands, !self.flags.zero)
+    }
+
+    pub fn je(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
+        self.jump_if(operands, self.flags.zero)
+    }
+
+    pub fn jne(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
+        self.jump_if(operands, !self.flags.zero)
+    }
+
+    // signed comparisons, as set up by CMP/CMPI: less than iff sign != overflow
+    pub fn jg(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
+        let greater = !self.flags.zero && self.flags.sign == self.flags.overflow;
+        self.jump_if(operands, greater)
+    }
+
+    pub fn jge(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
+        self.jump_if(operands, self.flags.sign == self.flags.overflow)
+    }
+
+    pub fn jl(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
+        self.jump_if(operands, self.flags.sign != self.flags.overflow)
+    }
+
+    pub fn jle(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
+        let less_or_equal = self.flags.zero || self.flags.sign != self.flags.overflow;
+        self.jump_if(operands, less_or_equal)
+    }
+
+    pub fn ei(&mut self, _: &[u32]) -> Result<Delta, VMError> {
+        self.flags.interrupt_enable = true;
         Ok(Delta {
             registers: vec![],
-            flags: vec![],
-            memory_access: None,
+            flags: vec![String::from("IE")],
+            memory_accesses: vec![],
         })
     }
 
-    pub fn jge(&mut self, operands: &[u32]) -> Result<Delta, VMError> {
-        let address = operands[0];
-        if !self.flags.zero && !self.flags.sign && self.flags.carry {
-            self.program_counter = address;
-        };
+    pub fn di(&mut self, _: &[u32]) -> Result<Delta, VMError> {
+        self.flags.interrupt_enable = false;
         Ok(Delta {
             registers: vec![],
-            flags: vec![],
-            memory_access: None,
+            flags: vec![String::from("IE")],
+            memory_accesses: vec![],
         })
     }
 
-    pub fn jl(&self, _operands: &[u32]) -> Result<Delta, VMError> {
+    // unwinds the frame pushed by `service_interrupt`, which also restores the enable flag
+    pub fn iret(&mut self, _: &[u32]) -> Result<Delta, VMError> {
+        let flags = Flags::from_byte(self.pop_byte()?);
+        let changed = flags.changed(&self.flags);
+        self.flags = flags;
+        self.program_counter = self.pop_address()?;
         Ok(Delta {
             registers: vec![],
-            flags: vec![],
-            memory_access: None,
+            flags: changed,
+            memory_accesses: vec![],
         })
     }
 }
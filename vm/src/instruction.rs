@@ -1,4 +1,7 @@
-use crate::memory::{Memory, MemoryError};
+use std::fmt::Display;
+
+use crate::memory::MemoryError;
+use isa::{MAX_OPERANDS, Opcode, OperandType, OptSpec};
 
 #[derive(Debug, thiserror::Error)]
 pub enum InstructionError {
@@ -6,19 +9,24 @@ pub enum InstructionError {
     MemoryError(#[from] MemoryError),
     #[error("Invalid opcode: {0}")]
     InvalidOpcode(u32),
+    #[error("Operation {0} has more than {MAX_OPERANDS} operands")]
+    TooManyOperands(Opcode),
 }
 
-#[derive(Debug)]
-pub struct Instruction {
-    opcode: u32,
-    operation_name: String,
-    operands: Vec<u32>,
+#[derive(Debug, Clone, Copy)]
+pub struct DecodedInstruction {
+    pub opcode: Opcode,
+    pub size: u32,
+    operands: [u32; MAX_OPERANDS],
+    operand_count: usize,
 }
 
-fn get_bits(memory: &Memory<u8>, mut start: u32, bits_count: u32) -> Result<u32, InstructionError> {
+fn get_bits(memory: &[u8], mut start: u32, bits_count: u32) -> Result<u32, InstructionError> {
     let mut value: u32 = 0;
     for _ in 0..bits_count {
-        let byte = memory.get(start / 8)?;
+        let byte = *memory
+            .get((start / 8) as usize)
+            .ok_or(MemoryError::OutOfBounds)?;
         let bit = (byte >> (7 - start % 8)) & 1;
         value = (value << 1) | bit as u32;
         start += 1;
@@ -26,47 +34,99 @@ fn get_bits(memory: &Memory<u8>, mut start: u32, bits_count: u32) -> Result<u32,
     Ok(value)
 }
 
-impl Instruction {
-    pub fn new(
-        memory: &Memory<u8>,
-        pc: &mut u32,
-        optspec: &isa::OptSpec,
+impl DecodedInstruction {
+    // `memory` is the packed program bitstream, `address` a bit address into it
+    pub fn decode(
+        memory: &[u8],
+        address: u32,
+        optspec: &OptSpec,
     ) -> Result<Self, InstructionError> {
-        let opcode = get_bits(memory, *pc, optspec.opcode_bit_count as u32)?;
-        *pc += optspec.opcode_bit_count as u32;
+        let mut pc = address;
+        let opcode = get_bits(memory, pc, optspec.opcode_bit_count as u32)?;
+        pc += optspec.opcode_bit_count as u32;
 
         let operation = match optspec.get_by_opcode(&opcode) {
             Some(operation) => operation,
             None => return Err(InstructionError::InvalidOpcode(opcode)),
         };
+        if operation.operands.len() > MAX_OPERANDS {
+            return Err(InstructionError::TooManyOperands(operation.kind));
+        }
 
-        let operands = operation.operands.iter().fold(
-            Ok(Vec::new()),
-            |acc: Result<Vec<u32>, InstructionError>, operand_spec| {
-                let mut acc = acc?;
-                let operand = get_bits(memory, *pc, operand_spec.bit_count as u32)?;
-                *pc += operand_spec.bit_count as u32;
-                acc.push(operand);
-                Ok(acc)
-            },
-        )?;
+        let mut operands = [0; MAX_OPERANDS];
+        for (i, operand_spec) in operation.operands.iter().enumerate() {
+            operands[i] = get_bits(memory, pc, operand_spec.bit_count as u32)?;
+            pc += operand_spec.bit_count as u32;
+        }
 
         Ok(Self {
-            opcode,
+            opcode: operation.kind,
+            size: pc - address,
             operands,
-            operation_name: operation.operation_name.clone(),
+            operand_count: operation.operands.len(),
         })
     }
 
-    pub fn get_opcode(&self) -> u32 {
-        return self.opcode;
+    pub fn operands(&self) -> &[u32] {
+        &self.operands[..self.operand_count]
+    }
+
+    // the operand types of the form this instruction was decoded with
+    pub fn operand_types(&self, optspec: &OptSpec) -> Vec<OperandType> {
+        optspec
+            .get_operations()
+            .iter()
+            .find(|operation| operation.kind == self.opcode)
+            .map(|operation| {
+                operation
+                    .operands
+                    .iter()
+                    .map(|operand| operand.operand_type)
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 
-    pub fn get_operands(&self) -> &Vec<u32> {
-        return &self.operands;
+    // the instruction in the syntax the assembler accepts, `label` names a code address for a
+    // label operand or a data address for a memory operand
+    pub fn render(
+        &self,
+        optspec: &OptSpec,
+        label: impl Fn(OperandType, u32) -> Option<String>,
+    ) -> String {
+        let operands: Vec<String> = self
+            .operand_types(optspec)
+            .into_iter()
+            .zip(self.operands())
+            .map(|(operand_type, value)| match operand_type {
+                OperandType::Register => format!("R{}", value),
+                OperandType::Constant => (*value as u8 as i8).to_string(),
+                OperandType::Memory | OperandType::Label => {
+                    label(operand_type, *value).unwrap_or_else(|| value.to_string())
+                }
+                OperandType::Indirect => format!("[R{}]", value),
+                OperandType::Indexed => {
+                    let register_bits = optspec.register_bits();
+                    let register = value & ((1 << register_bits) - 1);
+                    format!("[R{}+{}]", register, value >> register_bits)
+                }
+                OperandType::PostIncrement => format!("[R{}+]", value),
+            })
+            .collect();
+        if operands.is_empty() {
+            self.opcode.to_string()
+        } else {
+            format!("{} {}", self.opcode, operands.join(", "))
+        }
     }
+}
 
-    pub fn get_operation_name(&self) -> &String {
-        return &self.operation_name;
+impl Display for DecodedInstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.opcode)?;
+        for (i, operand) in self.operands().iter().enumerate() {
+            write!(f, "{}{}", if i == 0 { " " } else { ", " }, operand)?;
+        }
+        Ok(())
     }
 }
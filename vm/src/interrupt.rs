@@ -0,0 +1,139 @@
+use crate::{Flags, MemoryAccess, MyVM, Type, VMError};
+
+pub const INTERRUPT_LINES: u8 = isa::INTERRUPT_LINES as u8;
+// the bit address of the handler for a line starts at data memory cell
+// `VECTOR_TABLE + line * address_bytes`, high byte first like a pushed return address
+pub use isa::VECTOR_TABLE;
+
+pub const TIMER_INTERRUPT: u8 = 0;
+pub const KEYBOARD_INTERRUPT: u8 = 1;
+
+// in the bit order of `Flags::to_byte`, EI and DI report IE
+const FLAG_NAMES: [&str; 5] = ["Z", "S", "O", "C", "IE"];
+
+impl Flags {
+    pub fn to_byte(&self) -> u8 {
+        self.zero as u8
+            | (self.sign as u8) << 1
+            | (self.overflow as u8) << 2
+            | (self.carry as u8) << 3
+            | (self.interrupt_enable as u8) << 4
+    }
+
+    pub fn from_byte(byte: u8) -> Self {
+        Self {
+            zero: byte & 1 != 0,
+            sign: byte & (1 << 1) != 0,
+            overflow: byte & (1 << 2) != 0,
+            carry: byte & (1 << 3) != 0,
+            interrupt_enable: byte & (1 << 4) != 0,
+        }
+    }
+
+    // the names of the flags that differ from `other`
+    pub fn changed(&self, other: &Flags) -> Vec<String> {
+        let changed = self.to_byte() ^ other.to_byte();
+        FLAG_NAMES
+            .iter()
+            .enumerate()
+            .filter(|(bit, _)| changed & (1 << bit) != 0)
+            .map(|(_, name)| name.to_string())
+            .collect()
+    }
+}
+
+impl MyVM {
+    // the line stays pending until interrupts are enabled
+    pub fn raise_interrupt(&mut self, line: u8) -> Result<(), VMError> {
+        if line >= INTERRUPT_LINES {
+            return Err(VMError::InvalidInterrupt(line));
+        }
+        self.pending_interrupts |= 1 << line;
+        Ok(())
+    }
+
+    pub fn pending_interrupts(&self) -> u8 {
+        self.pending_interrupts
+    }
+
+    // pushes the PC (see `push_address`) and the flags, then jumps through the vector table,
+    // the lowest pending line wins. Returns the line taken and the stack writes
+    pub(crate) fn service_interrupt(&mut self) -> Result<Option<(u8, Vec<MemoryAccess>)>, VMError> {
+        if !self.flags.interrupt_enable
+            || self.pending_interrupts == 0
+            || self.program_counter >= self.eof
+        {
+            return Ok(None);
+        }
+        let line = self.pending_interrupts.trailing_zeros() as u8;
+        self.pending_interrupts &= !(1 << line);
+
+        let mut accesses = self.push_address(self.program_counter)?;
+        accesses.push(self.push_byte(self.flags.to_byte())?);
+        self.flags.interrupt_enable = false;
+        self.program_counter = self.vector(line)?;
+        Ok(Some((line, accesses)))
+    }
+
+    // the bit address of the handler for `line`, stored like a pushed return address
+    pub fn vector(&self, line: u8) -> Result<u32, VMError> {
+        let bytes = self.opt_spec.address_bytes();
+        let start = VECTOR_TABLE + line as u32 * bytes;
+        let mut address = 0;
+        for byte in 0..bytes {
+            address = address << 8 | self.data_memory.get(start + byte)? as u32;
+        }
+        Ok(address)
+    }
+
+    // points a line at the handler at bit address `address`
+    pub fn set_vector(&mut self, line: u8, address: u32) -> Result<(), VMError> {
+        if line >= INTERRUPT_LINES {
+            return Err(VMError::InvalidInterrupt(line));
+        }
+        let bytes = self.opt_spec.address_bytes();
+        let start = VECTOR_TABLE + line as u32 * bytes;
+        for byte in 0..bytes {
+            let shift = 8 * (bytes - 1 - byte);
+            self.data_memory
+                .set(start + byte, (address >> shift) as u8)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn push_byte(&mut self, value: u8) -> Result<MemoryAccess, VMError> {
+        self.stack_pointer = self
+            .stack_pointer
+            .checked_sub(1)
+            .ok_or(VMError::StackOverflow)?;
+        self.write_ram(self.stack_pointer, value)?;
+        Ok(MemoryAccess {
+            address: self.stack_pointer,
+            value,
+            type_: Type::Write,
+            device: None,
+        })
+    }
+
+    pub(crate) fn pop_byte(&mut self) -> Result<u8, VMError> {
+        let value = self.data_memory.get(self.stack_pointer)?;
+        self.stack_pointer += 1;
+        Ok(value)
+    }
+
+    // a return address takes as many bytes as the address width needs. The low byte is pushed
+    // first, so the address ends up high byte at the lower address, the same order as a DW word
+    pub(crate) fn push_address(&mut self, address: u32) -> Result<Vec<MemoryAccess>, VMError> {
+        (0..self.opt_spec.address_bytes())
+            .map(|byte| self.push_byte((address >> (8 * byte)) as u8))
+            .collect()
+    }
+
+    pub(crate) fn pop_address(&mut self) -> Result<u32, VMError> {
+        let mut address = 0;
+        for _ in 0..self.opt_spec.address_bytes() {
+            address = address << 8 | self.pop_byte()? as u32;
+        }
+        Ok(address)
+    }
+}
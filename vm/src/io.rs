@@ -0,0 +1,148 @@
+use std::{
+    cell::RefCell,
+    collections::VecDeque,
+    fmt::Display,
+    io::{self, Write, stdin, stdout},
+    rc::Rc,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Output {
+    Register { register: u32, value: i8 },
+    Combined(i16),
+    Char(char),
+}
+
+impl Display for Output {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Output::Register { register, value } => {
+                writeln!(f, "Output from register {register}: {value}")
+            }
+            Output::Combined(value) => {
+                writeln!(f, "Combined output from registers 0 and 1: {value}")
+            }
+            Output::Char(value) => write!(f, "{value}"),
+        }
+    }
+}
+
+pub trait IoDevice {
+    // returns the raw line entered for `IN register`, parsing is left to the VM
+    fn input(&mut self, register: u32) -> io::Result<String>;
+    fn output(&mut self, output: Output) -> io::Result<()>;
+    // unprompted input for devices such as the keyboard, an empty line means nothing is available
+    fn read_line(&mut self) -> io::Result<String> {
+        Ok(String::new())
+    }
+}
+
+pub struct ConsoleIo;
+
+impl IoDevice for ConsoleIo {
+    fn input(&mut self, register: u32) -> io::Result<String> {
+        let mut input = String::new();
+        print!("Enter value for register {register}: ");
+        stdout().flush()?;
+        stdin().read_line(&mut input)?;
+        Ok(input)
+    }
+
+    fn output(&mut self, output: Output) -> io::Result<()> {
+        print!("{output}");
+        stdout().flush()
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut input = String::new();
+        stdin().read_line(&mut input)?;
+        Ok(input)
+    }
+}
+
+// clones share the same queues, so a caller can keep one handle and hand another to the VM
+#[derive(Clone, Default)]
+pub struct ScriptedIo {
+    inputs: Rc<RefCell<VecDeque<String>>>,
+    outputs: Rc<RefCell<Vec<Output>>>,
+}
+
+impl ScriptedIo {
+    pub fn new<I, S>(inputs: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self {
+            inputs: Rc::new(RefCell::new(inputs.into_iter().map(Into::into).collect())),
+            outputs: Rc::default(),
+        }
+    }
+
+    pub fn push_input(&self, input: impl Into<String>) {
+        self.inputs.borrow_mut().push_back(input.into());
+    }
+
+    pub fn outputs(&self) -> Vec<Output> {
+        self.outputs.borrow().clone()
+    }
+
+    // everything written so far, formatted the way the console device prints it
+    pub fn transcript(&self) -> String {
+        self.outputs
+            .borrow()
+            .iter()
+            .map(|output| output.to_string())
+            .collect()
+    }
+}
+
+impl IoDevice for ScriptedIo {
+    fn input(&mut self, register: u32) -> io::Result<String> {
+        self.inputs.borrow_mut().pop_front().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("No scripted input left for register {register}"),
+            )
+        })
+    }
+
+    fn output(&mut self, output: Output) -> io::Result<()> {
+        self.outputs.borrow_mut().push(output);
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        Ok(self.inputs.borrow_mut().pop_front().unwrap_or_default())
+    }
+}
+
+type InputCallback = Box<dyn FnMut(u32) -> io::Result<String>>;
+type OutputCallback = Box<dyn FnMut(Output) -> io::Result<()>>;
+
+pub struct CallbackIo {
+    input: InputCallback,
+    output: OutputCallback,
+}
+
+impl CallbackIo {
+    pub fn new(
+        input: impl FnMut(u32) -> io::Result<String> + 'static,
+        output: impl FnMut(Output) -> io::Result<()> + 'static,
+    ) -> Self {
+        Self {
+            input: Box::new(input),
+            output: Box::new(output),
+        }
+    }
+}
+
+impl IoDevice for CallbackIo {
+    fn input(&mut self, register: u32) -> io::Result<String> {
+        (self.input)(register)
+    }
+
+    fn output(&mut self, output: Output) -> io::Result<()> {
+        (self.output)(output)
+    }
+}
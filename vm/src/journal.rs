@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+
+use crate::{Flags, MyVM, VMError, register::Register, snapshot::DeviceState};
+
+pub const DEFAULT_JOURNAL_DEPTH: usize = 1024;
+
+// everything a single step can change, captured before the step runs
+struct JournalEntry {
+    program_counter: u32,
+    stack_pointer: u32,
+    flags: Flags,
+    registers: Register<u8>,
+    pending_interrupts: u8,
+    // (address, previous value) in the order the writes happened
+    memory: Vec<(u32, u8)>,
+    devices: Vec<DeviceState>,
+}
+
+pub struct Journal {
+    depth: usize,
+    entries: VecDeque<JournalEntry>,
+}
+
+impl Journal {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            entries: VecDeque::new(),
+        }
+    }
+
+    fn begin(&mut self, entry: JournalEntry) {
+        if self.depth == 0 {
+            return;
+        }
+        if self.entries.len() == self.depth {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    fn record_write(&mut self, address: u32, previous: u8) {
+        if let Some(entry) = self.entries.back_mut() {
+            entry.memory.push((address, previous));
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+impl MyVM {
+    // 0 disables the journal, older entries are dropped once it is full
+    pub fn set_journal_depth(&mut self, depth: usize) {
+        self.journal.depth = depth;
+        while self.journal.entries.len() > depth {
+            self.journal.entries.pop_front();
+        }
+    }
+
+    pub fn journal(&self) -> &Journal {
+        &self.journal
+    }
+
+    pub(crate) fn begin_journal_entry(&mut self) {
+        if self.journal.depth == 0 {
+            return;
+        }
+        self.journal.begin(JournalEntry {
+            program_counter: self.program_counter,
+            stack_pointer: self.stack_pointer,
+            flags: self.flags,
+            registers: self.register.clone(),
+            pending_interrupts: self.pending_interrupts,
+            memory: Vec::new(),
+            devices: self.bus.save(),
+        });
+    }
+
+    // every data memory write made by an instruction goes through here so it can be undone
+    pub(crate) fn write_ram(&mut self, address: u32, value: u8) -> Result<(), VMError> {
+        let previous = self.data_memory.get(address)?;
+        self.data_memory.set(address, value)?;
+        self.journal.record_write(address, previous);
+        Ok(())
+    }
+
+    // undoes the last step, including a step that failed halfway; false once the journal is empty
+    pub fn step_back(&mut self) -> Result<bool, VMError> {
+        let Some(entry) = self.journal.entries.pop_back() else {
+            return Ok(false);
+        };
+        for (address, previous) in entry.memory.into_iter().rev() {
+            self.data_memory.set(address, previous)?;
+        }
+        self.bus.restore(&entry.devices)?;
+        self.program_counter = entry.program_counter;
+        self.stack_pointer = entry.stack_pointer;
+        self.flags = entry.flags;
+        self.register = entry.registers;
+        self.pending_interrupts = entry.pending_interrupts;
+        Ok(true)
+    }
+
+    // steps back until `stop` accepts the PC or the journal runs out, returns the steps undone
+    pub fn reverse_continue(&mut self, stop: impl Fn(u32) -> bool) -> Result<usize, VMError> {
+        let mut undone = 0;
+        while self.step_back()? {
+            undone += 1;
+            if stop(self.program_counter) {
+                break;
+            }
+        }
+        Ok(undone)
+    }
+}
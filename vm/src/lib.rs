@@ -1,16 +1,32 @@
+pub mod bus;
+pub mod debugger;
+#[cfg(test)]
+mod flag_usage;
 mod handler;
-mod instruction;
+pub mod instruction;
+pub mod interrupt;
+pub mod io;
+pub mod journal;
 mod memory;
 mod register;
+pub mod snapshot;
+pub mod symbols;
+pub mod trace;
 
-use crate::instruction::{Instruction, InstructionError};
+use crate::bus::{Bus, BusError};
+use crate::handler::Delta;
+use crate::instruction::{DecodedInstruction, InstructionError};
+use crate::io::{ConsoleIo, IoDevice};
+use crate::journal::{DEFAULT_JOURNAL_DEPTH, Journal};
 use crate::memory::{Memory, MemoryError};
 use crate::register::{Register, RegisterError};
+use crate::snapshot::{DeviceState, SnapshotError};
+use crate::trace::{TraceError, TraceRecord, TraceWriter};
 use args::Args;
-use isa::OptSpec;
+use container::{Container, ContainerError, UNKNOWN_ISA};
+use isa::{IsaError, Opcode, OperandType, OptSpec};
 use logger::{LogTo, Logger, LoggerError};
-use std::io;
-use std::num::ParseIntError;
+use std::{collections::HashMap, num::ParseIntError};
 
 #[derive(Debug, thiserror::Error)]
 pub enum VMError {
@@ -19,19 +35,37 @@ pub enum VMError {
     #[error("{0}")]
     Register(#[from] RegisterError),
     #[error("{0}")]
-    IO(#[from] io::Error),
+    IO(#[from] std::io::Error),
     #[error("{0}")]
     ParseInt(#[from] ParseIntError),
-    #[error("Operation {0} not implemented yet")]
-    NoImplementation(String),
     #[error("{0}")]
     Instruction(#[from] InstructionError),
+    #[error("{0}")]
+    Bus(#[from] BusError),
     #[error("Logger error: {0}")]
     Logger(#[from] LoggerError),
-    #[error("Invalid binary")]
-    InvalidBinary,
-    #[error("Error converting Vec to slice")]
-    VecToSlice,
+    #[error("{0}")]
+    Snapshot(#[from] SnapshotError),
+    #[error("Trace error: {0}")]
+    Trace(#[from] TraceError),
+    #[error("Invalid interrupt line {0}")]
+    InvalidInterrupt(u8),
+    #[error("Stack overflow, the stack pointer is already at the bottom of data memory")]
+    StackOverflow,
+    #[error("Invalid binary: {0}")]
+    Container(#[from] ContainerError),
+    #[error("{0}")]
+    Isa(#[from] IsaError),
+    #[error(
+        "The program is {bits} bits long, {address_bit_count} bit addresses reach {capacity} bits"
+    )]
+    ProgramTooLarge {
+        bits: u32,
+        capacity: u32,
+        address_bit_count: u8,
+    },
+    #[error("The data image is {bytes} bytes long, data memory holds {capacity}")]
+    DataTooLarge { bytes: u32, capacity: u32 },
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -40,12 +74,13 @@ pub struct Flags {
     pub sign: bool,
     pub overflow: bool,
     pub carry: bool,
+    pub interrupt_enable: bool,
 }
 
 pub struct MyVM {
     pub program_counter: u32,
     pub eof: u32,
-    pub program_memory: Memory<u8>,
+    program_memory: Memory<u8>,
     pub data_memory: Memory<u8>,
     pub register: Register<u8>,
     pub flags: Flags,
@@ -53,19 +88,28 @@ pub struct MyVM {
     pub opt_spec: OptSpec,
     pub logger: Logger,
     pub stack_pointer: u32,
+    instruction_cache: Vec<Option<DecodedInstruction>>,
+    io: Box<dyn IoDevice>,
+    pub bus: Bus,
+    pending_interrupts: u8,
+    journal: Journal,
+    tracer: Option<TraceWriter>,
+    // labels from the symbol section of the loaded binary
+    symbols: HashMap<String, u32>,
 }
 
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Clone, serde::Serialize, serde::Deserialize)]
 pub enum Type {
     Read,
     Write,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct MemoryAccess {
     pub address: u32,
     pub value: u8,
     pub type_: Type,
+    pub device: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -74,37 +118,104 @@ pub struct ExecutionStep {
     pub address: u32,
     pub changed_flags: Vec<String>,
     pub changed_regs: Vec<String>,
-    pub memory_access: Option<MemoryAccess>,
+    pub memory_accesses: Vec<MemoryAccess>,
     pub is_halted: bool,
     pub stack_pointer: u32,
+    pub interrupt: Option<u8>,
 }
 
 #[derive(Clone)]
 pub struct VMState {
+    // identifier of the ISA the state was taken with
+    pub isa: u32,
     pub program_counter: u32,
+    pub eof: u32,
     pub registers: Register<u8>,
     pub flags: Flags,
     pub program_memory: Memory<u8>,
     pub data_memory: Memory<u8>,
     pub stack_pointer: u32,
+    pub pending_interrupts: u8,
+    pub devices: Vec<DeviceState>,
+}
+
+type Handler = fn(&mut MyVM, &[u32]) -> Result<Delta, VMError>;
+
+fn handler(opcode: Opcode) -> Handler {
+    match opcode {
+        Opcode::Halt => |vm, operands| vm.halt(operands),
+        Opcode::In => |vm, operands| vm.input(operands),
+        Opcode::Out => |vm, operands| vm.output(operands),
+        Opcode::Out16 => |vm, operands| vm.output_16(operands),
+        Opcode::OutChar => |vm, operands| vm.output_char(operands),
+        Opcode::Mover => |vm, operands| vm.mover(operands, OperandType::Memory),
+        Opcode::Movei => |vm, operands| vm.mover(operands, OperandType::Constant),
+        Opcode::Movem => |vm, operands| vm.movem(operands, OperandType::Memory),
+        Opcode::MoverIndirect => |vm, operands| vm.mover(operands, OperandType::Indirect),
+        Opcode::MoverIndexed => |vm, operands| vm.mover(operands, OperandType::Indexed),
+        Opcode::MoverPostIncrement => |vm, operands| vm.mover(operands, OperandType::PostIncrement),
+        Opcode::MovemIndirect => |vm, operands| vm.movem(operands, OperandType::Indirect),
+        Opcode::MovemIndexed => |vm, operands| vm.movem(operands, OperandType::Indexed),
+        Opcode::MovemPostIncrement => |vm, operands| vm.movem(operands, OperandType::PostIncrement),
+        Opcode::Add => |vm, operands| vm.add(operands, false),
+        Opcode::Addi => |vm, operands| vm.add(operands, true),
+        Opcode::Adc => |vm, operands| vm.adc(operands, false),
+        Opcode::Adci => |vm, operands| vm.adc(operands, true),
+        Opcode::Sub => |vm, operands| vm.sub(operands, false),
+        Opcode::Subi => |vm, operands| vm.sub(operands, true),
+        Opcode::Sbc => |vm, operands| vm.sbc(operands, false),
+        Opcode::Sbci => |vm, operands| vm.sbc(operands, true),
+        Opcode::Mult => |vm, operands| vm.mult(operands, false),
+        Opcode::Multi => |vm, operands| vm.mult(operands, true),
+        Opcode::Mult16 => |vm, operands| vm.mult_16(operands),
+        Opcode::Jmp => |vm, operands| vm.jmp(operands),
+        Opcode::Jz => |vm, operands| vm.jz(operands),
+        Opcode::Jnz => |vm, operands| vm.jnz(operands),
+        Opcode::And => |vm, operands| vm.and(operands),
+        Opcode::Or => |vm, operands| vm.or(operands),
+        Opcode::Xor => |vm, operands| vm.xor(operands),
+        Opcode::Not => |vm, operands| vm.not(operands),
+        Opcode::Shl => |vm, operands| vm.shl(operands),
+        Opcode::Shr => |vm, operands| vm.shr(operands),
+        Opcode::Cmp => |vm, operands| vm.cmp(operands, false),
+        Opcode::Cmpi => |vm, operands| vm.cmp(operands, true),
+        Opcode::Push => |vm, operands| vm.push(operands),
+        Opcode::Pop => |vm, operands| vm.pop(operands),
+        Opcode::Call => |vm, operands| vm.call(operands),
+        Opcode::Ret => |vm, operands| vm.ret(operands),
+        Opcode::Jg => |vm, operands| vm.jg(operands),
+        Opcode::Jge => |vm, operands| vm.jge(operands),
+        Opcode::Jl => |vm, operands| vm.jl(operands),
+        Opcode::Jle => |vm, operands| vm.jle(operands),
+        Opcode::Je => |vm, operands| vm.je(operands),
+        Opcode::Jne => |vm, operands| vm.jne(operands),
+        Opcode::Ei => |vm, operands| vm.ei(operands),
+        Opcode::Di => |vm, operands| vm.di(operands),
+        Opcode::Iret => |vm, operands| vm.iret(operands),
+    }
 }
 
 impl MyVM {
     pub fn new(args: &Args) -> Result<Self, VMError> {
+        let opt_spec = match &args.isa {
+            Some(path) => OptSpec::load(path)?,
+            None => OptSpec::clone(),
+        };
         Ok(Self {
             program_counter: 0,
             eof: 0,
-            opt_spec: OptSpec::clone(),
+            register: Register::new(opt_spec.register_count),
+            program_memory: Memory::new(opt_spec.address_space() / 8),
+            data_memory: Memory::new(opt_spec.data_memory_size),
+            stack_pointer: opt_spec.data_memory_size,
+            opt_spec,
             flags: Flags {
                 zero: false,
                 sign: false,
                 overflow: false,
                 carry: false,
+                interrupt_enable: false,
             },
-            program_memory: Memory::new(256),
-            data_memory: Memory::new(256),
-            register: Register::new(4),
-            stack_pointer: 256,
             logger: Logger::new(
                 if let Some(filename) = args.filename.clone() {
                     filename
@@ -123,125 +234,251 @@ impl MyVM {
                 },
             )?,
             debug: args.debug,
+            instruction_cache: Vec::new(),
+            io: Box::new(ConsoleIo),
+            bus: if args.devices {
+                Bus::standard()
+            } else {
+                Bus::default()
+            },
+            pending_interrupts: 0,
+            journal: Journal::new(args.history.unwrap_or(DEFAULT_JOURNAL_DEPTH)),
+            tracer: None,
+            symbols: HashMap::new(),
         })
     }
 
+    pub fn set_io_device(&mut self, device: impl IoDevice + 'static) {
+        self.io = Box::new(device);
+    }
+
     pub fn execute(
         &mut self,
-        instruction: Instruction,
+        instruction: DecodedInstruction,
         program_counter: u32,
     ) -> Result<ExecutionStep, VMError> {
-        let opcode = instruction.get_opcode();
-        let operands = instruction.get_operands();
+        let operands = instruction.operands();
         if self.debug {
             self.logger.log(format!(
                 "Executing instruction at PC {}: Opcode = {}, Operands = {:?}",
-                program_counter, opcode, operands
+                program_counter, instruction.opcode, operands
             ))?;
         }
 
-        let changes = match instruction.get_operation_name().to_lowercase().as_str() {
-            "halt" => Ok(self.halt(operands)?),
-            "in" => Ok(self.input(operands)?),
-            "out" => Ok(self.output(operands)?),
-            "out_16" => Ok(self.output_16(operands)?),
-            "out_char" => Ok(self.output_char(operands)?),
-            "mover" => Ok(self.mover(operands, false)?),
-            "movei" => Ok(self.mover(operands, true)?),
-            "movem" => Ok(self.movem(operands)?),
-            "add" => Ok(self.add(operands, false)?),
-            "addi" => Ok(self.add(operands, true)?),
-            "adc" => Ok(self.adc(operands, false)?),
-            "adci" => Ok(self.adc(operands, true)?),
-            "sub" => Ok(self.sub(operands, false)?),
-            "subi" => Ok(self.sub(operands, true)?),
-            "sbc" => Ok(self.sbc(operands, false)?),
-            "sbci" => Ok(self.sbc(operands, true)?),
-            "mult" => Ok(self.mult(operands, false)?),
-            "mult_16" => Ok(self.mult_16(operands, false)?),
-            "multi" => Ok(self.mult(operands, true)?),
-            "mult_16i" => Ok(self.mult_16(operands, true)?),
-            "jmp" => Ok(self.jmp(operands)?),
-            "jz" => Ok(self.jz(operands)?),
-            "jnz" => Ok(self.jnz(operands)?),
-            "and" => Ok(self.and(operands)?),
-            "or" => Ok(self.or(operands)?),
-            "xor" => Ok(self.xor(operands)?),
-            "not" => Ok(self.not(operands)?),
-            "shl" => Ok(self.shl(operands)?),
-            "shr" => Ok(self.shr(operands)?),
-            "cmp" => Ok(self.cmp(operands, false)?),
-            "cmpi" => Ok(self.cmp(operands, true)?),
-            "push" => Ok(self.push(operands)?),
-            "pop" => Ok(self.pop(operands)?),
-            "call" => Ok(self.call(operands)?),
-            "ret" => Ok(self.ret(operands)?),
-            "jge" => Ok(self.jge(operands)?),
-            "jl" => Ok(self.jl(operands)?),
-            _ => Err(VMError::NoImplementation(
-                instruction.get_operation_name().to_string(),
-            )),
-        }?;
+        let changes = handler(instruction.opcode)(self, operands)?;
+        self.pending_interrupts |= self.bus.tick();
 
         Ok(ExecutionStep {
-            instruction_str: format!("{:?}", instruction),
+            instruction_str: instruction.to_string(),
             address: program_counter,
             changed_flags: changes.flags,
             changed_regs: changes.registers,
-            memory_access: changes.memory_access,
+            memory_accesses: changes.memory_accesses,
             is_halted: self.eof == self.program_counter,
             stack_pointer: self.stack_pointer,
+            interrupt: None,
         })
     }
 
-    pub fn load_binary(&mut self, mut binary_bytes: Vec<u8>) -> Result<(), VMError> {
+    // data memory accesses made by the program go through the bus, mapped devices shadow RAM
+    pub fn load_data(&mut self, address: u32) -> Result<MemoryAccess, VMError> {
+        let (value, device) = match self.bus.read(address, self.io.as_mut())? {
+            Some((value, device)) => (value, Some(device.to_string())),
+            None => (self.data_memory.get(address)?, None),
+        };
+        Ok(MemoryAccess {
+            address,
+            value,
+            type_: Type::Read,
+            device,
+        })
+    }
+
+    pub fn store_data(&mut self, address: u32, value: u8) -> Result<MemoryAccess, VMError> {
+        let device = match self.bus.write(address, value, self.io.as_mut())? {
+            Some(device) => Some(device.to_string()),
+            None => {
+                self.write_ram(address, value)?;
+                None
+            }
+        };
+        Ok(MemoryAccess {
+            address,
+            value,
+            type_: Type::Write,
+            device,
+        })
+    }
+
+    // accepts both containers and the legacy bitstream + bit length format
+    pub fn load_binary(&mut self, binary_bytes: Vec<u8>) -> Result<(), VMError> {
+        self.load_container(Container::parse(&binary_bytes)?)
+    }
+
+    pub fn load_container(&mut self, container: Container) -> Result<(), VMError> {
+        if container.isa == UNKNOWN_ISA
+            && let Some(legacy) = self.opt_spec.for_legacy_binary()?
+        {
+            self.set_isa(legacy);
+        }
+        container.check_isa(self.opt_spec.identifier())?;
+        if container.code_bits > self.opt_spec.address_space() {
+            return Err(VMError::ProgramTooLarge {
+                bits: container.code_bits,
+                capacity: self.opt_spec.address_space(),
+                address_bit_count: self.opt_spec.address_bit_count,
+            });
+        }
+        if container.data.len() as u32 > self.opt_spec.data_memory_size {
+            return Err(VMError::DataTooLarge {
+                bytes: container.data.len() as u32,
+                capacity: self.opt_spec.data_memory_size,
+            });
+        }
         self.reset();
 
-        let eof = binary_bytes.split_off(binary_bytes.len() - 4);
-        self.eof = u32::from_be_bytes(eof.try_into().map_err(|_| VMError::VecToSlice)?);
+        self.eof = container.code_bits;
+        for (address, byte) in container.code.into_iter().enumerate() {
+            self.program_memory.set(address as u32, byte)?;
+        }
+        for (address, byte) in container.data.into_iter().enumerate() {
+            self.data_memory.set(address as u32, byte)?;
+        }
+        self.symbols = container.symbols.unwrap_or_default().into_iter().collect();
 
-        for byte in binary_bytes {
-            self.program_memory.set(self.program_counter, byte)?;
-            self.program_counter += 1;
+        self.program_counter = container.entry;
+        self.predecode();
+        Ok(())
+    }
+
+    pub fn symbols(&self) -> &HashMap<String, u32> {
+        &self.symbols
+    }
+
+    // the register file is sized for the new ISA, so register contents are lost, program
+    // memory follows the address width and data memory its size, both keep what still fits.
+    // A resized data memory starts with an empty stack at its new end
+    pub fn set_isa(&mut self, opt_spec: OptSpec) {
+        self.register = Register::new(opt_spec.register_count);
+        self.program_memory
+            .mem
+            .resize((opt_spec.address_space() / 8) as usize, 0);
+        if opt_spec.data_memory_size != self.data_memory.size() {
+            self.data_memory
+                .mem
+                .resize(opt_spec.data_memory_size as usize, 0);
+            self.stack_pointer = opt_spec.data_memory_size;
         }
+        self.opt_spec = opt_spec;
+        self.predecode();
+    }
 
-        self.program_counter = 0;
+    fn predecode(&mut self) {
+        self.instruction_cache = vec![None; self.eof as usize];
+        let mut address = 0;
+        while address < self.eof {
+            match DecodedInstruction::decode(&self.program_memory.mem, address, &self.opt_spec) {
+                Ok(instruction) => {
+                    self.instruction_cache[address as usize] = Some(instruction);
+                    address += instruction.size;
+                }
+                // anything past this point is decoded lazily if it is ever reached
+                Err(_) => break,
+            }
+        }
+    }
+
+    pub fn decode_at(&mut self, address: u32) -> Result<DecodedInstruction, VMError> {
+        if let Some(Some(instruction)) = self.instruction_cache.get(address as usize) {
+            return Ok(*instruction);
+        }
+        let instruction =
+            DecodedInstruction::decode(&self.program_memory.mem, address, &self.opt_spec)?;
+        if let Some(slot) = self.instruction_cache.get_mut(address as usize) {
+            *slot = Some(instruction);
+        }
+        Ok(instruction)
+    }
+
+    pub fn write_program_memory(&mut self, address: u32, value: u8) -> Result<(), VMError> {
+        self.program_memory.set(address, value)?;
+        // drop every cached instruction whose bits overlap the written byte
+        let first = (address * 8).saturating_sub(self.opt_spec.max_instruction_size() - 1);
+        let last = address * 8 + 8;
+        for slot in self
+            .instruction_cache
+            .iter_mut()
+            .take(last as usize)
+            .skip(first as usize)
+        {
+            *slot = None;
+        }
         Ok(())
     }
 
     pub fn step(&mut self) -> Result<ExecutionStep, VMError> {
-        let instruction = Instruction::new(
-            &self.program_memory,
-            &mut self.program_counter,
-            &self.opt_spec,
-        )?;
-        self.execute(instruction, self.program_counter)
+        self.begin_journal_entry();
+        let before = self
+            .tracer
+            .as_ref()
+            .map(|_| (self.register.clone(), self.flags));
+        let interrupt = self.service_interrupt()?;
+        let address = self.program_counter;
+        let instruction = self.decode_at(address)?;
+        self.program_counter += instruction.size;
+        let mut step = self.execute(instruction, address)?;
+        if let Some((line, mut accesses)) = interrupt {
+            // the interrupt frame is pushed before the instruction runs
+            accesses.append(&mut step.memory_accesses);
+            step.memory_accesses = accesses;
+            step.interrupt = Some(line);
+        }
+        if let (Some(tracer), Some((registers, flags))) = (&mut self.tracer, before) {
+            tracer.write(&TraceRecord::new(
+                &step,
+                &registers,
+                flags,
+                &self.register,
+                self.flags,
+            ))?;
+        }
+        Ok(step)
+    }
+
+    // every step from now on is written to the trace, until it is replaced or flushed
+    pub fn set_trace(&mut self, tracer: TraceWriter) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn flush_trace(&mut self) -> Result<(), VMError> {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.flush()?;
+        }
+        Ok(())
     }
 
     pub fn run(&mut self) -> Result<(), VMError> {
         println!("Starting execution...");
-        while self.program_counter < self.program_memory.size() && self.program_counter < self.eof {
-            match self.step() {
-                Ok(step_info) => {
-                    if self.debug {
-                        println!("{:?}", step_info);
-                    }
-                }
+        // the program counter counts bits, memory sizes count bytes
+        while self.program_counter < self.program_memory.size() * 8
+            && self.program_counter < self.eof
+        {
+            let step_info = match self.step() {
+                Ok(step_info) => step_info,
                 Err(err) => {
-                    println!("Failed to execute instruction:\n\t{}", err);
-                    std::process::exit(1);
+                    // keep the trace up to the failing instruction
+                    self.flush_trace()?;
+                    return Err(err);
                 }
+            };
+            if self.debug {
+                println!("{:?}", step_info);
             }
         }
+        self.flush_trace()?;
         println!("End of Execution.");
         if self.debug {
-            match self.print_registers() {
-                Ok(()) => {}
-                Err(err) => {
-                    println!("Failed to print registers:\n\t{}", err);
-                    std::process::exit(1);
-                }
-            };
+            self.print_registers()?;
             self.print_program_counter();
         }
         Ok(())
@@ -253,24 +490,86 @@ impl MyVM {
         self.flags.carry = false;
         self.flags.sign = false;
         self.flags.overflow = false;
-        self.register = Register::new(4);
-        self.data_memory = Memory::new(256);
-        self.program_memory = Memory::new(256);
+        self.flags.interrupt_enable = false;
+        self.pending_interrupts = 0;
+        self.stack_pointer = self.opt_spec.data_memory_size;
+        self.register = Register::new(self.opt_spec.register_count);
+        self.data_memory = Memory::new(self.opt_spec.data_memory_size);
+        self.program_memory = Memory::new(self.opt_spec.address_space() / 8);
+        self.instruction_cache.clear();
+        self.bus.reset();
+        self.journal.clear();
     }
 
     pub fn get_state_struct(&self) -> VMState {
         VMState {
+            isa: self.opt_spec.identifier(),
             program_counter: self.program_counter,
             flags: self.flags,
             registers: self.register.clone(),
             data_memory: self.data_memory.clone(),
             program_memory: self.program_memory.clone(),
             stack_pointer: self.stack_pointer,
+            eof: self.eof,
+            pending_interrupts: self.pending_interrupts,
+            devices: self.bus.save(),
         }
     }
 
+    // devices are matched by address and name, so the VM must have the same ISA and devices,
+    // the register file and both memories must have the sizes of this VM
+    pub fn restore(&mut self, state: VMState) -> Result<(), VMError> {
+        let current = self.opt_spec.identifier();
+        if state.isa != current {
+            return Err(SnapshotError::IsaMismatch {
+                saved: state.isa,
+                current,
+            }
+            .into());
+        }
+        let sizes = [
+            (
+                "registers",
+                state.registers.regs.len() as u32,
+                self.register.regs.len() as u32,
+            ),
+            (
+                "bytes of program memory",
+                state.program_memory.size(),
+                self.program_memory.size(),
+            ),
+            (
+                "bytes of data memory",
+                state.data_memory.size(),
+                self.data_memory.size(),
+            ),
+        ];
+        for (what, saved, current) in sizes {
+            if saved != current {
+                return Err(SnapshotError::Mismatch {
+                    what,
+                    saved,
+                    current,
+                }
+                .into());
+            }
+        }
+        self.bus.restore(&state.devices)?;
+        self.program_counter = state.program_counter;
+        self.eof = state.eof;
+        self.register = state.registers;
+        self.flags = state.flags;
+        self.program_memory = state.program_memory;
+        self.data_memory = state.data_memory;
+        self.stack_pointer = state.stack_pointer;
+        self.pending_interrupts = state.pending_interrupts;
+        self.journal.clear();
+        self.predecode();
+        Ok(())
+    }
+
     pub fn print_registers(&self) -> Result<(), VMError> {
-        for i in 0..4 {
+        for i in 0..self.register.count {
             println!("Register {i}: {}", self.register.get(i)?);
         }
         Ok(())
@@ -280,3 +579,545 @@ impl MyVM {
         println!("Program Counter: {}", self.program_counter);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interrupt::{INTERRUPT_LINES, KEYBOARD_INTERRUPT, TIMER_INTERRUPT, VECTOR_TABLE};
+    use crate::io::{CallbackIo, Output, ScriptedIo};
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn test_every_operation_decodes_to_its_opcode() {
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        let opt_spec = OptSpec::clone();
+        for operation in opt_spec.get_operations() {
+            let opcode_bits = opt_spec.opcode_bit_count as u32;
+            vm.write_program_memory(0, (operation.opcode << (8 - opcode_bits)) as u8)
+                .unwrap();
+            let instruction = vm.decode_at(0).unwrap();
+            assert_eq!(instruction.opcode, operation.kind);
+            assert_eq!(
+                instruction.size,
+                opcode_bits + operation.operand_bit_count()
+            );
+            assert_eq!(instruction.operands().len(), operation.operands.len());
+        }
+    }
+
+    #[test]
+    fn test_program_memory_write_invalidates_cache() {
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        // MOVEI R1, 5 followed by HALT
+        vm.load_binary(vec![0b00001001, 0b00000101, 0, 0, 0, 0, 22])
+            .unwrap();
+        assert_eq!(vm.decode_at(0).unwrap().opcode, Opcode::Movei);
+        // patch it into MOVEM R1, 0
+        vm.write_program_memory(0, 0b00001101).unwrap();
+        assert_eq!(vm.decode_at(0).unwrap().opcode, Opcode::Movem);
+    }
+
+    #[test]
+    fn test_signed_conditional_jumps() {
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        for (a, b) in [
+            (5i8, 3i8),
+            (3, 5),
+            (4, 4),
+            (-100, 100),
+            (100, -100),
+            (-1, -2),
+        ] {
+            vm.register.set(0, a as u8).unwrap();
+            vm.register.set(1, b as u8).unwrap();
+            vm.cmp(&[0, 1], false).unwrap();
+            let jumps = [
+                (Opcode::Jg, a > b),
+                (Opcode::Jge, a >= b),
+                (Opcode::Jl, a < b),
+                (Opcode::Jle, a <= b),
+                (Opcode::Je, a == b),
+                (Opcode::Jne, a != b),
+            ];
+            for (name, expected) in jumps {
+                vm.program_counter = 0;
+                handler(name)(&mut vm, &[42]).unwrap();
+                assert_eq!(
+                    vm.program_counter == 42,
+                    expected,
+                    "{name} after CMP {a}, {b}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_scripted_io() {
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        let io = ScriptedIo::new(["-7", "65"]);
+        vm.set_io_device(io.clone());
+
+        handler(Opcode::In)(&mut vm, &[2]).unwrap();
+        handler(Opcode::Out)(&mut vm, &[2]).unwrap();
+        handler(Opcode::In)(&mut vm, &[3]).unwrap();
+        handler(Opcode::OutChar)(&mut vm, &[3]).unwrap();
+        vm.register.set(1, 1).unwrap();
+        vm.register.set(0, 2).unwrap();
+        handler(Opcode::Out16)(&mut vm, &[]).unwrap();
+
+        assert_eq!(
+            io.outputs(),
+            vec![
+                Output::Register {
+                    register: 2,
+                    value: -7
+                },
+                Output::Char('A'),
+                Output::Combined(258),
+            ]
+        );
+        assert_eq!(
+            io.transcript(),
+            "Output from register 2: -7\nACombined output from registers 0 and 1: 258\n"
+        );
+        assert!(matches!(
+            handler(Opcode::In)(&mut vm, &[0]),
+            Err(VMError::IO(_))
+        ));
+    }
+
+    #[test]
+    fn test_callback_io() {
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        let written = Rc::new(RefCell::new(Vec::new()));
+        let sink = written.clone();
+        vm.set_io_device(CallbackIo::new(
+            |register| Ok(format!("{}\n", register * 10)),
+            move |output| {
+                sink.borrow_mut().push(output);
+                Ok(())
+            },
+        ));
+
+        handler(Opcode::In)(&mut vm, &[3]).unwrap();
+        assert_eq!(vm.register.get(3).unwrap(), 30);
+        handler(Opcode::Out)(&mut vm, &[3]).unwrap();
+        assert_eq!(
+            *written.borrow(),
+            vec![Output::Register {
+                register: 3,
+                value: 30
+            }]
+        );
+    }
+
+    #[test]
+    fn test_addressing_modes() {
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        vm.data_memory.mem[200] = 7;
+        vm.register.set(1, 200).unwrap();
+        handler(Opcode::MoverIndirect)(&mut vm, &[0, 1]).unwrap();
+        assert_eq!(vm.register.get(0).unwrap(), 7);
+
+        // offset 3 above register 1
+        handler(Opcode::MovemIndexed)(&mut vm, &[0, 3 << 2 | 1]).unwrap();
+        assert_eq!(vm.data_memory.mem[203], 7);
+
+        let delta = handler(Opcode::MoverPostIncrement)(&mut vm, &[2, 1]).unwrap();
+        assert_eq!(vm.register.get(2).unwrap(), 7);
+        assert_eq!(vm.register.get(1).unwrap(), 201);
+        assert_eq!(delta.registers, ["R1", "R2"]);
+
+        // the value is read before the register steps
+        handler(Opcode::MovemPostIncrement)(&mut vm, &[1, 1]).unwrap();
+        assert_eq!(vm.data_memory.mem[201], 201);
+        assert_eq!(vm.register.get(1).unwrap(), 202);
+    }
+
+    #[test]
+    fn test_mapped_devices() {
+        let args = Args {
+            devices: true,
+            ..Args::default()
+        };
+        let mut vm = MyVM::new(&args).unwrap();
+        let io = ScriptedIo::new(["hi\n"]);
+        vm.set_io_device(io.clone());
+
+        vm.register.set(0, b'A').unwrap();
+        handler(Opcode::Movem)(&mut vm, &[0, bus::TERMINAL_ADDRESS]).unwrap();
+        assert_eq!(io.outputs(), vec![Output::Char('A')]);
+        assert_eq!(vm.data_memory.get(bus::TERMINAL_ADDRESS).unwrap(), 0);
+
+        let delta = handler(Opcode::Mover)(&mut vm, &[1, bus::KEYBOARD_ADDRESS]).unwrap();
+        assert_eq!(vm.register.get(1).unwrap(), b'h');
+        let access = &delta.memory_accesses[0];
+        assert_eq!(access.device.as_deref(), Some("keyboard"));
+        assert_eq!(access.type_, Type::Read);
+
+        vm.register.set(2, 2).unwrap();
+        handler(Opcode::Movem)(&mut vm, &[2, bus::TIMER_ADDRESS]).unwrap();
+        vm.bus.tick();
+        assert_eq!(vm.load_data(bus::TIMER_ADDRESS).unwrap().value, 1);
+        vm.bus.tick();
+        vm.bus.tick();
+        assert_eq!(vm.load_data(bus::TIMER_ADDRESS).unwrap().value, 0);
+
+        let first = vm.load_data(bus::RNG_ADDRESS).unwrap().value;
+        let second = vm.load_data(bus::RNG_ADDRESS).unwrap().value;
+        assert_ne!(first, second);
+        vm.reset();
+        assert_eq!(vm.load_data(bus::RNG_ADDRESS).unwrap().value, first);
+
+        // unmapped addresses are still plain RAM
+        let access = vm.store_data(3, 9).unwrap();
+        assert_eq!(access.device, None);
+        assert_eq!(vm.data_memory.get(3).unwrap(), 9);
+        assert!(matches!(
+            vm.bus
+                .map(bus::TIMER_ADDRESS, Box::new(bus::Timer::default())),
+            Err(BusError::Overlap { .. })
+        ));
+    }
+
+    // packs (value, bit count) fields into a binary the way the encoder lays them out
+    fn program(fields: &[(u32, u32)]) -> Vec<u8> {
+        let mut bits = Vec::new();
+        for (value, count) in fields {
+            bits.extend((0..*count).rev().map(|bit| (value >> bit) & 1 == 1));
+        }
+        let binary: Vec<u8> = bits
+            .chunks(8)
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0, |byte, (i, bit)| byte | (*bit as u8) << (7 - i))
+            })
+            .collect();
+        Container::new(OptSpec::clone().identifier(), binary, bits.len() as u32).to_bytes()
+    }
+
+    #[test]
+    fn test_interrupt_entry_and_iret() {
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        vm.load_binary(program(&[
+            (43, 6), // 0: EI
+            (2, 6),  // 6: MOVEI R0, 1
+            (0, 2),
+            (1, 8),
+            (0, 6), // 22: HALT
+            (2, 6), // 28: MOVEI R1, 7
+            (1, 2),
+            (7, 8),
+            (45, 6), // 44: IRET
+        ]))
+        .unwrap();
+        vm.set_vector(2, 28).unwrap();
+
+        // nothing is taken while interrupts are disabled
+        vm.raise_interrupt(2).unwrap();
+        assert_eq!(vm.step().unwrap().interrupt, None);
+        assert!(vm.flags.interrupt_enable);
+
+        let step = vm.step().unwrap();
+        assert_eq!(step.interrupt, Some(2));
+        assert_eq!(step.address, 28);
+        assert!(!vm.flags.interrupt_enable);
+        assert_eq!(vm.stack_pointer, 253);
+        // the return address is stored high byte first, like a DW word
+        assert_eq!(vm.data_memory.get(254).unwrap(), 0);
+        assert_eq!(vm.data_memory.get(255).unwrap(), 6);
+        assert!(Flags::from_byte(vm.data_memory.get(253).unwrap()).interrupt_enable);
+        let pushed: Vec<_> = step
+            .memory_accesses
+            .iter()
+            .map(|access| (access.address, access.value, access.type_.clone()))
+            .collect();
+        assert_eq!(pushed[..2], [(255, 6, Type::Write), (254, 0, Type::Write)]);
+        assert_eq!(pushed[2].0, 253);
+
+        // a line raised inside the handler waits for IRET to re-enable interrupts
+        vm.raise_interrupt(TIMER_INTERRUPT).unwrap();
+        vm.set_vector(TIMER_INTERRUPT, 22).unwrap();
+        let step = vm.step().unwrap();
+        assert_eq!(step.interrupt, None);
+        assert_eq!(step.changed_flags, vec![String::from("IE")]);
+        assert_eq!(vm.program_counter, 6);
+        assert_eq!(vm.stack_pointer, 256);
+        assert!(vm.flags.interrupt_enable);
+        assert_eq!(vm.step().unwrap().interrupt, Some(TIMER_INTERRUPT));
+        assert_eq!(vm.program_counter, vm.eof);
+        assert_eq!(vm.register.get(0).unwrap(), 0);
+        assert_eq!(vm.register.get(1).unwrap(), 7);
+
+        assert!(matches!(
+            vm.raise_interrupt(4),
+            Err(VMError::InvalidInterrupt(4))
+        ));
+    }
+
+    #[test]
+    fn test_vector_beyond_first_byte() {
+        let mut fields = vec![
+            (43, 6), // 0: EI
+            (2, 6),  // 6: MOVEI R0, 1
+            (0, 2),
+            (1, 8),
+            (0, 6), // 22: HALT
+        ];
+        // sixteen MOVEI R0, 0 from bit 28 put the handler at bit 284
+        fields.extend([(2, 6), (0, 2), (0, 8)].repeat(16));
+        fields.extend([
+            (2, 6), // 284: MOVEI R1, 7
+            (1, 2),
+            (7, 8),
+            (45, 6), // 300: IRET
+        ]);
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        vm.load_binary(program(&fields)).unwrap();
+        vm.set_vector(KEYBOARD_INTERRUPT, 284).unwrap();
+        // two bytes per line, high byte first
+        let start = VECTOR_TABLE + 2 * KEYBOARD_INTERRUPT as u32;
+        assert_eq!(vm.data_memory.get(start).unwrap(), 1);
+        assert_eq!(vm.data_memory.get(start + 1).unwrap(), 28);
+        assert_eq!(vm.vector(KEYBOARD_INTERRUPT).unwrap(), 284);
+
+        vm.raise_interrupt(KEYBOARD_INTERRUPT).unwrap();
+        vm.step().unwrap();
+        let step = vm.step().unwrap();
+        assert_eq!(step.interrupt, Some(KEYBOARD_INTERRUPT));
+        assert_eq!(step.address, 284);
+        vm.step().unwrap();
+        assert_eq!(vm.program_counter, 6);
+        assert_eq!(vm.register.get(1).unwrap(), 7);
+        assert!(matches!(
+            vm.set_vector(INTERRUPT_LINES, 0),
+            Err(VMError::InvalidInterrupt(_))
+        ));
+    }
+
+    #[test]
+    fn test_timer_interrupt() {
+        let args = Args {
+            devices: true,
+            ..Args::default()
+        };
+        let mut vm = MyVM::new(&args).unwrap();
+        vm.load_binary(program(&[
+            (43, 6), // 0: EI
+            (2, 6),  // 6: MOVEI R0, 2
+            (0, 2),
+            (2, 8),
+            (3, 6), // 22: MOVEM R0, 14
+            (0, 2),
+            (bus::TIMER_ADDRESS, 4),
+            (21, 6), // 34: JMP 34
+            (34, 16),
+            (0, 6), // 56: HALT
+        ]))
+        .unwrap();
+        vm.set_vector(TIMER_INTERRUPT, 56).unwrap();
+
+        let mut interrupts = Vec::new();
+        for _ in 0..10 {
+            let step = vm.step().unwrap();
+            interrupts.extend(step.interrupt);
+            if step.is_halted {
+                break;
+            }
+        }
+        assert_eq!(interrupts, vec![TIMER_INTERRUPT]);
+        assert_eq!(vm.program_counter, vm.eof);
+        assert_eq!(vm.data_memory.get(255).unwrap(), 34);
+    }
+
+    #[test]
+    fn test_call_beyond_first_byte() {
+        // sixteen MOVEI R0, 0 put the CALL at bit 256, past what one byte can hold
+        let mut fields = [(2, 6), (0, 2), (0, 8)].repeat(16);
+        fields.extend([
+            (34, 6), // 256: CALL 284
+            (284, 16),
+            (0, 6), // 278: HALT
+            (2, 6), // 284: MOVEI R1, 5
+            (1, 2),
+            (5, 8),
+            (35, 6), // 300: RET
+        ]);
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        vm.load_binary(program(&fields)).unwrap();
+        vm.program_counter = 256;
+
+        vm.step().unwrap();
+        assert_eq!(vm.program_counter, 284);
+        assert_eq!(vm.stack_pointer, 254);
+        // 278 is 1 * 256 + 22, high byte at the lower address
+        assert_eq!(vm.data_memory.get(254).unwrap(), 1);
+        assert_eq!(vm.data_memory.get(255).unwrap(), 22);
+        vm.step().unwrap();
+        vm.step().unwrap();
+        assert_eq!(vm.program_counter, 278);
+        assert_eq!(vm.stack_pointer, 256);
+        assert!(vm.step().unwrap().is_halted);
+        assert_eq!(vm.register.get(1).unwrap(), 5);
+
+        // code past the address space is refused before anything is loaded
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        let too_large = Container::new(vm.opt_spec.identifier(), vec![0; 8193], 8193 * 8);
+        assert!(matches!(
+            vm.load_container(too_large),
+            Err(VMError::ProgramTooLarge {
+                capacity: 65536,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_step_back_and_reverse_continue() {
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        vm.load_binary(program(&[
+            (2, 6), // 0: MOVEI R0, 7
+            (0, 2),
+            (7, 8),
+            (32, 6), // 16: PUSH R0
+            (0, 2),
+            (3, 6), // 24: MOVEM R0, 3
+            (0, 2),
+            (3, 4),
+            (9, 6), // 36: ADDI R0, R0, 1
+            (0, 2),
+            (0, 2),
+            (1, 8),
+            (33, 6), // 54: POP R1
+            (1, 2),
+            (33, 6), // 62: POP R2, the stack is empty by now
+            (2, 2),
+        ]))
+        .unwrap();
+        let initial = vm.get_state_struct().to_bytes().unwrap();
+
+        for _ in 0..5 {
+            vm.step().unwrap();
+        }
+        let before_crash = vm.get_state_struct().to_bytes().unwrap();
+        assert!(vm.step().is_err());
+        assert!(vm.step_back().unwrap());
+        assert_eq!(vm.get_state_struct().to_bytes().unwrap(), before_crash);
+
+        assert_eq!(vm.reverse_continue(|address| address == 24).unwrap(), 3);
+        assert_eq!(vm.program_counter, 24);
+        assert_eq!(vm.data_memory.get(3).unwrap(), 0);
+        assert_eq!(vm.register.get(1).unwrap(), 0);
+        assert_eq!(vm.stack_pointer, 255);
+
+        assert_eq!(vm.reverse_continue(|_| false).unwrap(), 2);
+        assert_eq!(vm.get_state_struct().to_bytes().unwrap(), initial);
+        assert!(!vm.step_back().unwrap());
+
+        vm.set_journal_depth(2);
+        for _ in 0..3 {
+            vm.step().unwrap();
+        }
+        assert_eq!(vm.journal().len(), 2);
+        assert_eq!(vm.reverse_continue(|_| false).unwrap(), 2);
+        assert_eq!(vm.program_counter, 16);
+    }
+
+    #[test]
+    fn test_load_container() {
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        let binary = program(&[
+            (0, 6), // 0: HALT
+            (1, 6), // 6: MOVER R0, 2
+            (0, 2),
+            (2, 4),
+        ]);
+        let mut container = Container::parse(&binary).unwrap();
+        container.isa = vm.opt_spec.identifier();
+        container.entry = 6;
+        container.data = vec![0, 0, 42];
+        container.symbols = Some([(String::from("START"), 6)].into());
+        vm.load_binary(container.to_bytes()).unwrap();
+        assert_eq!(vm.program_counter, 6);
+        assert_eq!(vm.symbols().get("START"), Some(&6));
+        vm.step().unwrap();
+        assert_eq!(vm.register.get(0).unwrap(), 42);
+
+        container.isa ^= 1;
+        assert!(matches!(
+            vm.load_binary(container.to_bytes()),
+            Err(VMError::Container(ContainerError::IsaMismatch { .. }))
+        ));
+        // a legacy binary claiming 9 bits of code in one byte
+        assert!(matches!(
+            vm.load_binary(vec![0, 0, 0, 0, 9]),
+            Err(VMError::Container(ContainerError::CodeLength { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_data_memory_size() {
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        assert_eq!(vm.data_memory.size(), 256);
+        let opt_spec = OptSpec::from_toml(
+            &isa::DEFAULT_ISA.replace("data_memory_size = 256", "data_memory_size = 512"),
+        )
+        .unwrap();
+        vm.set_isa(opt_spec);
+        assert_eq!(vm.data_memory.size(), 512);
+        assert_eq!(vm.stack_pointer, 512);
+
+        // the stack starts at the end of the larger memory
+        vm.load_binary(program(&[
+            (34, 6), // 0: CALL 22
+            (22, 16),
+            (0, 6), // 22: HALT
+        ]))
+        .unwrap();
+        assert_eq!(vm.stack_pointer, 512);
+        vm.step().unwrap();
+        assert_eq!(vm.stack_pointer, 510);
+        assert_eq!(vm.data_memory.get(510).unwrap(), 0);
+        assert_eq!(vm.data_memory.get(511).unwrap(), 22);
+
+        let mut container = Container::new(vm.opt_spec.identifier(), vec![0], 6);
+        container.data = vec![0; 513];
+        assert!(matches!(
+            vm.load_container(container),
+            Err(VMError::DataTooLarge {
+                bytes: 513,
+                capacity: 512,
+            })
+        ));
+    }
+
+    #[test]
+    fn test_legacy_binary() {
+        // written by the assembler before binaries recorded their ISA, with 8 bit labels:
+        // MOVEI R0, 3 / LOOP: OUT R0 / SUBI R0, R0, 1 / JNZ LOOP / HALT
+        let binary = vec![0x08, 0x03, 0x18, 0x38, 0x00, 0x57, 0x10, 0x00, 0, 0, 0, 62];
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        let io = ScriptedIo::new(Vec::<&str>::new());
+        vm.set_io_device(io.clone());
+        vm.load_binary(binary.clone()).unwrap();
+        assert_eq!(vm.opt_spec.address_bit_count, 8);
+        vm.run().unwrap();
+        let values: Vec<Output> = [3, 2, 1]
+            .into_iter()
+            .map(|value| Output::Register { register: 0, value })
+            .collect();
+        assert_eq!(io.outputs(), values);
+
+        // another description only runs them with 8 bit labels of its own
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        let opt_spec =
+            OptSpec::from_toml(&isa::DEFAULT_ISA.replace("opcode = 0\n", "opcode = 63\n")).unwrap();
+        vm.set_isa(opt_spec);
+        assert!(matches!(
+            vm.load_binary(binary.clone()),
+            Err(VMError::Isa(IsaError::LegacyLabels { bit_count: 16, .. }))
+        ));
+    }
+}
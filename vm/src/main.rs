@@ -1,10 +1,14 @@
 use args::Args;
 use std::{
     fs::File,
-    io::{BufReader, Read},
+    io::{self, BufReader, Read},
     process,
 };
-use vm::MyVM;
+use vm::{
+    MyVM, VMState,
+    debugger::Debugger,
+    trace::{TraceFormat, TraceWriter},
+};
 
 pub fn main() {
     let args = match Args::parse() {
@@ -14,20 +18,6 @@ pub fn main() {
             std::process::exit(1);
         }
     };
-    let input_filename = match args.input_filename.clone() {
-        Some(filename) => {
-            if filename.split('.').last().unwrap() == "bin" {
-                filename
-            } else {
-                println!("VM only accepts .bin files");
-                process::exit(1);
-            }
-        }
-        None => {
-            println!("Usage: vm <filename.bin> [--debug] [--log=<console|file>]");
-            process::exit(1);
-        }
-    };
     let mut vm = match MyVM::new(&args) {
         Ok(vm) => vm,
         Err(err) => {
@@ -39,31 +29,101 @@ pub fn main() {
         println!("Debug mode enabled.");
     }
 
-    let file = match File::open(&input_filename) {
-        Ok(file) => file,
-        Err(err) => {
-            println!("Failed to open file:\n\t{}", err);
+    match (&args.load_state, &args.input_filename) {
+        (Some(snapshot), _) => {
+            println!("Loading snapshot: {}", snapshot);
+            if let Err(err) = VMState::load(snapshot).and_then(|state| vm.restore(state)) {
+                println!("Failed to load snapshot:\n\t{}", err);
+                std::process::exit(1);
+            }
+        }
+        (None, Some(input_filename)) => load_binary(&mut vm, input_filename),
+        (None, None) => {
+            println!(
+                "Usage: vm <filename.bin|-> [--debug] [--debugger] [--symbols=<file.sym>] [--devices] [--save-state=<file>] [--load-state=<file>] [--history=<steps>] [--trace=<file>] [--trace-format=<jsonl|binary>] [--isa=<file.toml>] [--log=<console|file>]"
+            );
+            process::exit(1);
+        }
+    }
+
+    if let Some(trace) = &args.trace {
+        let format = args.trace_format.as_deref().unwrap_or("jsonl");
+        match TraceFormat::parse(format).and_then(|format| TraceWriter::create(trace, format)) {
+            Ok(tracer) => vm.set_trace(tracer),
+            Err(err) => {
+                println!("Failed to create trace:\n\t{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if args.debugger {
+        let mut debugger = Debugger::new();
+        // a symbol file given on the command line wins over the binary's own symbol section
+        debugger.add_symbols(vm.symbols());
+        if let Some(symbols) = &args.symbols {
+            let contents = match std::fs::read_to_string(symbols) {
+                Ok(contents) => contents,
+                Err(err) => {
+                    println!("Failed to read symbol file:\n\t{}", err);
+                    std::process::exit(1);
+                }
+            };
+            if let Err(err) = debugger.load_symbols(&contents) {
+                println!("Failed to load symbols:\n\t{}", err);
+                std::process::exit(1);
+            }
+        }
+        let result = debugger.run(&mut vm);
+        if let Err(err) = vm.flush_trace() {
+            println!("Failed to write trace:\n\t{}", err);
+        }
+        if let Err(err) = result {
+            println!("Debugger failed:\n\t{}", err);
+            save_state(&vm, &args);
             std::process::exit(1);
         }
+        save_state(&vm, &args);
+        return;
+    }
+
+    // the state is saved on failure too, so it can be attached to a bug report
+    let result = vm.run();
+    save_state(&vm, &args);
+    if let Err(err) = result {
+        println!("Failed to run:\n\t{}", err);
+        std::process::exit(1);
     };
-    println!("Loading binary file: {}", input_filename);
-    let mut reader = BufReader::new(file);
+}
+
+// any file name is accepted, `-` reads the binary from stdin
+fn load_binary(vm: &mut MyVM, input_filename: &str) {
     let mut buffer = Vec::new();
-    match reader.read_to_end(&mut buffer) {
-        Ok(_) => (),
-        Err(err) => {
-            println!("Failed to read file:\n\t{}", err);
-            std::process::exit(1);
-        }
+    let result = if input_filename == "-" {
+        io::stdin().read_to_end(&mut buffer)
+    } else {
+        File::open(input_filename).and_then(|file| BufReader::new(file).read_to_end(&mut buffer))
     };
+    if let Err(err) = result {
+        println!("Failed to read {}:\n\t{}", input_filename, err);
+        std::process::exit(1);
+    }
+    println!("Loading binary file: {}", input_filename);
 
     if let Err(err) = vm.load_binary(buffer) {
         println!("Failed to load binary:\n\t{}", err);
         std::process::exit(1);
     };
+}
 
-    if let Err(err) = vm.run() {
-        println!("Failed to run:\n\t{}", err);
-        std::process::exit(1);
-    };
+fn save_state(vm: &MyVM, args: &Args) {
+    if let Some(snapshot) = &args.save_state {
+        match vm.get_state_struct().save(snapshot) {
+            Ok(()) => println!("State saved to {}", snapshot),
+            Err(err) => {
+                println!("Failed to save state:\n\t{}", err);
+                std::process::exit(1);
+            }
+        }
+    }
 }
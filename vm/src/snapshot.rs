@@ -0,0 +1,295 @@
+use std::{fs, path::Path};
+
+use crate::{Flags, VMError, VMState, memory::Memory, register::Register};
+
+const MAGIC: &[u8; 4] = b"VMST";
+pub const SNAPSHOT_VERSION: u8 = 3;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("Not a VM snapshot")]
+    BadMagic,
+    #[error("Unsupported snapshot version {0}, expected {SNAPSHOT_VERSION}")]
+    UnsupportedVersion(u8),
+    #[error("Snapshot is truncated")]
+    Truncated,
+    #[error("Snapshot has {0} unexpected trailing bytes")]
+    TrailingBytes(usize),
+    #[error("Invalid device name in snapshot")]
+    InvalidDeviceName,
+    #[error("A snapshot holds at most 255 registers, the VM has {0}")]
+    TooManyRegisters(usize),
+    #[error("Snapshot was taken with ISA {saved:08X}, this VM implements {current:08X}")]
+    IsaMismatch { saved: u32, current: u32 },
+    #[error("Snapshot was taken with {saved} {what}, this VM has {current}")]
+    Mismatch {
+        what: &'static str,
+        saved: u32,
+        current: u32,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceState {
+    pub address: u32,
+    pub name: String,
+    pub state: Vec<u8>,
+}
+
+// Layout (version 3), integers are big endian like the eof marker of a binary. The layout is
+// that of version 2, but data memory now holds the vectors after the devices and the stack
+// return addresses as wide as the ISA's, so older snapshots would resume wrongly:
+//   "VMST" version:u8 isa:u32 pc:u32 eof:u32 sp:u32 flags:u8 pending_interrupts:u8
+//   register_count:u8 registers:[u8]
+//   program_memory_len:u32 program_memory:[u8] data_memory_len:u32 data_memory:[u8]
+//   device_count:u8 { address:u32 name_len:u8 name:[u8] state_len:u32 state:[u8] }
+impl VMState {
+    pub fn to_bytes(&self) -> Result<Vec<u8>, SnapshotError> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.push(SNAPSHOT_VERSION);
+        bytes.extend(self.isa.to_be_bytes());
+        bytes.extend(self.program_counter.to_be_bytes());
+        bytes.extend(self.eof.to_be_bytes());
+        bytes.extend(self.stack_pointer.to_be_bytes());
+        bytes.push(self.flags.to_byte());
+        bytes.push(self.pending_interrupts);
+
+        let register_count = u8::try_from(self.registers.regs.len())
+            .map_err(|_| SnapshotError::TooManyRegisters(self.registers.regs.len()))?;
+        bytes.push(register_count);
+        bytes.extend(&self.registers.regs);
+        for memory in [&self.program_memory, &self.data_memory] {
+            bytes.extend(memory.size().to_be_bytes());
+            bytes.extend(&memory.mem);
+        }
+
+        bytes.push(self.devices.len() as u8);
+        for device in &self.devices {
+            bytes.extend(device.address.to_be_bytes());
+            bytes.push(device.name.len() as u8);
+            bytes.extend(device.name.as_bytes());
+            bytes.extend((device.state.len() as u32).to_be_bytes());
+            bytes.extend(&device.state);
+        }
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        let mut reader = Reader { bytes };
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = reader.u8()?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let isa = reader.u32()?;
+        let program_counter = reader.u32()?;
+        let eof = reader.u32()?;
+        let stack_pointer = reader.u32()?;
+        let flags = Flags::from_byte(reader.u8()?);
+        let pending_interrupts = reader.u8()?;
+
+        let register_count = reader.u8()? as usize;
+        let mut registers = Register::new(register_count as u32);
+        registers.regs = reader.take(register_count)?.to_vec();
+        let program_memory = reader.memory()?;
+        let data_memory = reader.memory()?;
+
+        let device_count = reader.u8()?;
+        let mut devices = Vec::new();
+        for _ in 0..device_count {
+            let address = reader.u32()?;
+            let name_len = reader.u8()? as usize;
+            let name = String::from_utf8(reader.take(name_len)?.to_vec())
+                .map_err(|_| SnapshotError::InvalidDeviceName)?;
+            let state_len = reader.u32()? as usize;
+            let state = reader.take(state_len)?.to_vec();
+            devices.push(DeviceState {
+                address,
+                name,
+                state,
+            });
+        }
+        if !reader.bytes.is_empty() {
+            return Err(SnapshotError::TrailingBytes(reader.bytes.len()));
+        }
+
+        Ok(Self {
+            isa,
+            program_counter,
+            eof,
+            registers,
+            flags,
+            program_memory,
+            data_memory,
+            stack_pointer,
+            pending_interrupts,
+            devices,
+        })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), VMError> {
+        fs::write(path, self.to_bytes()?)?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, VMError> {
+        Ok(Self::from_bytes(&fs::read(path)?)?)
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() < count {
+            return Err(SnapshotError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, SnapshotError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn memory(&mut self) -> Result<Memory<u8>, SnapshotError> {
+        let size = self.u32()?;
+        let mut memory = Memory::new(size);
+        memory.mem = self.take(size as usize)?.to_vec();
+        Ok(memory)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MyVM, bus::BusError};
+    use args::Args;
+    use container::Container;
+
+    fn vm_with_devices() -> MyVM {
+        let args = Args {
+            devices: true,
+            ..Args::default()
+        };
+        MyVM::new(&args).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut vm = vm_with_devices();
+        // MOVEI R1, 5; ADDI R1, R1, 1; HALT
+        let code = vec![0b00001001, 0b00000101, 0b00100101, 0b01000000, 0b01000000];
+        vm.load_container(Container::new(vm.opt_spec.identifier(), code, 34))
+            .unwrap();
+        vm.step().unwrap();
+        vm.store_data(crate::bus::TIMER_ADDRESS, 9).unwrap();
+        vm.raise_interrupt(3).unwrap();
+        vm.flags.carry = true;
+
+        let bytes = vm.get_state_struct().to_bytes().unwrap();
+        let mut restored = vm_with_devices();
+        restored
+            .restore(VMState::from_bytes(&bytes).unwrap())
+            .unwrap();
+
+        assert_eq!(restored.program_counter, 16);
+        assert_eq!(restored.eof, 34);
+        assert_eq!(restored.register.get(1).unwrap(), 5);
+        assert!(restored.flags.carry);
+        assert_eq!(restored.pending_interrupts(), 1 << 3);
+        assert_eq!(
+            restored.get_state_struct().devices,
+            vm.get_state_struct().devices
+        );
+        assert_eq!(
+            restored.load_data(crate::bus::TIMER_ADDRESS).unwrap().value,
+            9
+        );
+        // the restored program keeps running where the original stopped
+        restored.step().unwrap();
+        assert_eq!(restored.register.get(1).unwrap(), 6);
+        assert_eq!(
+            restored.get_state_struct().to_bytes().unwrap().len(),
+            bytes.len()
+        );
+    }
+
+    #[test]
+    fn test_invalid_snapshots() {
+        let bytes = vm_with_devices().get_state_struct().to_bytes().unwrap();
+
+        assert!(matches!(
+            VMState::from_bytes(b"ELF\0"),
+            Err(SnapshotError::BadMagic)
+        ));
+        let mut future = bytes.clone();
+        future[4] = SNAPSHOT_VERSION + 1;
+        assert!(matches!(
+            VMState::from_bytes(&future),
+            Err(SnapshotError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            VMState::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(SnapshotError::Truncated)
+        ));
+
+        // a snapshot with devices cannot be restored into a VM without them
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+        assert!(matches!(
+            vm.restore(VMState::from_bytes(&bytes).unwrap()),
+            Err(VMError::Bus(BusError::DeviceCount { saved: 4, .. }))
+        ));
+    }
+
+    #[test]
+    fn test_mismatched_snapshots() {
+        let mut vm = MyVM::new(&Args::default()).unwrap();
+
+        let mut state = vm.get_state_struct();
+        state.registers = Register::new(256);
+        assert!(matches!(
+            state.to_bytes(),
+            Err(SnapshotError::TooManyRegisters(256))
+        ));
+
+        let mut state = vm.get_state_struct();
+        state.isa ^= 1;
+        assert!(matches!(
+            vm.restore(state),
+            Err(VMError::Snapshot(SnapshotError::IsaMismatch { .. }))
+        ));
+
+        let mut state = vm.get_state_struct();
+        state.registers = Register::new(8);
+        assert!(matches!(
+            vm.restore(state),
+            Err(VMError::Snapshot(SnapshotError::Mismatch {
+                what: "registers",
+                saved: 8,
+                current: 4,
+            }))
+        ));
+
+        let mut state = vm.get_state_struct();
+        state.data_memory = Memory::new(16);
+        assert!(matches!(
+            vm.restore(state),
+            Err(VMError::Snapshot(SnapshotError::Mismatch {
+                what: "bytes of data memory",
+                saved: 16,
+                current: 256,
+            }))
+        ));
+    }
+}
@@ -0,0 +1,26 @@
+use std::collections::HashMap;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SymbolError {
+    #[error("Invalid symbol file entry at line {0}")]
+    InvalidLine(usize),
+}
+
+// the assembler's `--symbols` output: one `LABEL BIT-ADDRESS` pair per line, `;` starts a comment
+pub fn parse_symbols(contents: &str) -> Result<HashMap<String, u32>, SymbolError> {
+    let mut symbols = HashMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.split(';').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next().map(str::parse), parts.next()) {
+            (Some(label), Some(Ok(address)), None) => {
+                symbols.insert(label.to_string(), address);
+            }
+            _ => return Err(SymbolError::InvalidLine(i + 1)),
+        }
+    }
+    Ok(symbols)
+}
@@ -0,0 +1,405 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{ExecutionStep, Flags, MemoryAccess, Type, register::Register};
+
+const MAGIC: &[u8; 4] = b"VMTR";
+const TRACE_VERSION: u8 = 1;
+const NO_INTERRUPT: u8 = u8::MAX;
+// same bit order as `Flags::to_byte`
+pub(crate) const FLAG_NAMES: [&str; 5] = ["zero", "sign", "overflow", "carry", "interrupt_enable"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum TraceError {
+    #[error("{0}")]
+    IO(#[from] io::Error),
+    #[error("Invalid trace record: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Unknown trace format '{0}', expected jsonl or binary")]
+    UnknownFormat(String),
+    #[error("Unsupported binary trace version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Binary trace is truncated")]
+    Truncated,
+    #[error("Invalid text in binary trace")]
+    InvalidText,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    JsonLines,
+    Binary,
+}
+
+impl TraceFormat {
+    pub fn parse(format: &str) -> Result<Self, TraceError> {
+        match format {
+            "jsonl" => Ok(Self::JsonLines),
+            "binary" => Ok(Self::Binary),
+            _ => Err(TraceError::UnknownFormat(format.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegisterDelta {
+    pub register: u32,
+    pub old: u8,
+    pub new: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FlagDelta {
+    pub flag: String,
+    pub old: bool,
+    pub new: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TraceRecord {
+    pub pc: u32,
+    pub instruction: String,
+    pub registers: Vec<RegisterDelta>,
+    pub flags: Vec<FlagDelta>,
+    pub memory: Vec<MemoryAccess>,
+    pub sp: u32,
+    pub interrupt: Option<u8>,
+}
+
+impl TraceRecord {
+    // the deltas come from comparing the state around the step, not from what the handler reports
+    pub fn new(
+        step: &ExecutionStep,
+        old_registers: &Register<u8>,
+        old_flags: Flags,
+        new_registers: &Register<u8>,
+        new_flags: Flags,
+    ) -> Self {
+        let registers = old_registers
+            .regs
+            .iter()
+            .zip(&new_registers.regs)
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(register, (old, new))| RegisterDelta {
+                register: register as u32,
+                old: *old,
+                new: *new,
+            })
+            .collect();
+        let (old_flags, new_flags) = (old_flags.to_byte(), new_flags.to_byte());
+        Self {
+            pc: step.address,
+            instruction: step.instruction_str.clone(),
+            registers,
+            flags: flag_deltas(old_flags ^ new_flags, new_flags),
+            memory: step.memory_accesses.clone(),
+            sp: step.stack_pointer,
+            interrupt: step.interrupt,
+        }
+    }
+
+    // names of the fields that differ, in the order they are printed by trace-diff
+    pub fn differences(&self, other: &TraceRecord) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        if self.pc != other.pc {
+            fields.push("pc");
+        }
+        if self.instruction != other.instruction {
+            fields.push("instruction");
+        }
+        if self.registers != other.registers {
+            fields.push("registers");
+        }
+        if self.flags != other.flags {
+            fields.push("flags");
+        }
+        if self.memory != other.memory {
+            fields.push("memory");
+        }
+        if self.sp != other.sp {
+            fields.push("sp");
+        }
+        if self.interrupt != other.interrupt {
+            fields.push("interrupt");
+        }
+        fields
+    }
+}
+
+fn flag_deltas(changed: u8, new: u8) -> Vec<FlagDelta> {
+    FLAG_NAMES
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| changed & (1 << bit) != 0)
+        .map(|(bit, name)| FlagDelta {
+            flag: name.to_string(),
+            old: new & (1 << bit) == 0,
+            new: new & (1 << bit) != 0,
+        })
+        .collect()
+}
+
+pub struct TraceWriter {
+    format: TraceFormat,
+    writer: BufWriter<Box<dyn Write>>,
+}
+
+impl TraceWriter {
+    pub fn new(writer: impl Write + 'static, format: TraceFormat) -> Result<Self, TraceError> {
+        let mut writer = BufWriter::new(Box::new(writer) as Box<dyn Write>);
+        if format == TraceFormat::Binary {
+            writer.write_all(MAGIC)?;
+            writer.write_all(&[TRACE_VERSION])?;
+        }
+        Ok(Self { format, writer })
+    }
+
+    pub fn create(path: impl AsRef<Path>, format: TraceFormat) -> Result<Self, TraceError> {
+        Self::new(File::create(path)?, format)
+    }
+
+    pub fn write(&mut self, record: &TraceRecord) -> Result<(), TraceError> {
+        match self.format {
+            TraceFormat::JsonLines => {
+                serde_json::to_writer(&mut self.writer, record)?;
+                self.writer.write_all(b"\n")?;
+            }
+            TraceFormat::Binary => self.writer.write_all(&encode(record))?,
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), TraceError> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Binary record (version 1), integers are big endian:
+//   pc:u32 instruction_len:u8 instruction:[u8]
+//   register_count:u8 { register:u8 old:u8 new:u8 } changed_flags:u8 new_flags:u8
+//   access_count:u8 { type:u8 (1 read, 2 write) address:u32 value:u8 device_len:u8 device:[u8] }
+//   sp:u32 interrupt:u8 (255 for none)
+fn encode(record: &TraceRecord) -> Vec<u8> {
+    let mut bytes = record.pc.to_be_bytes().to_vec();
+    bytes.push(record.instruction.len() as u8);
+    bytes.extend(record.instruction.as_bytes());
+
+    bytes.push(record.registers.len() as u8);
+    for delta in &record.registers {
+        bytes.extend([delta.register as u8, delta.old, delta.new]);
+    }
+    let (mut changed, mut new) = (0u8, 0u8);
+    for delta in &record.flags {
+        if let Some(bit) = FLAG_NAMES.iter().position(|name| *name == delta.flag) {
+            changed |= 1 << bit;
+            new |= (delta.new as u8) << bit;
+        }
+    }
+    bytes.extend([changed, new]);
+
+    bytes.push(record.memory.len() as u8);
+    for access in &record.memory {
+        bytes.push(if access.type_ == Type::Read { 1 } else { 2 });
+        bytes.extend(access.address.to_be_bytes());
+        bytes.push(access.value);
+        let device = access.device.as_deref().unwrap_or("");
+        bytes.push(device.len() as u8);
+        bytes.extend(device.as_bytes());
+    }
+    bytes.extend(record.sp.to_be_bytes());
+    bytes.push(record.interrupt.unwrap_or(NO_INTERRUPT));
+    bytes
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], TraceError> {
+        if self.bytes.len() < count {
+            return Err(TraceError::Truncated);
+        }
+        let (taken, rest) = self.bytes.split_at(count);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, TraceError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, TraceError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn text(&mut self) -> Result<String, TraceError> {
+        let len = self.u8()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| TraceError::InvalidText)
+    }
+
+    fn record(&mut self) -> Result<TraceRecord, TraceError> {
+        let pc = self.u32()?;
+        let instruction = self.text()?;
+        let mut registers = Vec::new();
+        for _ in 0..self.u8()? {
+            let delta = self.take(3)?;
+            registers.push(RegisterDelta {
+                register: delta[0] as u32,
+                old: delta[1],
+                new: delta[2],
+            });
+        }
+        let changed = self.u8()?;
+        let flags = flag_deltas(changed, self.u8()?);
+        let mut memory = Vec::new();
+        for _ in 0..self.u8()? {
+            let kind = self.u8()?;
+            let address = self.u32()?;
+            let value = self.u8()?;
+            let device = self.text()?;
+            memory.push(MemoryAccess {
+                address,
+                value,
+                type_: if kind == 1 { Type::Read } else { Type::Write },
+                device: (!device.is_empty()).then_some(device),
+            });
+        }
+        let sp = self.u32()?;
+        let interrupt = Some(self.u8()?).filter(|line| *line != NO_INTERRUPT);
+        Ok(TraceRecord {
+            pc,
+            instruction,
+            registers,
+            flags,
+            memory,
+            sp,
+            interrupt,
+        })
+    }
+}
+
+// the format is detected from the binary header, anything else is read as JSON Lines
+pub fn read_trace(mut reader: impl Read) -> Result<Vec<TraceRecord>, TraceError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    if let Some(body) = bytes.strip_prefix(MAGIC) {
+        let mut decoder = Decoder { bytes: body };
+        let version = decoder.u8()?;
+        if version != TRACE_VERSION {
+            return Err(TraceError::UnsupportedVersion(version));
+        }
+        let mut records = Vec::new();
+        while !decoder.bytes.is_empty() {
+            records.push(decoder.record()?);
+        }
+        return Ok(records);
+    }
+    let text = String::from_utf8(bytes).map_err(|_| TraceError::InvalidText)?;
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| Ok(serde_json::from_str(line)?))
+        .collect()
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Divergence {
+    pub index: usize,
+    // None when that trace ended first
+    pub expected: Option<TraceRecord>,
+    pub actual: Option<TraceRecord>,
+}
+
+pub fn first_divergence(expected: &[TraceRecord], actual: &[TraceRecord]) -> Option<Divergence> {
+    let index = expected
+        .iter()
+        .zip(actual)
+        .position(|(expected, actual)| expected != actual)
+        .unwrap_or(expected.len().min(actual.len()));
+    if index == expected.len() && index == actual.len() {
+        return None;
+    }
+    Some(Divergence {
+        index,
+        expected: expected.get(index).cloned(),
+        actual: actual.get(index).cloned(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(pc: u32, instruction: &str) -> TraceRecord {
+        TraceRecord {
+            pc,
+            instruction: instruction.to_string(),
+            registers: vec![RegisterDelta {
+                register: 2,
+                old: 0,
+                new: 251,
+            }],
+            flags: flag_deltas(0b10010, 0b10000),
+            memory: vec![MemoryAccess {
+                address: 13,
+                value: 104,
+                type_: Type::Read,
+                device: Some(String::from("keyboard")),
+            }],
+            sp: 253,
+            interrupt: Some(1),
+        }
+    }
+
+    #[test]
+    fn test_formats_round_trip() {
+        let mut plain = record(16, "ADDI 2, 2, -5");
+        plain.memory = vec![];
+        plain.interrupt = None;
+        let records = vec![record(0, "MOVER 2, 13"), plain];
+
+        for format in [TraceFormat::JsonLines, TraceFormat::Binary] {
+            // the process id keeps concurrent test runs from sharing the file
+            let path =
+                std::env::temp_dir().join(format!("vm-trace-{}-{:?}", std::process::id(), format));
+            let mut writer = TraceWriter::create(&path, format).unwrap();
+            for record in &records {
+                writer.write(record).unwrap();
+            }
+            writer.flush().unwrap();
+            let read = read_trace(File::open(&path).unwrap()).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(read, records, "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn test_first_divergence() {
+        let reference = vec![record(0, "IN 2"), record(8, "OUT 2"), record(16, "HALT")];
+        assert_eq!(first_divergence(&reference, &reference), None);
+
+        let mut student = reference.clone();
+        student[1].sp = 255;
+        let divergence = first_divergence(&reference, &student).unwrap();
+        assert_eq!(divergence.index, 1);
+        assert_eq!(
+            divergence
+                .expected
+                .unwrap()
+                .differences(&divergence.actual.unwrap()),
+            vec!["sp"]
+        );
+
+        let divergence = first_divergence(&reference, &reference[..2]).unwrap();
+        assert_eq!(divergence.index, 2);
+        assert_eq!(divergence.actual, None);
+    }
+}
@@ -1,7 +1,11 @@
 use args::Args;
 use assembler::MyAssembler;
-use cpu::MyCPU;
+use isa::OptSpec;
 use serde::Serialize;
+use vm::{
+    MyVM,
+    io::{CallbackIo, Output},
+};
 use wasm_bindgen::prelude::*;
 use web_sys::console;
 
@@ -17,6 +21,7 @@ pub struct JsFlags {
     pub carry: bool,
     pub sign: bool,
     pub overflow: bool,
+    pub interrupt_enable: bool,
 }
 
 #[derive(Serialize)]
@@ -41,6 +46,7 @@ pub struct MemAccess {
     pub address: u32,
     pub value: u8,
     pub type_: Type,
+    pub device: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -62,22 +68,44 @@ pub struct JsExecutionStep {
     pub memory_access: Option<MemAccess>,
     pub is_halted: bool,
     pub stack_pointer: u32,
+    pub interrupt: Option<u8>,
 }
 
 #[wasm_bindgen]
 pub struct MyCpuController {
-    cpu: MyCPU,
+    cpu: MyVM,
     assembler: MyAssembler,
 }
 
+impl Default for MyCpuController {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[wasm_bindgen]
 impl MyCpuController {
     #[wasm_bindgen(constructor)]
     pub fn new() -> MyCpuController {
         console_error_panic_hook::set_once();
         let args = Args::default();
-        let cpu = MyCPU::new(&args).expect("Failed to create CPU");
-        let assembler = MyAssembler::new(&args).expect("Failed to create Assembler");
+        let mut cpu = MyVM::new(&args).expect("Failed to create CPU");
+        // there is no stdin in the browser, IN reads through a prompt and output goes to the console
+        cpu.set_io_device(CallbackIo::new(
+            |register| {
+                let message = format!("Enter value for register {register}:");
+                web_sys::window()
+                    .and_then(|window| window.prompt_with_message(&message).ok().flatten())
+                    .ok_or_else(|| {
+                        std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "no input")
+                    })
+            },
+            |output: Output| {
+                console::log_1(&JsValue::from_str(&output.to_string()));
+                Ok(())
+            },
+        ));
+        let assembler = MyAssembler::new().expect("Failed to create Assembler");
         MyCpuController { cpu, assembler }
     }
 
@@ -89,14 +117,14 @@ impl MyCpuController {
 
     #[wasm_bindgen(js_name = loadProgram)]
     pub fn load_program(&mut self, assembly_string: String) -> bool {
-        match self.assembler.assemble(assembly_string) {
-            Ok((binary, _)) => {
+        match self.assembler.assemble(&assembly_string) {
+            Ok((container, _)) => {
                 // produce a human readable binary string and log it to browser console
-                let byte_strs: Vec<String> = binary.iter().map(|byte| format!("{:08b}", byte)).collect();
+                let byte_strs: Vec<String> = container.code.iter().map(|byte| format!("{:08b}", byte)).collect();
                 let binary_str = byte_strs.join(" ");
                 console::log_1(&JsValue::from_str(&binary_str));
                 console::log_1(&JsValue::from_str("Program assembled"));
-                self.cpu.load_binary(binary).is_ok()
+                self.cpu.load_container(container).is_ok()
             },
             Err(e) => {
                 console::error_1(&JsValue::from_str(&format!("assemble error: {:?}", e)));
@@ -118,13 +146,16 @@ impl MyCpuController {
                     address: step_info.address,
                     changed_registers: step_info.changed_regs,
                     changed_flags: step_info.changed_flags,
-                    memory_access: step_info.memory_access.map(|ma| MemAccess {
+                    // the visualizer highlights a single cell, the instruction's own access comes last
+                    memory_access: step_info.memory_accesses.into_iter().last().map(|ma| MemAccess {
                         address: ma.address,
                         value: ma.value,
-                        type_: if ma.type_ == cpu::Type::Read { Type::Read } else { Type::Write },
+                        type_: if ma.type_ == vm::Type::Read { Type::Read } else { Type::Write },
+                        device: ma.device,
                     }),
                     is_halted: step_info.is_halted,
                     stack_pointer: step_info.stack_pointer,
+                    interrupt: step_info.interrupt,
                 };
 
                 match serde_wasm_bindgen::to_value(&js_step) {
@@ -159,6 +190,7 @@ impl MyCpuController {
                 carry: state.flags.carry,
                 sign: state.flags.sign,
                 overflow: state.flags.overflow,
+                interrupt_enable: state.flags.interrupt_enable,
             },
             program_memory: JsMemory { mem: state.program_memory.mem },
             data_memory: JsMemory { mem: state.data_memory.mem },
@@ -175,8 +207,42 @@ impl MyCpuController {
         }
     }
 
+    #[wasm_bindgen(js_name = stepBack)]
+    pub fn step_back(&mut self) -> bool {
+        self.cpu.step_back().unwrap_or(false)
+    }
+
+    // returns the number of steps undone
+    #[wasm_bindgen(js_name = reverseContinue)]
+    pub fn reverse_continue(&mut self, breakpoints: Vec<u32>) -> usize {
+        self.cpu
+            .reverse_continue(|address| breakpoints.contains(&address))
+            .unwrap_or(0)
+    }
+
+    #[wasm_bindgen(js_name = raiseInterrupt)]
+    pub fn raise_interrupt(&mut self, line: u8) -> bool {
+        self.cpu.raise_interrupt(line).is_ok()
+    }
+
     #[wasm_bindgen]
     pub fn reset(&mut self) {
         self.cpu.reset();
     }
+
+    // takes the text of an ISA description in TOML, programs have to be loaded again afterwards
+    #[wasm_bindgen(js_name = setIsa)]
+    pub fn set_isa(&mut self, description: String) -> bool {
+        match OptSpec::from_toml(&description) {
+            Ok(isa) => {
+                self.assembler.set_isa(isa.clone());
+                self.cpu.set_isa(isa);
+                true
+            },
+            Err(e) => {
+                console::error_1(&JsValue::from_str(&format!("ISA error: {}", e)));
+                false
+            },
+        }
+    }
 }